@@ -1,94 +1,251 @@
 use std::cell::RefCell;
 pub mod ansi_color;
+mod command_expansion;
+mod command_history;
+mod completion;
+mod dock;
 pub mod functions;
+mod keybindings;
 mod lua_execution;
+mod lua_panels;
+mod lua_repl;
+mod lua_scripts;
+mod mapper;
 mod miniwindow;
+mod paste;
+mod plugins;
+mod script_errors;
+mod script_limits;
+mod search;
+mod selection;
+mod session_log;
+mod session_replay;
+mod settings;
 mod settings_window;
 use settings_window::SettingsWindow;
 mod styles;
 pub mod telnet;
 use crate::app::lua_execution::LuaExecutor;
-use egui::{Color32, Layout, TextStyle};
-use miniwindow::WindowResizeTest;
+use command_history::{CommandHistory, HistorySearch};
+use completion::TabCompletion;
+use dock::{DockLayouts, Tab};
+use egui::text::{CCursor, CCursorRange};
+use egui::{Color32, Key, KeyboardShortcut, Layout, Modifiers};
+use functions::SendQueue;
+use keybindings::{Accelerators, BindingAction, KeyBindings};
+use lua_repl::LuaRepl;
+use mapper::{Mapper, SharedMap};
+use miniwindow::Miniwindows;
 use mlua::Lua;
-use std::collections::VecDeque;
-use std::time::Instant;
+use paste::{PacedLines, PasteDialog};
+use plugins::{PluginContext, PluginManager};
+use script_errors::ScriptErrors;
+use search::ScrollbackSearch;
+use selection::{OutputAction, OutputSelection};
+use session_log::{LogLine, SessionLog};
+use session_replay::SessionReplay;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use telnet::SplitView;
+type MenuAction = Box<dyn Fn(&mut TemplateApp, &egui::Context)>;
+
 #[derive(serde::Deserialize, serde::Serialize, Default)]
 #[serde(default)]
 pub struct TemplateApp {
     label: String,
     value: f32,
-    window_resize_test: WindowResizeTest,
     #[serde(skip)]
-    telnet_client: telnet::TelnetClient,
+    telnet_client: Arc<Mutex<telnet::TelnetClient>>,
     show_connection_prompt: RefCell<bool>,
     show_settings: RefCell<bool>,
-    #[serde(skip)]
     settings_window: SettingsWindow,
     ip_address: String,
     port: String,
+    character: String, // Filled into log file names
     command: String,
-    command_history: Vec<String>,
-    current_history_index: usize,
+    history: CommandHistory,
+    #[serde(skip_serializing)]
+    command_history: Vec<String>, // Saved before history was kept per world; read once
+    #[serde(skip)]
+    completion: TabCompletion,
+    plain_worlds: BTreeSet<String>, // Worlds whose commands are sent without expansion
+    key_bindings: KeyBindings,
+    paste_dialog: PasteDialog,
+    #[serde(skip)]
+    mapper: Mapper,
+    #[serde(skip)]
+    paced_lines: PacedLines, // Multi-line input and pastes waiting to be sent
     fps: f64,
     #[serde(skip)]
     last_frame_time: Option<Instant>,
     #[serde(skip)]
     frame_durations: VecDeque<f64>,
-    show_lua_execution_window: RefCell<bool>,
+    lua_repl: LuaRepl,
     #[serde(skip)]
     lua: Option<Lua>,
     #[serde(skip)]
     lua_executor: LuaExecutor,
-    lua_code: String,
+    #[serde(skip)]
+    plugin_manager: PluginManager,
+    #[serde(skip)]
+    script_errors: ScriptErrors,
+    #[serde(skip)]
+    miniwindows: Miniwindows,
+    dock: DockLayouts,
+    #[serde(skip)]
+    session_log: SessionLog,
+    #[serde(skip)]
+    sends: SendQueue, // Commands scripts and plugins sent
+    session_replay: SessionReplay,
+    search: ScrollbackSearch,
+    #[serde(skip)]
+    split_view: SplitView,
+    #[serde(skip)]
+    selection: OutputSelection,
+    #[serde(skip)]
+    link_to_open: Option<String>, // A clicked link waiting for confirmation
 }
 
 impl TemplateApp {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        // Set custom style and fonts
-        let style = styles::default_style();
-        cc.egui_ctx.set_style(style);
-        let font = styles::custom_font();
-        cc.egui_ctx.set_fonts(font);
-        let lua_executor = LuaExecutor::new().expect("Failed to initialize Lua executor");
-
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
-        }
-
-        Self {
-            label: "Hello World!".to_owned(),
-            value: 2.7,
-            window_resize_test: WindowResizeTest::new(),
-            telnet_client: telnet::TelnetClient::new(),
-            show_connection_prompt: RefCell::new(false),
-            show_settings: RefCell::new(false),
-            settings_window: SettingsWindow::default(),
-            ip_address: "127.0.0.1".to_owned(),
-            port: 23.to_string(),
-            command: String::new(),
-            command_history: Vec::new(),
-            current_history_index: 0,
-            fps: 0.0,
-            last_frame_time: None,
-            frame_durations: VecDeque::with_capacity(10),
-            show_lua_execution_window: RefCell::new(false),
-            lua: None,
-            lua_executor,
-            lua_code: String::new(),
+        // The style and fonts are set from the settings on the first frame. Ctrl +/- zooms the
+        // output pane rather than the whole UI.
+        cc.egui_ctx.options_mut(|o| o.zoom_with_keyboard = false);
+        let telnet_client = Arc::new(Mutex::new(telnet::TelnetClient::new()));
+        let script_errors = ScriptErrors::default();
+        let miniwindows = Miniwindows::default();
+        let session_log = SessionLog::default();
+        let accelerators = Accelerators::default();
+        let map = SharedMap::default();
+        let sends = SendQueue::default();
+        let lua_executor = LuaExecutor::new(
+            telnet_client.clone(),
+            script_errors.clone(),
+            miniwindows.clone(),
+            session_log.clone(),
+            accelerators.clone(),
+            map.clone(),
+            sends.clone(),
+        )
+        .expect("Failed to initialize Lua executor");
+
+        let mut app = if let Some(storage) = cc.storage {
+            let mut app: Self = eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+            app.telnet_client = telnet_client;
+            app.lua_executor = lua_executor;
+            app.script_errors = script_errors;
+            app.miniwindows = miniwindows;
+            app.session_log = session_log;
+            app.sends = sends;
+            app
+        } else {
+            Self {
+                label: "Hello World!".to_owned(),
+                value: 2.7,
+                telnet_client,
+                show_connection_prompt: RefCell::new(false),
+                show_settings: RefCell::new(false),
+                settings_window: SettingsWindow::default(),
+                ip_address: "127.0.0.1".to_owned(),
+                port: 23.to_string(),
+                character: String::new(),
+                command: String::new(),
+                history: CommandHistory::default(),
+                command_history: Vec::new(),
+                completion: TabCompletion::default(),
+                plain_worlds: BTreeSet::new(),
+                key_bindings: KeyBindings::default(),
+                paste_dialog: PasteDialog::default(),
+                mapper: Mapper::default(),
+                paced_lines: PacedLines::default(),
+                fps: 0.0,
+                last_frame_time: None,
+                frame_durations: VecDeque::with_capacity(10),
+                lua_repl: LuaRepl::default(),
+                lua: None,
+                lua_executor,
+                plugin_manager: PluginManager::default(),
+                script_errors,
+                miniwindows,
+                dock: DockLayouts::default(),
+                session_log,
+                sends,
+                session_replay: SessionReplay::default(),
+                search: ScrollbackSearch::default(),
+                split_view: SplitView::default(),
+                selection: OutputSelection::default(),
+                link_to_open: None,
+            }
+        };
+
+        let old_history = std::mem::take(&mut app.command_history);
+        app.history.import(
+            &app.world(),
+            old_history,
+            &app.settings_window.settings.input,
+        );
+        app.key_bindings.accelerators = accelerators;
+        app.mapper.shared = map;
+        app.mapper.switch_world(&app.world());
+        let plugin_context = PluginContext::new(
+            app.telnet_client.clone(),
+            app.script_errors.clone(),
+            app.miniwindows.clone(),
+            app.session_log.clone(),
+            app.key_bindings.accelerators.clone(),
+            app.mapper.shared.clone(),
+            app.sends.clone(),
+        );
+        app.plugin_manager
+            .load_directory(plugins::PLUGIN_FOLDER, plugin_context);
+        if app.settings_window.settings.network.connect_on_startup {
+            app.connect();
         }
+        app
     }
 }
 
 impl eframe::App for TemplateApp {
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.mapper.save();
         eframe::set_value(storage, eframe::APP_KEY, self);
     }
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if let Some(applied) = self.settings_window.apply(ctx) {
+            if let Some(recolor) = applied.recolor {
+                self.telnet_client.lock().unwrap().recolor(&recolor);
+            }
+            self.configure_log();
+        }
+        self.handle_key_bindings(ctx);
         self.update_menu(ctx);
         self.update_ui(ctx);
+        self.session_replay.poll(&self.telnet_client);
+        for line in self.paced_lines.due() {
+            self.send_line(&line);
+        }
+        for command in self.sends.take() {
+            self.send_command(&command, false);
+        }
+        for command in self.plugin_manager.take_executed() {
+            self.send_expanded(&command);
+        }
+        for command in self.mapper.walk_commands() {
+            self.send_command(&command, false);
+        }
+        for note in self.mapper.take_notes() {
+            self.telnet_client
+                .lock()
+                .unwrap()
+                .append_text(&format!("{}\n", note), Color32::GRAY);
+        }
         self.handle_telnet_input();
+        let connected = self.telnet_client.lock().unwrap().is_connected();
+        self.plugin_manager.tick(connected);
+        self.lua_executor.poll_scripts();
+        self.run_hotspot_callbacks();
         self.update_fps();
         ctx.request_repaint();
     }
@@ -96,7 +253,16 @@ impl eframe::App for TemplateApp {
 
 impl TemplateApp {
     fn update_menu(&mut self, ctx: &egui::Context) {
-        let menus: &[(&str, Vec<(&str, Box<dyn Fn(&mut Self, &egui::Context)>)>)] = &[
+        let mut view_menu: Vec<(&str, MenuAction)> = Tab::ALL
+            .iter()
+            .map(|&tab| {
+                let action: MenuAction = Box::new(move |s, _| s.dock.open_tab(tab));
+                (tab.title(), action)
+            })
+            .collect();
+        view_menu.push(("Reset layout", Box::new(|s, _| s.dock.reset())));
+
+        let menus: &[(&str, Vec<(&str, MenuAction)>)] = &[
             (
                 "File",
                 vec![
@@ -107,12 +273,60 @@ impl TemplateApp {
                             s.settings_window.open = true;
                         }),
                     ),
+                    (
+                        "Paste to MUD",
+                        Box::new(|s, _| {
+                            s.paste_dialog.open = true;
+                        }),
+                    ),
+                    (
+                        "Key bindings",
+                        Box::new(|s, _| {
+                            s.key_bindings.open = true;
+                        }),
+                    ),
+                    (
+                        "Record and replay",
+                        Box::new(|s, _| {
+                            s.session_replay.open = true;
+                        }),
+                    ),
                     (
                         "Quit",
                         Box::new(|_, ctx| ctx.send_viewport_cmd(egui::ViewportCommand::Close)),
                     ),
                 ],
             ),
+            (
+                "Scripts",
+                vec![
+                    (
+                        "Plugins",
+                        Box::new(|s, _| {
+                            s.plugin_manager.open = true;
+                        }),
+                    ),
+                    (
+                        "Lua REPL",
+                        Box::new(|s, _| {
+                            s.dock.open_tab(Tab::LuaRepl);
+                        }),
+                    ),
+                    (
+                        "Reload scripts",
+                        Box::new(|s, _| {
+                            s.lua_executor.reload_scripts();
+                        }),
+                    ),
+                    (
+                        "Script Errors",
+                        Box::new(|s, _| {
+                            s.dock.open_tab(Tab::ScriptErrors);
+                        }),
+                    ),
+                ],
+            ),
+            ("View", view_menu),
             (
                 "Connection",
                 vec![(
@@ -142,38 +356,184 @@ impl TemplateApp {
     }
 
     fn update_ui(&mut self, ctx: &egui::Context) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.with_layout(Layout::bottom_up(egui::Align::LEFT), |ui| {
-                ui.horizontal(|ui| {
-                    egui::warn_if_debug_build(ui);
-                    ui.label(format!("FPS: {:.0}", self.fps));
-                });
-                ui.set_max_width(ui.available_size().x);
-                ui.add_space(16.0);
-                ui.horizontal(|ui| {
-                    let input_box_width = ui.available_size().x - 100.0;
+        if ctx.input(|i| i.key_down(egui::Key::Escape) && i.key_pressed(egui::Key::I)) {
+            self.dock.toggle_tab(Tab::LuaRepl);
+        }
+        if ctx.input_mut(|i| i.consume_key(Modifiers::COMMAND, Key::F)) {
+            self.search.show();
+        }
 
-                    let response = ui.add_sized(
-                        [input_box_width, ui.text_style_height(&TextStyle::Body)],
-                        |ui: &mut egui::Ui| ui.text_edit_singleline(&mut self.command),
-                    );
+        let output_rect = dock::show(self, ctx).unwrap_or_else(|| ctx.available_rect());
+
+        self.handle_connection_prompt(ctx);
+        self.confirm_open_link(ctx);
+        self.settings_window.show(ctx);
+        self.session_replay.show(ctx, &self.telnet_client);
+        let world = self.world();
+        self.key_bindings.show(ctx, &world);
+        if let Some((lines, delay)) = self.paste_dialog.show(ctx) {
+            self.paced_lines.push(lines, delay);
+        }
+        self.miniwindows.show(ctx, output_rect);
+        self.plugin_manager.show(ctx);
+    }
+
+    /// Draws the world output, zooming it for the current world with Ctrl+scroll or Ctrl +/-.
+    fn output_ui(&mut self, ui: &mut egui::Ui) {
+        let world = self.world();
+        let fonts = &mut self.settings_window.settings.fonts;
+        let mut size = fonts.output_size(&world);
+        if ui.rect_contains_pointer(ui.max_rect()) {
+            size *= ui.input(|i| i.zoom_delta());
+        }
+        ui.input_mut(|i| {
+            if i.consume_key(Modifiers::COMMAND, Key::Plus)
+                || i.consume_key(Modifiers::COMMAND, Key::Equals)
+            {
+                size += 1.0;
+            }
+            if i.consume_key(Modifiers::COMMAND, Key::Minus) {
+                size -= 1.0;
+            }
+        });
+        if size != fonts.output_size(&world) {
+            fonts.set_output_size(&world, size);
+        }
+        if ui.input_mut(|i| i.consume_key(Modifiers::COMMAND, Key::Num0)) {
+            fonts.world_output_sizes.remove(&world);
+        }
 
-                    self.handle_lua_execution_window(ctx, ui);
+        let font_id = fonts.output_font_id(&world);
+        let appearance = &self.settings_window.settings.appearance;
+        let split_ratio = appearance
+            .split_scrollback
+            .then_some(appearance.split_ratio);
+        let telnet_client = self.telnet_client.lock().unwrap();
+        self.search.update(&telnet_client.received_data);
+        if self.search.open {
+            self.search.bar_ui(ui);
+        }
+        telnet_client.output_ui(
+            ui,
+            &font_id,
+            &mut self.search,
+            &mut self.split_view,
+            split_ratio,
+            &mut self.selection,
+        );
+        drop(telnet_client);
+        match self.selection.take_action() {
+            Some(OutputAction::Search(text)) => self.search.search_for(&text),
+            Some(OutputAction::SendToInput(text)) => self.command = text,
+            Some(OutputAction::OpenLink(target)) => self.link_to_open = Some(target),
+            None => {}
+        }
+    }
 
-                    self.handle_command_input(ui, response);
+    fn input_ui(&mut self, ui: &mut egui::Ui) {
+        ui.with_layout(Layout::bottom_up(egui::Align::LEFT), |ui| {
+            ui.horizontal(|ui| {
+                egui::warn_if_debug_build(ui);
+                ui.label(format!("FPS: {:.0}", self.fps));
+            });
+            ui.set_max_width(ui.available_size().x);
+            if self.paced_lines.pending() > 0 {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} lines waiting to be sent",
+                        self.paced_lines.pending()
+                    ));
+                    if ui.button("Cancel").clicked() {
+                        self.paced_lines.cancel();
+                    }
                 });
+            }
+            ui.horizontal(|ui| {
+                let input_box_width = ui.available_size().x - 130.0;
+
+                let font_id = self.settings_window.settings.fonts.input_font_id();
+                let password = self.telnet_client.lock().unwrap().echo_off();
+                let multiline = self.settings_window.settings.input.multiline && !password;
+                let row_height = ui.fonts(|f| f.row_height(&font_id));
+                let response = if multiline {
+                    ui.add_sized(
+                        [input_box_width, row_height * MULTILINE_ROWS as f32],
+                        egui::TextEdit::multiline(&mut self.command)
+                            .id(input_id())
+                            .lock_focus(true)
+                            .return_key(KeyboardShortcut::new(Modifiers::SHIFT, Key::Enter))
+                            .desired_rows(MULTILINE_ROWS)
+                            .font(font_id),
+                    )
+                } else {
+                    ui.add_sized(
+                        [input_box_width, row_height],
+                        egui::TextEdit::singleline(&mut self.command)
+                            .id(input_id())
+                            .lock_focus(true) // Keep the focus on Tab, for completion
+                            .font(font_id)
+                            .password(password),
+                    )
+                };
+
+                self.handle_command_input(ui, response, multiline);
+                ui.toggle_value(&mut self.settings_window.settings.input.multiline, "¶")
+                    .on_hover_text("Multi-line input (Shift+Enter for a new line)");
             });
+            if self.history.is_searching() {
+                let world = self.world();
+                match self.history.search_ui(ui, &world) {
+                    Some(HistorySearch::Accept(command)) => {
+                        self.command = command;
+                        self.focus_input(ui.ctx());
+                    }
+                    Some(HistorySearch::Cancel) => self.focus_input(ui.ctx()),
+                    None => {}
+                }
+            }
         });
-
-        self.handle_connection_prompt(ctx);
-        self.settings_window.show(ctx);
-        self.window_resize_test.show(ctx);
-        self.telnet_client.show(ctx);
     }
 
     fn handle_telnet_input(&mut self) {
-        if self.telnet_client.is_connected() {
-            if let Some(_data) = self.telnet_client.read_nonblocking() {}
+        let (lines, gmcp, connected) = {
+            let mut telnet_client = self.telnet_client.lock().unwrap();
+            let connected = telnet_client.is_connected();
+            if connected {
+                if let Some(_data) = telnet_client.read_nonblocking() {}
+            }
+            (
+                telnet_client.take_completed_lines(),
+                telnet_client.take_gmcp(),
+                connected,
+            )
+        };
+        let mapper_settings = &self.settings_window.settings.mapper;
+        for (package, data) in gmcp {
+            self.mapper.gmcp(&package, &data, mapper_settings);
+        }
+        for line in lines {
+            self.mapper
+                .line_received(&line.text, &self.settings_window.settings.mapper);
+            // Triggers run first so they can keep the line out of the log. Lines replayed
+            // offline aren't logged.
+            if !self.plugin_manager.line_received(&line.text) && connected {
+                self.session_log.write_received(&LogLine {
+                    text: &line.text,
+                    raw: &line.raw,
+                    spans: &line.spans,
+                });
+            }
+        }
+        self.mapper.autosave();
+    }
+
+    fn run_hotspot_callbacks(&mut self) {
+        for call in self.miniwindows.take_calls() {
+            if call.owner.is_empty() {
+                self.lua_executor.hotspot_callback(&call);
+            } else {
+                self.plugin_manager.hotspot_callback(&call);
+            }
         }
     }
 
@@ -199,71 +559,78 @@ impl TemplateApp {
         }
     }
 
-    fn handle_lua_execution_window(&mut self, ctx: &egui::Context, _ui: &mut egui::Ui) {
-        if ctx.input(|i| i.key_down(egui::Key::Escape) && i.key_pressed(egui::Key::I)) {
-            let mut open = self.show_lua_execution_window.borrow_mut();
-            *open = !*open;
+    fn handle_command_input(
+        &mut self,
+        ui: &mut egui::Ui,
+        response: egui::Response,
+        multiline: bool,
+    ) {
+        let world = self.world();
+        if response.changed() {
+            self.history.reset_navigation();
         }
-
-        let open = *self.show_lua_execution_window.borrow();
-        if open {
-            let app = self;
-            egui::Window::new("Lua Execution")
-                .open(&mut app.show_lua_execution_window.borrow_mut())
-                .show(ctx, |ui| {
-                    ui.label("Enter Lua code to execute:");
-                    ui.add_space(8.0);
-                    ui.code_editor(&mut app.lua_code);
-                    ui.add_space(8.0);
-                    if ui.button("Execute").clicked() {
-                        if let Err(err) = app.lua_executor.execute(&app.lua_code) {
-                            let error_message = format!("Error executing Lua code: {}\n", err);
-                            app.telnet_client.append_text(&error_message, Color32::RED);
-                        } else {
-                            let output = app.lua_executor.take_output();
-                            app.telnet_client.append_text(&output, Color32::KHAKI);
-                        }
-                    }
-                });
+        if response.has_focus() && ui.input_mut(|i| i.consume_key(Modifiers::CTRL, Key::R)) {
+            self.history.start_search();
         }
-    }
-
-    fn handle_command_input(&mut self, ui: &mut egui::Ui, response: egui::Response) {
-        if ui.input(|i| i.key_pressed(egui::Key::ArrowUp)) {
-            if self.current_history_index > 0 {
-                self.current_history_index -= 1;
-                self.command = self.command_history[self.current_history_index]
-                    .trim()
-                    .to_string();
-            }
-        } else if ui.input(|i| i.key_pressed(egui::Key::ArrowDown)) {
-            if self.current_history_index < self.command_history.len() - 1 {
-                self.current_history_index += 1;
-                self.command = self.command_history[self.current_history_index]
-                    .trim()
-                    .to_string();
-            } else {
-                self.command.clear();
-                self.current_history_index = self.command_history.len();
+        // Up and Down move between the lines of multi-line input.
+        let recalled = if !response.has_focus() || multiline {
+            None
+        } else if ui.input(|i| i.key_pressed(Key::ArrowUp)) {
+            self.history.previous(&world, &self.command)
+        } else if ui.input(|i| i.key_pressed(Key::ArrowDown)) {
+            self.history.next(&world, &self.command)
+        } else {
+            None
+        };
+        if let Some(command) = recalled {
+            self.command = command;
+            move_cursor_to_end(ui.ctx(), response.id, &self.command);
+        }
+        if response.has_focus() {
+            if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Tab)) {
+                self.complete_word(ui.ctx(), true);
+            } else if ui.input_mut(|i| i.consume_key(Modifiers::SHIFT, Key::Tab)) {
+                self.complete_word(ui.ctx(), false);
             }
         }
 
-        if ui.button("Send").clicked()
-            || response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))
-        {
+        let enter = if multiline {
+            response.has_focus() && ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Enter))
+        } else {
+            response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter))
+        };
+        if ui.button("Send").clicked() || enter {
             if !self.command.is_empty() {
                 //        println!("Sending command: {}", self.command); // Add debug log here
-                self.command.push('\n');
-                if let Err(e) = self.telnet_client.write(self.command.as_bytes()) {
-                    eprintln!("Failed to send command: {}", e);
+                let command = self.command.clone();
+                // With echo off the server is asking for a password: keep it out of the
+                // output, the log and the history.
+                let hidden = self.telnet_client.lock().unwrap().echo_off();
+                let input = &self.settings_window.settings.input;
+                if command.contains('\n') && input.send_lines_separately {
+                    let delay = Duration::from_millis(input.line_delay_ms);
+                    self.paced_lines
+                        .push(command.lines().map(str::to_string), delay);
+                } else if command.contains('\n') {
+                    for line in command.lines() {
+                        self.send_line(line);
+                    }
+                } else {
+                    self.execute(&command, hidden);
+                }
+                let input = &self.settings_window.settings.input;
+                let keep_command = input.keep_command;
+                if !hidden {
+                    for line in command.lines() {
+                        self.history.add(&world, line, input);
+                    }
                 }
-                if self.command_history.is_empty()
-                    || *self.command_history.last().unwrap() != self.command
-                {
-                    self.command_history.push(self.command.clone());
+                if keep_command && !hidden {
+                    self.command = command;
+                    select_all(ui.ctx(), response.id, &self.command);
+                } else {
+                    self.command.clear();
                 }
-                self.current_history_index = self.command_history.len();
-                self.command.clear();
             } else {
                 self.command.push(' ');
                 println!("Command is empty");
@@ -272,6 +639,100 @@ impl TemplateApp {
         }
     }
 
+    /// Echoes a line as typed and sends the commands it expands to.
+    fn execute(&mut self, line: &str, hidden: bool) {
+        if hidden {
+            self.send_command(line, true);
+        } else {
+            self.echo(line);
+            self.send_expanded(line);
+        }
+    }
+
+    /// Sends the commands a line expands to without echoing it, for text plugins execute.
+    fn send_expanded(&mut self, line: &str) {
+        let commands = if self.plain_worlds.contains(&self.world()) {
+            vec![line.to_string()]
+        } else {
+            command_expansion::expand(line, &self.settings_window.settings.input)
+        };
+        for command in commands {
+            self.send_command(&command, false);
+        }
+    }
+
+    /// Echoes and sends text as it is, without expanding command stacks or speedwalks, for
+    /// composed notes and pastes.
+    fn send_line(&mut self, line: &str) {
+        self.echo(line);
+        self.send_command(line, false);
+    }
+
+    fn echo(&self, line: &str) {
+        let input = &self.settings_window.settings.input;
+        if input.echo_commands {
+            self.telnet_client.lock().unwrap().append_input(
+                &format!("{}\n", line),
+                input.echo_color,
+                input.echo_style,
+            );
+        }
+    }
+
+    /// Runs the actions of the bound keys pressed this frame. Keys that type text count only
+    /// while the input line is empty and nothing else has the keyboard.
+    fn handle_key_bindings(&mut self, ctx: &egui::Context) {
+        let text_keys = self.command.is_empty()
+            && ctx.memory(|memory| memory.focused().is_none_or(|id| id == input_id()));
+        let actions = self
+            .key_bindings
+            .take_pressed(ctx, &self.world(), text_keys);
+        for (action, text) in actions {
+            match action {
+                BindingAction::Send => {
+                    for line in text.lines() {
+                        let hidden = self.telnet_client.lock().unwrap().echo_off();
+                        if !hidden {
+                            self.echo(line);
+                        }
+                        self.send_command(line, hidden);
+                    }
+                }
+                BindingAction::Execute => {
+                    for line in text.lines() {
+                        self.execute(line, false);
+                    }
+                }
+                BindingAction::Lua => self.lua_executor.run("Key binding", &text),
+            }
+        }
+    }
+
+    /// Sends one command through the plugin aliases and `OnPluginSend`, then to the world.
+    fn send_command(&mut self, command: &str, hidden: bool) {
+        if !hidden && self.mapper.command(command) {
+            return;
+        }
+        if self.plugin_manager.command_entered(command) || !self.plugin_manager.allow_send(command)
+        {
+            return;
+        }
+        let result = self
+            .telnet_client
+            .lock()
+            .unwrap()
+            .write(format!("{}\n", command).as_bytes());
+        if let Err(e) = result {
+            eprintln!("Failed to send command: {}", e);
+        }
+        self.mapper.command_sent(command);
+        if !hidden {
+            self.session_log
+                .write_input(command, self.settings_window.settings.input.echo_color);
+        }
+        self.plugin_manager.sent(command);
+    }
+
     fn handle_connection_prompt(&mut self, ctx: &egui::Context) {
         let open = *self.show_connection_prompt.borrow();
         let mut close_window = false;
@@ -291,17 +752,202 @@ impl TemplateApp {
                         ui.label("Port number:");
                         ui.text_edit_singleline(&mut self.port);
                     });
-                    if ui.button("Connect").clicked() {
-                        if let Err(e) = self.telnet_client.connect(&self.ip_address, &self.port) {
-                            eprintln!("Connection error: {}", e);
+                    ui.horizontal(|ui| {
+                        ui.label("Character:     ");
+                        ui.text_edit_singleline(&mut self.character);
+                    });
+                    // The game's own syntax may use the separator or prefixes.
+                    let world = format!("{}:{}", self.ip_address, self.port); // As typed so far
+                    let mut expand = !self.plain_worlds.contains(&world);
+                    if ui
+                        .checkbox(&mut expand, "Expand command stacks, repeats and speedwalks")
+                        .changed()
+                    {
+                        if expand {
+                            self.plain_worlds.remove(&world);
+                        } else {
+                            self.plain_worlds.insert(world);
                         }
+                    }
+                    if ui.button("Connect").clicked() {
                         close_window = true;
                     }
                 });
 
             if close_window {
                 *self.show_connection_prompt.borrow_mut() = false;
+                self.connect();
+            }
+        }
+    }
+
+    /// Asks before opening a link from the output, showing exactly where it goes. Only web and
+    /// mail links can be opened, so a server can't make a click launch anything else.
+    fn confirm_open_link(&mut self, ctx: &egui::Context) {
+        let Some(target) = self.link_to_open.clone() else {
+            return;
+        };
+        let openable = ["http://", "https://", "mailto:"]
+            .iter()
+            .any(|scheme| target.to_ascii_lowercase().starts_with(scheme));
+        let mut open = true;
+        let mut close = false;
+        egui::Window::new("Open link?")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label("The server sent a link to:");
+                ui.monospace(&target);
+                if !openable {
+                    ui.colored_label(
+                        Color32::RED,
+                        "Only http, https and mailto links can be opened.",
+                    );
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(openable, egui::Button::new("Open"))
+                        .clicked()
+                    {
+                        ctx.open_url(egui::OpenUrl::new_tab(&target));
+                        close = true;
+                    }
+                    if ui.button("Copy").clicked() {
+                        ctx.copy_text(target.clone());
+                        close = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if !open || close {
+            self.link_to_open = None;
+        }
+    }
+
+    fn connect(&mut self) {
+        let timeout =
+            Duration::from_secs(self.settings_window.settings.network.connect_timeout_secs);
+        let result =
+            self.telnet_client
+                .lock()
+                .unwrap()
+                .connect(&self.ip_address, &self.port, timeout);
+        match result {
+            Ok(()) => {
+                self.dock.switch_world(&self.world());
+                self.mapper.switch_world(&self.world());
+                self.configure_log();
+                self.session_log.on_connect();
+                self.plugin_manager.on_connect();
             }
+            Err(e) => eprintln!("Connection error: {}", e),
+        }
+    }
+
+    /// Completes the word before the cursor from recent output, the configured word list and
+    /// the Lua `OnTabComplete` and `OnPluginTabComplete` callbacks, in that order.
+    fn complete_word(&mut self, ctx: &egui::Context, forward: bool) {
+        let Some(state) = egui::TextEdit::load_state(ctx, input_id()) else {
+            return;
+        };
+        let Some(range) = state.cursor.char_range() else {
+            return;
+        };
+        let cursor = self
+            .command
+            .char_indices()
+            .nth(range.primary.index)
+            .map_or(self.command.len(), |(offset, _)| offset);
+        let input = &self.settings_window.settings.input;
+        let completed = self
+            .completion
+            .complete(&self.command, cursor, forward, |word| {
+                let mut candidates = Vec::new();
+                let scrollback = completion::scrollback_words(
+                    &self.telnet_client.lock().unwrap().received_data,
+                    input.completion_lines,
+                );
+                completion::add_candidates(
+                    &mut candidates,
+                    word,
+                    scrollback.iter().map(String::as_str),
+                );
+                completion::add_candidates(
+                    &mut candidates,
+                    word,
+                    input.completion_words.split_whitespace(),
+                );
+                let scripted = self.lua_executor.tab_completions(word);
+                completion::add_candidates(
+                    &mut candidates,
+                    word,
+                    scripted.iter().map(String::as_str),
+                );
+                let plugins = self.plugin_manager.tab_completions(word);
+                completion::add_candidates(
+                    &mut candidates,
+                    word,
+                    plugins.iter().map(String::as_str),
+                );
+                candidates
+            });
+        if let Some((line, cursor)) = completed {
+            let cursor = line[..cursor].chars().count();
+            self.command = line;
+            move_cursor(ctx, input_id(), cursor);
         }
     }
+
+    /// Gives the command line the focus back, with the cursor after the text.
+    fn focus_input(&self, ctx: &egui::Context) {
+        ctx.memory_mut(|memory| memory.request_focus(input_id()));
+        move_cursor_to_end(ctx, input_id(), &self.command);
+    }
+
+    /// Passes the logging settings and the world and character to the session log, when either
+    /// changes.
+    fn configure_log(&self) {
+        self.session_log.configure(
+            &self.settings_window.settings.logging,
+            &self.world(),
+            &self.character,
+        );
+    }
+
+    /// The `host:port` the connection settings point at, used to key per-world state.
+    fn world(&self) -> String {
+        format!("{}:{}", self.ip_address, self.port)
+    }
+}
+
+const MULTILINE_ROWS: usize = 4;
+
+fn input_id() -> egui::Id {
+    egui::Id::new("command_input")
+}
+
+fn move_cursor(ctx: &egui::Context, id: egui::Id, index: usize) {
+    if let Some(mut state) = egui::TextEdit::load_state(ctx, id) {
+        let cursor = CCursor::new(index);
+        state.cursor.set_char_range(Some(CCursorRange::one(cursor)));
+        state.store(ctx, id);
+    }
+}
+
+fn move_cursor_to_end(ctx: &egui::Context, id: egui::Id, text: &str) {
+    move_cursor(ctx, id, text.chars().count());
+}
+
+fn select_all(ctx: &egui::Context, id: egui::Id, text: &str) {
+    if let Some(mut state) = egui::TextEdit::load_state(ctx, id) {
+        let end = CCursor::new(text.chars().count());
+        state
+            .cursor
+            .set_char_range(Some(CCursorRange::two(CCursor::new(0), end)));
+        state.store(ctx, id);
+    }
 }
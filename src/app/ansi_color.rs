@@ -1,50 +1,52 @@
 use egui::Color32;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
+use std::fs;
+use std::sync::RwLock;
 
 pub fn generate_xterm_color_map() -> HashMap<&'static str, Color32> {
     let mut color_map = HashMap::new();
 
     // Standard colors
-    color_map.insert("0;30", Color32::from_rgb(0, 0, 0));       // Black
-    color_map.insert("0;31", Color32::from_rgb(128, 0, 0));     // Dark Red
-    color_map.insert("0;32", Color32::from_rgb(0, 128, 0));     // Dark Green
-    color_map.insert("0;33", Color32::from_rgb(128, 128, 0));   // Dark Yellow
-    color_map.insert("0;34", Color32::from_rgb(0, 0, 128));     // Dark Blue
-    color_map.insert("0;35", Color32::from_rgb(128, 0, 128));   // Dark Magenta
-    color_map.insert("0;36", Color32::from_rgb(0, 128, 128));   // Dark Cyan
+    color_map.insert("0;30", Color32::from_rgb(0, 0, 0)); // Black
+    color_map.insert("0;31", Color32::from_rgb(128, 0, 0)); // Dark Red
+    color_map.insert("0;32", Color32::from_rgb(0, 128, 0)); // Dark Green
+    color_map.insert("0;33", Color32::from_rgb(128, 128, 0)); // Dark Yellow
+    color_map.insert("0;34", Color32::from_rgb(0, 0, 128)); // Dark Blue
+    color_map.insert("0;35", Color32::from_rgb(128, 0, 128)); // Dark Magenta
+    color_map.insert("0;36", Color32::from_rgb(0, 128, 128)); // Dark Cyan
     color_map.insert("0;37", Color32::from_rgb(192, 192, 192)); // Light Gray
     color_map.insert("1;30", Color32::from_rgb(128, 128, 128)); // Dark Gray
-    color_map.insert("1;31", Color32::from_rgb(255, 0, 0));     // Red
-    color_map.insert("1;32", Color32::from_rgb(0, 255, 0));     // Green
-    color_map.insert("1;33", Color32::from_rgb(255, 255, 0));   // Yellow
-    color_map.insert("1;34", Color32::from_rgb(0, 0, 255));     // Blue
-    color_map.insert("1;35", Color32::from_rgb(255, 0, 255));   // Magenta
-    color_map.insert("1;36", Color32::from_rgb(0, 255, 255));   // Cyan
+    color_map.insert("1;31", Color32::from_rgb(255, 0, 0)); // Red
+    color_map.insert("1;32", Color32::from_rgb(0, 255, 0)); // Green
+    color_map.insert("1;33", Color32::from_rgb(255, 255, 0)); // Yellow
+    color_map.insert("1;34", Color32::from_rgb(0, 0, 255)); // Blue
+    color_map.insert("1;35", Color32::from_rgb(255, 0, 255)); // Magenta
+    color_map.insert("1;36", Color32::from_rgb(0, 255, 255)); // Cyan
     color_map.insert("1;37", Color32::from_rgb(255, 255, 255)); // White
 
     // Generate xterm colors
     for i in 0..256 {
-        let r = if i >= 16 && i < 232 {
+        let r = if (16..232).contains(&i) {
             (((i - 16) / 36) * 51) as u8 // 0, 51, 102, ...
         } else if i >= 232 {
-            (((i - 232) * 255) / 23) as u8    // Linear grayscale
+            (((i - 232) * 255) / 23) as u8 // Linear grayscale
         } else {
             0
         };
 
-        let g = if i >= 16 && i < 232 {
+        let g = if (16..232).contains(&i) {
             (((i - 16) % 36 / 6) * 51) as u8
-        } else if i >= 232 { 
-            (((i - 232) * 255) / 23) as u8    
+        } else if i >= 232 {
+            (((i - 232) * 255) / 23) as u8
         } else {
             0
         };
 
-        let b = if i >= 16 && i < 232 {
+        let b = if (16..232).contains(&i) {
             (((i - 16) % 6) * 51) as u8
-        } else if i >= 232 { 
-            (((i - 232) * 255) / 23) as u8     
+        } else if i >= 232 {
+            (((i - 232) * 255) / 23) as u8
         } else {
             0
         };
@@ -60,3 +62,203 @@ pub fn generate_xterm_color_map() -> HashMap<&'static str, Color32> {
 lazy_static! {
     pub static ref COLOR_MAP: HashMap<&'static str, Color32> = generate_xterm_color_map();
 }
+
+lazy_static! {
+    static ref PALETTE: RwLock<Palette> = RwLock::new(Palette::default());
+}
+
+/// The colors used for ANSI text: default foreground and background, the 16 base colors
+/// (`0;30`-`0;37` then `1;30`-`1;37`) and the 256 xterm colors (`38;5;n`).
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Palette {
+    pub foreground: Color32,
+    pub background: Color32,
+    pub base: [Color32; 16],
+    pub remap_cube: bool, // Use `cube` for 38;5;16-255 instead of the standard xterm colors
+    pub cube: Vec<Color32>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::from_base(PalettePreset::MushClient.base())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PalettePreset {
+    MushClient,
+    Xterm,
+    Solarized,
+    HighContrast,
+}
+
+impl PalettePreset {
+    pub const ALL: [PalettePreset; 4] = [
+        PalettePreset::MushClient,
+        PalettePreset::Xterm,
+        PalettePreset::Solarized,
+        PalettePreset::HighContrast,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PalettePreset::MushClient => "MUSHclient default",
+            PalettePreset::Xterm => "xterm",
+            PalettePreset::Solarized => "Solarized",
+            PalettePreset::HighContrast => "High contrast",
+        }
+    }
+
+    fn base(self) -> [u32; 16] {
+        match self {
+            PalettePreset::MushClient => [
+                0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xc0c0c0,
+                0x808080, 0xff0000, 0x00ff00, 0xffff00, 0x0000ff, 0xff00ff, 0x00ffff, 0xffffff,
+            ],
+            PalettePreset::Xterm => [
+                0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5,
+                0x7f7f7f, 0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+            ],
+            PalettePreset::Solarized => [
+                0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5,
+                0x002b36, 0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+            ],
+            PalettePreset::HighContrast => [
+                0x000000, 0xff5555, 0x55ff55, 0xffff55, 0x6496ff, 0xff55ff, 0x55ffff, 0xe6e6e6,
+                0xa0a0a0, 0xff7878, 0x78ff78, 0xffff78, 0x8cb4ff, 0xff8cff, 0x8cffff, 0xffffff,
+            ],
+        }
+    }
+
+    pub fn palette(self) -> Palette {
+        let mut palette = Palette::from_base(self.base());
+        if self == PalettePreset::Solarized {
+            palette.foreground = rgb(0x839496);
+            palette.background = rgb(0x002b36);
+        }
+        palette
+    }
+}
+
+fn rgb(colour: u32) -> Color32 {
+    Color32::from_rgb((colour >> 16) as u8, (colour >> 8) as u8, colour as u8)
+}
+
+/// The standard xterm color for `38;5;index`, for indexes past the 16 base colors.
+fn xterm_color(index: usize) -> Color32 {
+    if index >= 232 {
+        Color32::from_gray((8 + (index - 232) * 10) as u8)
+    } else {
+        let level = |value: usize| {
+            if value == 0 {
+                0
+            } else {
+                (55 + value * 40) as u8
+            }
+        };
+        let index = index.saturating_sub(16);
+        Color32::from_rgb(level(index / 36), level(index % 36 / 6), level(index % 6))
+    }
+}
+
+impl Palette {
+    fn from_base(base: [u32; 16]) -> Self {
+        Self {
+            foreground: Color32::WHITE,
+            background: Color32::BLACK,
+            base: base.map(rgb),
+            remap_cube: false,
+            cube: (0..256).map(xterm_color).collect(),
+        }
+    }
+
+    /// The color for an SGR code such as `1;31` or `38;5;208`.
+    pub fn color(&self, code: &str) -> Option<Color32> {
+        match code {
+            "" | "0" | "39" => return Some(self.foreground),
+            _ => {}
+        }
+        if let Some(index) = code.strip_prefix("38;5;") {
+            let index: usize = index.parse().ok().filter(|index| *index < 256)?;
+            return Some(if index < 16 {
+                self.base[index]
+            } else if self.remap_cube {
+                self.cube.get(index).copied().unwrap_or(xterm_color(index))
+            } else {
+                xterm_color(index)
+            });
+        }
+        let (bright, colour) = match code.split_once(';') {
+            Some(("0", colour)) => (false, colour),
+            Some(("1", colour)) => (true, colour),
+            _ => (false, code),
+        };
+        let colour: usize = colour.parse().ok().filter(|c| (30..38).contains(c))?;
+        Some(self.base[colour - 30 + if bright { 8 } else { 0 }])
+    }
+
+    /// An SGR code that gives `color` in this palette, falling back to a 24-bit color code.
+    pub fn code(&self, color: Color32) -> String {
+        if color == self.foreground {
+            return "0".to_owned();
+        }
+        match self.base.iter().position(|base| *base == color) {
+            Some(index) if index < 8 => format!("0;{}", 30 + index),
+            Some(index) => format!("1;{}", 30 + index - 8),
+            None => format!("38;2;{};{};{}", color.r(), color.g(), color.b()),
+        }
+    }
+
+    /// Every color this palette can produce, default foreground first.
+    fn entries(&self) -> Vec<Color32> {
+        let mut entries = vec![self.foreground];
+        entries.extend(self.base);
+        entries.extend((16..256).map(|index| self.color(&format!("38;5;{}", index)).unwrap()));
+        entries
+    }
+
+    /// Maps each color of `self` to the matching color of `new`, for recoloring text that was
+    /// received before the palette changed. Where two entries share a color the first wins.
+    pub fn recolor_map(&self, new: &Palette) -> HashMap<Color32, Color32> {
+        let mut map = HashMap::new();
+        for (old, new) in self.entries().into_iter().zip(new.entries()) {
+            map.entry(old).or_insert(new);
+        }
+        map
+    }
+
+    pub fn export(&self, path: &str) -> Result<(), String> {
+        let text = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path, e))
+    }
+
+    pub fn import(path: &str) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid palette file: {}", e))
+    }
+}
+
+/// The current palette's color for an SGR code.
+pub fn palette_color(code: &str) -> Option<Color32> {
+    PALETTE.read().unwrap().color(code)
+}
+
+/// The current palette's SGR code for a color, for rebuilding ANSI text.
+pub fn palette_code(color: Color32) -> String {
+    PALETTE.read().unwrap().code(color)
+}
+
+pub fn default_foreground() -> Color32 {
+    PALETTE.read().unwrap().foreground
+}
+
+pub fn default_background() -> Color32 {
+    PALETTE.read().unwrap().background
+}
+
+/// Replaces the palette used for new text and returns the previous one.
+pub fn set_palette(palette: Palette) -> Palette {
+    std::mem::replace(&mut *PALETTE.write().unwrap(), palette)
+}
@@ -0,0 +1,167 @@
+use crate::app::settings::InputSettings;
+
+const MAX_REPEAT: usize = 100; // Stops a typo like `#5000 kill rat` flooding the server
+const ESCAPE: char = '\\';
+
+/// Turns one typed line into the commands to send: the line is split at the command separator
+/// (a backslash before it keeps it literal), `#5 kill rat` repeats a command and `.3n2e` walks.
+/// A prefix or separator left empty in the settings switches that expansion off.
+pub fn expand(line: &str, settings: &InputSettings) -> Vec<String> {
+    let mut commands = Vec::new();
+    for part in split_stack(line, &settings.command_separator) {
+        let (count, command) = repeat(&part, &settings.repeat_prefix);
+        let expanded =
+            speedwalk(command, &settings.speedwalk_prefix).unwrap_or_else(|| vec![command.into()]);
+        for _ in 0..count {
+            commands.extend(expanded.iter().cloned());
+        }
+    }
+    commands
+}
+
+/// Splits at unescaped separators, dropping the escapes. A trailing separator doesn't add an
+/// empty command.
+fn split_stack(line: &str, separator: &str) -> Vec<String> {
+    if separator.is_empty() {
+        return vec![line.to_string()];
+    }
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        if let Some(after) = rest
+            .strip_prefix(ESCAPE)
+            .and_then(|after| after.strip_prefix(separator))
+        {
+            current.push_str(separator);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix(separator) {
+            parts.push(std::mem::take(&mut current));
+            rest = after;
+        } else {
+            let c = rest.chars().next().unwrap_or_default();
+            current.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Reads a `#<count> ` prefix, returning how often to send the rest.
+fn repeat<'a>(command: &'a str, prefix: &str) -> (usize, &'a str) {
+    if prefix.is_empty() {
+        return (1, command);
+    }
+    let repeated = command.trim_start().strip_prefix(prefix).and_then(|rest| {
+        let (count, rest) = rest.split_once(char::is_whitespace)?;
+        let count: usize = count.parse().ok()?;
+        Some((count.min(MAX_REPEAT), rest.trim_start()))
+    });
+    repeated.unwrap_or((1, command))
+}
+
+/// Expands `.3n2e(enter portal)u` into single moves. Letters are sent as typed, so `3n` is three
+/// `n` commands; anything in parentheses is one command. Returns `None` for anything that isn't a
+/// well-formed speedwalk, which is then sent unchanged.
+fn speedwalk(command: &str, prefix: &str) -> Option<Vec<String>> {
+    if prefix.is_empty() {
+        return None;
+    }
+    let mut rest = command.trim().strip_prefix(prefix)?;
+    let mut moves = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let count = match &rest[..digits] {
+            "" => 1,
+            count => count.parse::<usize>().ok()?.min(MAX_REPEAT),
+        };
+        rest = &rest[digits..];
+        let step = if let Some(inner) = rest.strip_prefix('(') {
+            let end = inner.find(')')?;
+            rest = &inner[end + 1..];
+            inner[..end].trim().to_string()
+        } else {
+            let c = rest.chars().next()?;
+            if !"nsewud".contains(c.to_ascii_lowercase()) {
+                return None;
+            }
+            rest = &rest[c.len_utf8()..];
+            c.to_string()
+        };
+        moves.extend(std::iter::repeat_n(step, count));
+    }
+    (!moves.is_empty()).then_some(moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_default(line: &str) -> Vec<String> {
+        expand(line, &InputSettings::default())
+    }
+
+    #[test]
+    fn splits_at_unescaped_separators() {
+        assert_eq!(split_stack("n;e;look", ";"), ["n", "e", "look"]);
+        assert_eq!(split_stack(r"say hi\;bye", ";"), ["say hi;bye"]);
+        assert_eq!(split_stack(r"say a\b", ";"), [r"say a\b"]);
+        assert_eq!(split_stack("a;;b", ";"), ["a", "", "b"]);
+        assert_eq!(split_stack("n;e", ""), ["n;e"]);
+    }
+
+    #[test]
+    fn trailing_separator_adds_no_command() {
+        assert_eq!(expand_default("n;"), ["n"]);
+        assert_eq!(expand_default(";"), [""]);
+        assert_eq!(expand_default(""), [""]);
+    }
+
+    #[test]
+    fn repeats_commands() {
+        assert_eq!(repeat("#3 kill rat", "#"), (3, "kill rat"));
+        assert_eq!(expand_default("#2 bow;smile"), ["bow", "bow", "smile"]);
+        assert_eq!(repeat("#x kill rat", "#"), (1, "#x kill rat"));
+        assert_eq!(repeat("#3", "#"), (1, "#3"));
+        assert_eq!(repeat("#3 kill rat", ""), (1, "#3 kill rat"));
+    }
+
+    #[test]
+    fn repeat_of_zero_sends_nothing() {
+        assert!(expand_default("#0 kill rat").is_empty());
+    }
+
+    #[test]
+    fn repeats_are_clamped() {
+        assert_eq!(repeat("#5000 kill rat", "#"), (MAX_REPEAT, "kill rat"));
+        assert_eq!(expand_default(".500n").len(), MAX_REPEAT);
+    }
+
+    #[test]
+    fn expands_speedwalks() {
+        assert_eq!(
+            speedwalk(".3n2e(enter portal)u", "."),
+            Some(
+                ["n", "n", "n", "e", "e", "enter portal", "u"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+        assert_eq!(expand_default("#2 .2s"), ["s", "s", "s", "s"]);
+    }
+
+    #[test]
+    fn malformed_speedwalks_are_sent_unchanged() {
+        assert_eq!(speedwalk(".3(", "."), None);
+        assert_eq!(speedwalk(".3x", "."), None);
+        assert_eq!(speedwalk(".", "."), None);
+        assert_eq!(speedwalk("look", "."), None);
+        assert_eq!(expand_default(".3("), [".3("]);
+        assert_eq!(expand_default("..."), ["..."]);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::app::settings::InputSettings;
+use egui::{Key, Modifiers, TextEdit, Ui};
+use std::collections::{BTreeMap, VecDeque};
+
+/// What the reverse search asks of the input line.
+pub enum HistorySearch {
+    Accept(String),
+    Cancel,
+}
+
+/// Ctrl+R state: the typed query and the history entry it currently matches.
+#[derive(Default)]
+struct ReverseSearch {
+    query: String,
+    found: Option<usize>,
+    focus: bool,
+}
+
+/// Typed commands, kept per world and saved with the app.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+pub struct CommandHistory {
+    worlds: BTreeMap<String, VecDeque<String>>,
+    #[serde(skip)]
+    position: Option<usize>, // Entry shown while browsing with Up/Down
+    #[serde(skip)]
+    prefix: String, // What was typed before browsing; only entries starting with it are shown
+    #[serde(skip)]
+    search: Option<ReverseSearch>,
+}
+
+impl CommandHistory {
+    pub fn add(&mut self, world: &str, command: &str, settings: &InputSettings) {
+        self.reset_navigation();
+        if command.trim().is_empty() {
+            return;
+        }
+        let entries = self.worlds.entry(world.to_string()).or_default();
+        if settings.history_dedupe {
+            entries.retain(|entry| entry != command);
+        } else if entries.back().is_some_and(|last| last == command) {
+            return;
+        }
+        entries.push_back(command.to_string());
+        while entries.len() > settings.history_size {
+            entries.pop_front();
+        }
+    }
+
+    /// Adds commands from the single history kept before it was split by world.
+    pub fn import(&mut self, world: &str, commands: Vec<String>, settings: &InputSettings) {
+        for command in commands {
+            self.add(world, &command, settings);
+        }
+    }
+
+    /// Forgets where Up/Down browsing had got to, after the input line was edited.
+    pub fn reset_navigation(&mut self) {
+        self.position = None;
+    }
+
+    /// The next older entry starting with what was typed, for the Up key.
+    pub fn previous(&mut self, world: &str, input: &str) -> Option<String> {
+        if self.position.is_none() {
+            self.prefix = input.to_string();
+        }
+        let entries = self.worlds.get(world)?;
+        let end = self.position.unwrap_or(entries.len());
+        let index = (0..end)
+            .rev()
+            .find(|&index| self.matches_prefix(&entries[index], input))?;
+        self.position = Some(index);
+        Some(entries[index].clone())
+    }
+
+    /// The next newer entry starting with what was typed, for the Down key. Past the newest it
+    /// gives back what was typed.
+    pub fn next(&mut self, world: &str, input: &str) -> Option<String> {
+        let position = self.position?;
+        let entries = self.worlds.get(world)?;
+        match (position + 1..entries.len())
+            .find(|&index| self.matches_prefix(&entries[index], input))
+        {
+            Some(index) => {
+                self.position = Some(index);
+                Some(entries[index].clone())
+            }
+            None => {
+                self.position = None;
+                Some(self.prefix.clone())
+            }
+        }
+    }
+
+    fn matches_prefix(&self, entry: &str, shown: &str) -> bool {
+        entry.starts_with(&self.prefix) && entry != shown
+    }
+
+    pub fn start_search(&mut self) {
+        self.search = Some(ReverseSearch {
+            focus: true,
+            ..Default::default()
+        });
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// The `(reverse-i-search)` line: typing narrows the match, Ctrl+R finds an older one,
+    /// Enter takes it and Escape gives up.
+    pub fn search_ui(&mut self, ui: &mut Ui, world: &str) -> Option<HistorySearch> {
+        let empty = VecDeque::new();
+        let entries = self.worlds.get(world).unwrap_or(&empty);
+        let search = self.search.as_mut()?;
+        let find = |query: &str, before: usize| {
+            (0..before)
+                .rev()
+                .find(|&index| entries[index].contains(query))
+        };
+
+        let mut result = None;
+        ui.horizontal(|ui| {
+            ui.label("(reverse-i-search)");
+            let response = ui.add(TextEdit::singleline(&mut search.query).desired_width(150.0));
+            if std::mem::take(&mut search.focus) {
+                response.request_focus();
+            }
+            if response.changed() {
+                search.found = find(&search.query, entries.len());
+            }
+            if ui.input_mut(|i| i.consume_key(Modifiers::CTRL, Key::R)) {
+                let before = search.found.unwrap_or(entries.len());
+                search.found = find(&search.query, before).or(search.found);
+                response.request_focus();
+            }
+            let found = search.found.map(|index| entries[index].clone());
+            ui.monospace(found.as_deref().unwrap_or(""));
+
+            if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                result = Some(found.map_or(HistorySearch::Cancel, HistorySearch::Accept));
+            } else if ui.input(|i| i.key_pressed(Key::Escape)) {
+                result = Some(HistorySearch::Cancel);
+            }
+        });
+        if result.is_some() {
+            self.search = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(commands: &[&str], settings: &InputSettings) -> CommandHistory {
+        let mut history = CommandHistory::default();
+        for command in commands {
+            history.add("world", command, settings);
+        }
+        history
+    }
+
+    fn entries(history: &CommandHistory) -> Vec<&str> {
+        history.worlds["world"].iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn next_on_an_empty_or_unknown_world_gives_nothing() {
+        let mut history = CommandHistory::default();
+        assert_eq!(history.next("world", ""), None);
+        assert_eq!(history.previous("world", ""), None);
+        assert_eq!(history.next("world", ""), None);
+
+        let mut history = typed(&["look"], &InputSettings::default());
+        assert_eq!(history.previous("elsewhere", ""), None);
+        assert_eq!(history.next("elsewhere", ""), None);
+    }
+
+    #[test]
+    fn browsing_keeps_to_the_typed_prefix_and_returns_to_it() {
+        let mut history = typed(
+            &["say hi", "look", "say bye", "north"],
+            &InputSettings::default(),
+        );
+        assert_eq!(history.previous("world", "sa").as_deref(), Some("say bye"));
+        assert_eq!(
+            history.previous("world", "say bye").as_deref(),
+            Some("say hi")
+        );
+        assert_eq!(history.previous("world", "say hi"), None);
+        assert_eq!(history.next("world", "say hi").as_deref(), Some("say bye"));
+        assert_eq!(history.next("world", "say bye").as_deref(), Some("sa"));
+        assert_eq!(history.next("world", "sa"), None);
+
+        assert_eq!(history.previous("world", "").as_deref(), Some("north"));
+        history.reset_navigation();
+        assert_eq!(history.previous("world", "l").as_deref(), Some("look"));
+    }
+
+    #[test]
+    fn repeats_are_dropped() {
+        let dedupe = InputSettings::default();
+        let history = typed(&["look", "north", "look", "look", " "], &dedupe);
+        assert_eq!(entries(&history), ["north", "look"]);
+
+        let keep = InputSettings {
+            history_dedupe: false,
+            ..Default::default()
+        };
+        let history = typed(&["look", "north", "look", "look"], &keep);
+        assert_eq!(entries(&history), ["look", "north", "look"]);
+    }
+
+    #[test]
+    fn history_size_drops_the_oldest() {
+        let settings = InputSettings {
+            history_size: 2,
+            ..Default::default()
+        };
+        let history = typed(&["one", "two", "three"], &settings);
+        assert_eq!(entries(&history), ["two", "three"]);
+    }
+
+    #[test]
+    fn import_adds_old_commands_to_one_world() {
+        let settings = InputSettings::default();
+        let mut history = typed(&["look"], &settings);
+        let old = vec!["north".to_owned(), "look".to_owned(), "  ".to_owned()];
+        history.import("world", old, &settings);
+        assert_eq!(entries(&history), ["north", "look"]);
+        assert!(!history.worlds.contains_key(""));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::app::telnet::StyledLine;
+
+/// Where Tab has got to among the candidates for one word.
+struct Cycle {
+    start: usize, // Byte offset of the word in the line
+    original: String,
+    candidates: Vec<String>,
+    index: usize, // Index into candidates; candidates.len() stands for the original word
+    line: String, // The line as last completed, to tell whether it was edited since
+    cursor: usize,
+}
+
+/// Tab completion in the input line. Repeated Tabs cycle through the candidates and back to
+/// what was typed; Shift+Tab cycles the other way.
+#[derive(Default)]
+pub struct TabCompletion {
+    cycle: Option<Cycle>,
+}
+
+impl TabCompletion {
+    /// Completes the word ending at byte `cursor` of `line`. `candidates` is asked for the words
+    /// starting with it, best first, when a new word is completed. Returns the new line and the
+    /// byte offset of the cursor in it.
+    pub fn complete(
+        &mut self,
+        line: &str,
+        cursor: usize,
+        forward: bool,
+        candidates: impl FnOnce(&str) -> Vec<String>,
+    ) -> Option<(String, usize)> {
+        let continuing = self
+            .cycle
+            .as_ref()
+            .is_some_and(|cycle| cycle.line == line && cycle.cursor == cursor);
+        if !continuing {
+            let start = line[..cursor]
+                .rfind(|c: char| !is_word_char(c))
+                .map_or(0, |index| index + 1);
+            let original = &line[start..cursor];
+            if original.is_empty() {
+                self.cycle = None;
+                return None;
+            }
+            let candidates = candidates(original);
+            if candidates.is_empty() {
+                self.cycle = None;
+                return None;
+            }
+            self.cycle = Some(Cycle {
+                start,
+                original: original.to_string(),
+                index: candidates.len(),
+                candidates,
+                line: line.to_string(),
+                cursor,
+            });
+        }
+
+        let cycle = self.cycle.as_mut()?;
+        let steps = cycle.candidates.len() + 1;
+        cycle.index = if forward {
+            (cycle.index + 1) % steps
+        } else {
+            (cycle.index + steps - 1) % steps
+        };
+        let word = cycle.candidates.get(cycle.index).unwrap_or(&cycle.original);
+        let mut completed = line[..cycle.start].to_string();
+        completed.push_str(word);
+        let cursor = completed.len();
+        completed.push_str(&line[cycle.cursor..]);
+        cycle.line = completed.clone();
+        cycle.cursor = cursor;
+        Some((completed, cursor))
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '\'' || c == '_'
+}
+
+/// Adds the words starting with `prefix`, ignoring case, that aren't already in `candidates`.
+pub fn add_candidates<'a>(
+    candidates: &mut Vec<String>,
+    prefix: &str,
+    words: impl IntoIterator<Item = &'a str>,
+) {
+    let prefix = prefix.to_lowercase();
+    for word in words {
+        let lower = word.to_lowercase();
+        if lower.len() > prefix.len()
+            && lower.starts_with(&prefix)
+            && !candidates.iter().any(|known| known.to_lowercase() == lower)
+        {
+            candidates.push(word.to_string());
+        }
+    }
+}
+
+/// The words of the last `lines` output lines, most recent first.
+pub fn scrollback_words(output: &[StyledLine], lines: usize) -> Vec<String> {
+    let mut words = Vec::new();
+    for line in output.iter().rev().take(lines) {
+        let text: String = line.iter().map(|(text, _)| text.as_str()).collect();
+        words.extend(
+            text.split(|c: char| !is_word_char(c))
+                .rev()
+                .map(|word| word.trim_matches(|c| c == '-' || c == '\''))
+                .filter(|word| word.chars().count() >= 3)
+                .map(str::to_string),
+        );
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::Color32;
+
+    fn words<'a>(candidates: &'a [&'a str]) -> impl FnOnce(&str) -> Vec<String> + 'a {
+        |_| candidates.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn tab_cycles_through_the_candidates_and_back_to_the_word() {
+        let mut completion = TabCompletion::default();
+        let line = "kill dr now";
+        let found = completion.complete(line, 7, true, words(&["dragon", "drake"]));
+        assert_eq!(found, Some(("kill dragon now".to_owned(), 11)));
+        let (line, cursor) = found.unwrap();
+        let found = completion.complete(&line, cursor, true, words(&[]));
+        assert_eq!(found, Some(("kill drake now".to_owned(), 10)));
+        let (line, cursor) = found.unwrap();
+        let found = completion.complete(&line, cursor, true, words(&[]));
+        assert_eq!(found, Some(("kill dr now".to_owned(), 7)));
+
+        let (line, cursor) = found.unwrap();
+        let found = completion.complete(&line, cursor, false, words(&[]));
+        assert_eq!(found, Some(("kill drake now".to_owned(), 10)));
+        let (line, cursor) = found.unwrap();
+        let found = completion.complete(&line, cursor, false, words(&[]));
+        assert_eq!(found, Some(("kill dragon now".to_owned(), 11)));
+    }
+
+    #[test]
+    fn an_edit_starts_a_new_word() {
+        let mut completion = TabCompletion::default();
+        let (line, _) = completion
+            .complete("get sw", 6, true, words(&["sword"]))
+            .unwrap();
+        assert_eq!(line, "get sword");
+        let edited = "get sword sh";
+        let found = completion.complete(edited, edited.len(), true, words(&["shield"]));
+        assert_eq!(found, Some(("get sword shield".to_owned(), 16)));
+        assert_eq!(
+            completion.complete("get ", 4, true, words(&["sword"])),
+            None
+        );
+    }
+
+    #[test]
+    fn scrollback_words_come_most_recent_first_without_repeats() {
+        let output: Vec<StyledLine> = ["A goblin snarls.", "The GOBLIN glints."]
+            .iter()
+            .map(|text| vec![(text.to_string(), Color32::WHITE)])
+            .collect();
+        let words = scrollback_words(&output, 10);
+        assert_eq!(words, ["glints", "GOBLIN", "The", "snarls", "goblin"]);
+
+        let mut candidates = vec!["gold".to_owned()];
+        add_candidates(&mut candidates, "G", words.iter().map(String::as_str));
+        add_candidates(&mut candidates, "g", ["GOLD", "Glints", "g"]);
+        assert_eq!(candidates, ["gold", "glints", "GOBLIN"]);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::app::TemplateApp;
+use egui::{Rect, Ui, WidgetText};
+use egui_dock::{DockArea, DockState, NodeIndex, Style, TabViewer};
+use std::collections::HashMap;
+
+/// The panes that can be docked, split and tabbed in the main window.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+pub enum Tab {
+    Output,
+    Input,
+    Chat,
+    Map,
+    LuaRepl,
+    Variables,
+    ScriptPanels,
+    ScriptErrors,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 8] = [
+        Tab::Output,
+        Tab::Input,
+        Tab::Chat,
+        Tab::Map,
+        Tab::LuaRepl,
+        Tab::Variables,
+        Tab::ScriptPanels,
+        Tab::ScriptErrors,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Tab::Output => "Output",
+            Tab::Input => "Input",
+            Tab::Chat => "Chat",
+            Tab::Map => "Map",
+            Tab::LuaRepl => "Lua REPL",
+            Tab::Variables => "Variables",
+            Tab::ScriptPanels => "Script Panels",
+            Tab::ScriptErrors => "Script Errors",
+        }
+    }
+}
+
+/// Output fills the centre with the input under it; chat, map and variables share a column on
+/// the right.
+pub fn default_layout() -> DockState<Tab> {
+    let mut state = DockState::new(vec![Tab::Output]);
+    let surface = state.main_surface_mut();
+    let [output, _] = surface.split_right(
+        NodeIndex::root(),
+        0.75,
+        vec![Tab::Chat, Tab::Map, Tab::Variables],
+    );
+    surface.split_below(output, 0.9, vec![Tab::Input]);
+    state
+}
+
+/// The dock layout of every world, keyed by `host:port`. The empty key is the layout used
+/// before the first connection.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+pub struct DockLayouts {
+    world: String,
+    layouts: HashMap<String, DockState<Tab>>,
+}
+
+impl DockLayouts {
+    fn current(&mut self) -> &mut DockState<Tab> {
+        self.layouts
+            .entry(self.world.clone())
+            .or_insert_with(default_layout)
+    }
+
+    /// Switches to the layout saved for `world`. A world seen for the first time starts from
+    /// the layout in use now.
+    pub fn switch_world(&mut self, world: &str) {
+        if !self.layouts.contains_key(world) {
+            let layout = self.current().clone();
+            self.layouts.insert(world.to_string(), layout);
+        }
+        self.world = world.to_string();
+    }
+
+    pub fn reset(&mut self) {
+        *self.current() = default_layout();
+    }
+
+    /// Brings `tab` to the front, docking it beside the chat pane (or the output) if it was
+    /// closed.
+    pub fn open_tab(&mut self, tab: Tab) {
+        let state = self.current();
+        if let Some(location) = state.find_tab(&tab) {
+            state.set_active_tab(location);
+            state.set_focused_node_and_surface((location.0, location.1));
+            return;
+        }
+
+        match state
+            .find_tab(&Tab::Chat)
+            .or_else(|| state.find_tab(&Tab::Output))
+        {
+            Some((surface, node, _)) => {
+                state.set_focused_node_and_surface((surface, node));
+                state.push_to_focused_leaf(tab);
+            }
+            None => state.push_to_first_leaf(tab),
+        }
+    }
+
+    pub fn toggle_tab(&mut self, tab: Tab) {
+        let state = self.current();
+        match state.find_tab(&tab) {
+            Some(location) => {
+                state.remove_tab(location);
+            }
+            None => self.open_tab(tab),
+        }
+    }
+}
+
+/// Draws the dock in the space left by the menu bar and returns the rect of the output pane,
+/// or `None` when it is hidden behind another tab.
+pub fn show(app: &mut TemplateApp, ctx: &egui::Context) -> Option<Rect> {
+    // The layout is taken out while it is drawn so the tabs can borrow the rest of the app.
+    let world = app.dock.world.clone();
+    let mut state = app
+        .dock
+        .layouts
+        .remove(&world)
+        .unwrap_or_else(default_layout);
+    let mut tabs = AppTabs {
+        app,
+        output_rect: None,
+    };
+    DockArea::new(&mut state)
+        .style(Style::from_egui(ctx.style().as_ref()))
+        .show(ctx, &mut tabs);
+    let output_rect = tabs.output_rect;
+    app.dock.layouts.insert(world, state);
+    output_rect
+}
+
+struct AppTabs<'a> {
+    app: &'a mut TemplateApp,
+    output_rect: Option<Rect>,
+}
+
+impl TabViewer for AppTabs<'_> {
+    type Tab = Tab;
+
+    fn title(&mut self, tab: &mut Tab) -> WidgetText {
+        tab.title().into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, tab: &mut Tab) {
+        match tab {
+            Tab::Output => {
+                self.output_rect = Some(ui.max_rect());
+                self.app.output_ui(ui);
+            }
+            Tab::Input => self.app.input_ui(ui),
+            Tab::Chat => {
+                let world = self.app.world();
+                let font_id = self
+                    .app
+                    .settings_window
+                    .settings
+                    .fonts
+                    .output_font_id(&world);
+                self.app.telnet_client.lock().unwrap().chat_ui(ui, &font_id);
+            }
+            Tab::Map => self.app.mapper.ui(ui),
+            Tab::LuaRepl => self.app.lua_repl.ui(ui, &self.app.lua_executor),
+            Tab::Variables => self.app.plugin_manager.variables_ui(ui),
+            Tab::ScriptPanels => {
+                self.app.lua_executor.show_panels(ui);
+                self.app.plugin_manager.show_panels(ui);
+            }
+            Tab::ScriptErrors => self.app.script_errors.ui(ui),
+        }
+    }
+
+    fn closeable(&mut self, tab: &mut Tab) -> bool {
+        !matches!(tab, Tab::Output | Tab::Input)
+    }
+
+    fn scroll_bars(&self, tab: &Tab) -> [bool; 2] {
+        // Panes with their own stick-to-bottom scroll areas must not be wrapped in another one.
+        match tab {
+            Tab::Map | Tab::Variables | Tab::ScriptPanels => [false, true],
+            _ => [false, false],
+        }
+    }
+}
@@ -1,12 +1,29 @@
-use crate::app::ansi_color::COLOR_MAP;
+use crate::app::ansi_color::{palette_color, COLOR_MAP};
 use crate::app::telnet::parse_ansi_codes;
 use crate::app::telnet::TelnetClient;
 use egui::Color32;
 use mlua::prelude::*;
 use std::sync::{Arc, Mutex};
+
+/// Commands scripts and plugins sent with `Send`, waiting for the client to pass them through
+/// the same plugin callbacks, log and mapper as typed commands.
+#[derive(Clone, Default)]
+pub struct SendQueue(Arc<Mutex<Vec<String>>>);
+
+impl SendQueue {
+    pub fn push(&self, command: &str) {
+        self.0.lock().unwrap().push(command.to_string());
+    }
+
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
 #[derive(Clone)]
 pub struct LuaFunctions {
     telnet_client: Arc<Mutex<TelnetClient>>,
+    sends: SendQueue,
 }
 
 impl LuaFunctions {
@@ -46,14 +63,8 @@ impl LuaFunctions {
         &self,
         (text_colour, back_colour, text): (String, String, String),
     ) -> LuaResult<()> {
-        let text_colour = COLOR_MAP
-            .get(&*text_colour)
-            .unwrap_or(&Color32::WHITE)
-            .clone();
-        let back_colour = COLOR_MAP
-            .get(&*back_colour)
-            .unwrap_or(&Color32::BLACK)
-            .clone();
+        let text_colour = palette_color(&text_colour).unwrap_or(Color32::WHITE);
+        let back_colour = palette_color(&back_colour).unwrap_or(Color32::BLACK);
         let mut telnet_client = self.telnet_client.lock().unwrap();
         telnet_client.append_text_with_colours(&format!("{}\n", text), text_colour, back_colour);
         Ok(())
@@ -63,14 +74,8 @@ impl LuaFunctions {
         &self,
         (text_colour, back_colour, text): (String, String, String),
     ) -> LuaResult<()> {
-        let text_colour = COLOR_MAP
-            .get(&*text_colour)
-            .unwrap_or(&Color32::WHITE)
-            .clone();
-        let back_colour = COLOR_MAP
-            .get(&*back_colour)
-            .unwrap_or(&Color32::BLACK)
-            .clone();
+        let text_colour = palette_color(&text_colour).unwrap_or(Color32::WHITE);
+        let back_colour = palette_color(&back_colour).unwrap_or(Color32::BLACK);
         let mut telnet_client = self.telnet_client.lock().unwrap();
         telnet_client.append_text_with_colours(&text, text_colour, back_colour);
         Ok(())
@@ -88,18 +93,25 @@ impl LuaFunctions {
         }
         Ok(())
     }
+
+    pub fn chat_note(&self, text: String) -> LuaResult<()> {
+        let mut telnet_client = self.telnet_client.lock().unwrap();
+        telnet_client.append_chat(&text);
+        Ok(())
+    }
     //================================================================================================
     // COLOUR FUNCTIONS
+    // Colours are packed as 0xBBGGRR, as in MUSHclient.
     pub fn colour_name_to_rgb(&self, name: String) -> LuaResult<i32> {
-        let color = COLOR_MAP.get(&*name).unwrap_or(&Color32::WHITE);
-        let rgb = (color.r() as i32) << 16 | (color.g() as i32) << 8 | color.b() as i32;
+        let color = palette_color(&name).unwrap_or(Color32::WHITE);
+        let rgb = (color.b() as i32) << 16 | (color.g() as i32) << 8 | color.r() as i32;
         Ok(rgb)
     }
 
     pub fn rgb_colour_to_name(&self, colour: i32) -> LuaResult<String> {
-        let r = ((colour >> 16) & 0xFF) as u8;
+        let r = (colour & 0xFF) as u8;
         let g = ((colour >> 8) & 0xFF) as u8;
-        let b = (colour & 0xFF) as u8;
+        let b = ((colour >> 16) & 0xFF) as u8;
         let color = Color32::from_rgb(r, g, b);
         let name = COLOR_MAP
             .iter()
@@ -119,14 +131,27 @@ impl LuaFunctions {
         Ok(ansi_code)
     }
     //================================================================================================
+    // WORLD FUNCTIONS
+    pub fn send(&self, text: String) -> LuaResult<()> {
+        for command in text.lines() {
+            self.sends.push(command);
+        }
+        Ok(())
+    }
+    //================================================================================================
 }
 
-pub fn init_lua(lua: &Lua, telnet_client: Arc<Mutex<TelnetClient>>) -> LuaResult<()> {
+pub fn init_lua(
+    lua: &Lua,
+    telnet_client: Arc<Mutex<TelnetClient>>,
+    sends: SendQueue,
+) -> LuaResult<()> {
     println!("Initializing Lua environment with custom functions...");
     println!("Lua instance address in init_lua: {:p}", lua);
 
     let functions = LuaFunctions {
         telnet_client: telnet_client.clone(),
+        sends,
     };
 
     let globals = lua.globals();
@@ -139,9 +164,11 @@ pub fn init_lua(lua: &Lua, telnet_client: Arc<Mutex<TelnetClient>>) -> LuaResult
     let colour_note_functions = functions.clone();
     let colour_tell_functions = functions.clone();
     let ansi_note_functions = functions.clone();
+    let chat_note_functions = functions.clone();
     let colour_name_to_rgb_function = functions.clone();
     let rgb_colour_to_name_function = functions.clone();
     let ansi_function = functions.clone();
+    let send_function = functions.clone();
 
     // Set print function
     globals.set(
@@ -189,6 +216,12 @@ pub fn init_lua(lua: &Lua, telnet_client: Arc<Mutex<TelnetClient>>) -> LuaResult
         lua.create_function(move |_, text: String| ansi_note_functions.ansi_note(text))?,
     )?;
 
+    // Set chat note function, which writes to the chat pane instead of the output
+    globals.set(
+        "ChatNote",
+        lua.create_function(move |_, text: String| chat_note_functions.chat_note(text))?,
+    )?;
+
     globals.set(
         "ColourNameToRGB",
         lua.create_function(move |_, name: String| {
@@ -207,6 +240,11 @@ pub fn init_lua(lua: &Lua, telnet_client: Arc<Mutex<TelnetClient>>) -> LuaResult
         "ANSI",
         lua.create_function(move |_, code: i16| ansi_function.ansi(code))?,
     )?;
+
+    globals.set(
+        "Send",
+        lua.create_function(move |_, text: String| send_function.send(text))?,
+    )?;
     println!("Custom functions set in Lua environment.");
     println!("Lua environment initialized successfully.");
 
@@ -0,0 +1,519 @@
+use crate::app::miniwindow::{E_BAD_PARAMETER, E_OK};
+use egui::{Color32, ComboBox, Event, Grid, Key, Modifiers, Window};
+use mlua::{Lua, Result as LuaResult};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Shortcuts the app handles itself, shown when a binding takes one of them over.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("Ctrl+F", "search the output"),
+    ("Ctrl+R", "search the command history"),
+    ("Ctrl+C", "copy"),
+    ("Ctrl+V", "paste"),
+    ("Ctrl+X", "cut"),
+    ("Ctrl+Plus", "zoom in"),
+    ("Ctrl+Equals", "zoom in"),
+    ("Ctrl+Minus", "zoom out"),
+    ("Ctrl+0", "reset the zoom"),
+    ("Tab", "complete the word"),
+    ("Shift+Tab", "complete the word"),
+    ("F3", "next search match"),
+    ("Shift+F3", "previous search match"),
+    ("Up", "previous command"),
+    ("Down", "next command"),
+    ("Enter", "send the command"),
+    ("Escape", "close the search bar"),
+];
+
+/// A key with the modifiers held down with it. Written like `Ctrl+Alt+F5`.
+#[derive(
+    serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct KeyChord {
+    pub key: Key,
+    pub ctrl: bool, // Cmd on macOS
+    pub alt: bool,
+    pub shift: bool,
+}
+
+impl KeyChord {
+    pub fn new(key: Key, modifiers: Modifiers) -> Self {
+        Self {
+            key,
+            ctrl: modifiers.command,
+            alt: modifiers.alt,
+            shift: modifiers.shift,
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut chord = Self::new(Key::Escape, Modifiers::NONE);
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        // `Ctrl++` ends in an empty part for the plus key itself.
+        if text.ends_with("++") {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let (key, modifiers) = parts.split_last()?;
+        for modifier in modifiers {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "cmd" | "control" => chord.ctrl = true,
+                "alt" | "option" => chord.alt = true,
+                "shift" => chord.shift = true,
+                _ => return None,
+            }
+        }
+        // MUSHclient's `Numpad5` becomes the digit: egui can't tell the two apart.
+        chord.key = Key::from_name(key)
+            .or_else(|| Key::from_name(&key.to_ascii_uppercase()))
+            .or_else(|| key.strip_prefix("Numpad").and_then(Key::from_name))?;
+        Some(chord)
+    }
+
+    /// Whether pressing the chord also types a character, so it only acts as a binding while
+    /// the input line is empty.
+    fn types_text(&self) -> bool {
+        let arrow = matches!(
+            self.key,
+            Key::ArrowUp | Key::ArrowDown | Key::ArrowLeft | Key::ArrowRight
+        );
+        !self.ctrl
+            && !self.alt
+            && (self.key == Key::Space || !arrow && self.key.symbol_or_name().chars().count() == 1)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.ctrl, "Ctrl+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        f.write_str(self.key.name())
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum BindingAction {
+    Send,    // Straight to the world
+    Execute, // As if typed: aliases, command stacks and speedwalks apply
+    Lua,     // Runs as a Lua chunk in the scripts' state
+}
+
+impl BindingAction {
+    fn label(self) -> &'static str {
+        match self {
+            BindingAction::Send => "Send",
+            BindingAction::Execute => "Execute",
+            BindingAction::Lua => "Lua",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Binding {
+    pub chord: KeyChord,
+    pub action: BindingAction,
+    pub text: String,
+}
+
+/// Bindings made by scripts with `Accelerator`. They last until the app closes, like
+/// MUSHclient's, and are executed as if typed.
+#[derive(Clone, Default)]
+pub struct Accelerators {
+    bindings: Arc<Mutex<BTreeMap<KeyChord, String>>>,
+}
+
+impl Accelerators {
+    fn get(&self, chord: &KeyChord) -> Option<String> {
+        self.bindings.lock().unwrap().get(chord).cloned()
+    }
+}
+
+/// `Accelerator(key, send)` binds a key like `"Ctrl+F5"` to text executed as if typed. An
+/// empty `send` removes the binding.
+pub fn register_functions(lua: &Lua, accelerators: Accelerators) -> LuaResult<()> {
+    lua.globals().set(
+        "Accelerator",
+        lua.create_function(move |_, (key, send): (String, String)| {
+            let Some(chord) = KeyChord::parse(&key) else {
+                return Ok(E_BAD_PARAMETER);
+            };
+            let mut bindings = accelerators.bindings.lock().unwrap();
+            if send.is_empty() {
+                bindings.remove(&chord);
+            } else {
+                bindings.insert(chord, send);
+            }
+            Ok(E_OK)
+        })?,
+    )
+}
+
+/// Keyboard macros: chords bound to text or Lua, for every world with per-world overrides.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    #[serde(skip)]
+    pub open: bool,
+    global: Vec<Binding>,
+    worlds: BTreeMap<String, Vec<Binding>>,
+    text_keys: bool, // Keys that type text, like the numpad digits, may act at all
+    #[serde(skip)]
+    pub accelerators: Accelerators,
+    #[serde(skip)]
+    editing_world: bool, // The editor shows the current world's overrides
+    #[serde(skip)]
+    capturing: Option<usize>, // Row waiting for a key press to bind
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            open: false,
+            global: compass_directions(),
+            worlds: BTreeMap::new(),
+            text_keys: true,
+            accelerators: Accelerators::default(),
+            editing_world: false,
+            capturing: None,
+        }
+    }
+}
+
+/// The digits as a compass laid out like the numpad, with 5 to look. egui reports numpad and
+/// top-row digits as the same keys, so they only act while the input line is empty.
+fn compass_directions() -> Vec<Binding> {
+    [
+        (Key::Num1, "sw"),
+        (Key::Num2, "s"),
+        (Key::Num3, "se"),
+        (Key::Num4, "w"),
+        (Key::Num5, "look"),
+        (Key::Num6, "e"),
+        (Key::Num7, "nw"),
+        (Key::Num8, "n"),
+        (Key::Num9, "ne"),
+    ]
+    .into_iter()
+    .map(|(key, text)| Binding {
+        chord: KeyChord::new(key, Modifiers::NONE),
+        action: BindingAction::Execute,
+        text: text.to_owned(),
+    })
+    .collect()
+}
+
+impl KeyBindings {
+    /// What `chord` is bound to: the world's own bindings win over script accelerators, which
+    /// win over the global bindings.
+    fn lookup(&self, world: &str, chord: &KeyChord) -> Option<(BindingAction, String)> {
+        let find = |bindings: &[Binding]| {
+            bindings
+                .iter()
+                .find(|binding| binding.chord == *chord)
+                .map(|binding| (binding.action, binding.text.clone()))
+        };
+        self.worlds
+            .get(world)
+            .and_then(|bindings| find(bindings))
+            .or_else(|| {
+                self.accelerators
+                    .get(chord)
+                    .map(|text| (BindingAction::Execute, text))
+            })
+            .or_else(|| find(&self.global))
+    }
+
+    /// Takes the key presses this frame that are bound, with the text they would have typed,
+    /// and returns their actions. `text_keys` says whether keys that type text may be used.
+    pub fn take_pressed(
+        &self,
+        ctx: &egui::Context,
+        world: &str,
+        text_keys: bool,
+    ) -> Vec<(BindingAction, String)> {
+        if self.capturing.is_some() {
+            return Vec::new();
+        }
+        let mut actions = Vec::new();
+        ctx.input_mut(|i| {
+            let mut swallow_text = false;
+            i.events.retain(|event| match event {
+                Event::Key {
+                    key,
+                    pressed: true,
+                    repeat: false,
+                    modifiers,
+                    ..
+                } => {
+                    let chord = KeyChord::new(*key, *modifiers);
+                    if chord.types_text() && !(text_keys && self.text_keys) {
+                        return true;
+                    }
+                    match self.lookup(world, &chord) {
+                        Some(action) => {
+                            actions.push(action);
+                            swallow_text = chord.types_text();
+                            false
+                        }
+                        None => true,
+                    }
+                }
+                Event::Text(_) if swallow_text => {
+                    swallow_text = false;
+                    false
+                }
+                _ => true,
+            });
+        });
+        actions
+    }
+
+    /// The editor for the global bindings or the current world's overrides.
+    pub fn show(&mut self, ctx: &egui::Context, world: &str) {
+        let mut open = self.open;
+        Window::new("Key bindings")
+            .open(&mut open)
+            .default_width(520.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Bindings for");
+                    ComboBox::from_id_source("binding_scope")
+                        .selected_text(if self.editing_world {
+                            world
+                        } else {
+                            "all worlds"
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.editing_world, false, "all worlds");
+                            ui.selectable_value(&mut self.editing_world, true, world);
+                        });
+                });
+                ui.checkbox(
+                    &mut self.text_keys,
+                    "Keys that type text, like the numpad digits, act while the input line is \
+                     empty",
+                )
+                .on_hover_text(
+                    "The top-row digits count too, since the numpad can't be told apart from \
+                     them. Turn this off to type digits at prompts.",
+                );
+                ui.separator();
+
+                let global = self.global.clone();
+                let bindings = if self.editing_world {
+                    self.worlds.entry(world.to_string()).or_default()
+                } else {
+                    &mut self.global
+                };
+                let conflicts: Vec<Option<String>> = (0..bindings.len())
+                    .map(|index| conflict(bindings, index, self.editing_world.then_some(&global)))
+                    .collect();
+
+                let mut remove = None;
+                Grid::new("key_bindings")
+                    .num_columns(5)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (index, binding) in bindings.iter_mut().enumerate() {
+                            let capturing = self.capturing == Some(index);
+                            let label = if capturing {
+                                "Press a key…".to_owned()
+                            } else {
+                                binding.chord.to_string()
+                            };
+                            if ui.button(label).clicked() {
+                                self.capturing = Some(index);
+                            }
+                            if capturing {
+                                if let Some(chord) = pressed_chord(ui) {
+                                    if chord.key != Key::Escape {
+                                        binding.chord = chord;
+                                    }
+                                    self.capturing = None;
+                                }
+                            }
+                            ComboBox::from_id_source(("binding_action", index))
+                                .selected_text(binding.action.label())
+                                .show_ui(ui, |ui| {
+                                    for action in [
+                                        BindingAction::Send,
+                                        BindingAction::Execute,
+                                        BindingAction::Lua,
+                                    ] {
+                                        ui.selectable_value(
+                                            &mut binding.action,
+                                            action,
+                                            action.label(),
+                                        );
+                                    }
+                                });
+                            ui.text_edit_singleline(&mut binding.text);
+                            if ui.button("✖").clicked() {
+                                remove = Some(index);
+                            }
+                            match &conflicts[index] {
+                                Some(conflict) => ui.colored_label(Color32::YELLOW, conflict),
+                                None => ui.label(""),
+                            };
+                            ui.end_row();
+                        }
+                    });
+                if let Some(index) = remove {
+                    bindings.remove(index);
+                    self.capturing = None;
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Add binding").clicked() {
+                        bindings.push(Binding {
+                            chord: KeyChord::new(Key::F1, Modifiers::NONE),
+                            action: BindingAction::Send,
+                            text: String::new(),
+                        });
+                        self.capturing = Some(bindings.len() - 1);
+                    }
+                    if ui.button("Restore numpad directions").clicked() {
+                        bindings.retain(|binding| {
+                            !compass_directions()
+                                .iter()
+                                .any(|compass| compass.chord == binding.chord)
+                        });
+                        bindings.extend(compass_directions());
+                    }
+                });
+
+                let accelerators = self.accelerators.bindings.lock().unwrap();
+                if !accelerators.is_empty() {
+                    ui.separator();
+                    ui.label("Set by scripts with Accelerator:");
+                    Grid::new("accelerators").num_columns(2).show(ui, |ui| {
+                        for (chord, text) in accelerators.iter() {
+                            ui.monospace(chord.to_string());
+                            ui.label(text);
+                            ui.end_row();
+                        }
+                    });
+                }
+            });
+        if self.worlds.get(world).is_some_and(Vec::is_empty) {
+            self.worlds.remove(world);
+        }
+        self.open = open;
+        if !open {
+            self.capturing = None;
+        }
+    }
+}
+
+/// Describes what the binding at `index` clashes with: another binding in the same list, a
+/// built-in shortcut, or, for a world's override, the global binding it hides.
+fn conflict(bindings: &[Binding], index: usize, global: Option<&Vec<Binding>>) -> Option<String> {
+    let chord = bindings[index].chord;
+    if let Some(other) = bindings
+        .iter()
+        .enumerate()
+        .position(|(other, binding)| other != index && binding.chord == chord)
+    {
+        return Some(format!("Same key as row {}", other + 1));
+    }
+    let name = chord.to_string();
+    if let Some((_, action)) = BUILT_IN.iter().find(|(key, _)| *key == name) {
+        return Some(format!("Replaces {}", action));
+    }
+    global
+        .is_some_and(|global| global.iter().any(|binding| binding.chord == chord))
+        .then(|| "Overrides the global binding".to_owned())
+}
+
+/// The first key pressed this frame, taken from the input so nothing else reacts to it.
+fn pressed_chord(ui: &egui::Ui) -> Option<KeyChord> {
+    ui.input_mut(|i| {
+        let index = i
+            .events
+            .iter()
+            .position(|event| matches!(event, Event::Key { pressed: true, .. }))?;
+        let Event::Key { key, modifiers, .. } = i.events.remove(index) else {
+            return None;
+        };
+        Some(KeyChord::new(key, modifiers))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(key: Key, ctrl: bool, alt: bool, shift: bool) -> KeyChord {
+        KeyChord {
+            key,
+            ctrl,
+            alt,
+            shift,
+        }
+    }
+
+    #[test]
+    fn parses_keys_with_modifiers() {
+        assert_eq!(
+            KeyChord::parse("F5"),
+            Some(chord(Key::F5, false, false, false))
+        );
+        assert_eq!(
+            KeyChord::parse("Ctrl+Alt+F5"),
+            Some(chord(Key::F5, true, true, false))
+        );
+        assert_eq!(
+            KeyChord::parse("shift + cmd + a"),
+            Some(chord(Key::A, true, false, true))
+        );
+        assert_eq!(
+            KeyChord::parse("Option+Control+Up"),
+            Some(chord(Key::ArrowUp, true, true, false))
+        );
+    }
+
+    #[test]
+    fn parses_the_plus_key() {
+        assert_eq!(
+            KeyChord::parse("Ctrl++"),
+            Some(chord(Key::Plus, true, false, false))
+        );
+        assert_eq!(
+            KeyChord::parse("Ctrl+Plus"),
+            Some(chord(Key::Plus, true, false, false))
+        );
+    }
+
+    #[test]
+    fn numpad_names_are_digits() {
+        assert_eq!(
+            KeyChord::parse("Numpad5"),
+            Some(chord(Key::Num5, false, false, false))
+        );
+        assert_eq!(KeyChord::parse("Numpad5"), KeyChord::parse("5"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_modifiers() {
+        assert_eq!(KeyChord::parse(""), None);
+        assert_eq!(KeyChord::parse("Ctrl+"), None);
+        assert_eq!(KeyChord::parse("Hyper+F5"), None);
+        assert_eq!(KeyChord::parse("F99"), None);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for text in ["F5", "Ctrl+Alt+Shift+F5", "Ctrl+Plus", "Alt+A", "Up"] {
+            let parsed = KeyChord::parse(text).unwrap();
+            assert_eq!(parsed.to_string(), text);
+            assert_eq!(KeyChord::parse(&parsed.to_string()), Some(parsed));
+        }
+    }
+}
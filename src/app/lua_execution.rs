@@ -1,114 +1,324 @@
-use crate::app::functions::init_lua;
+use crate::app::functions::{init_lua, SendQueue};
+use crate::app::keybindings::{self, Accelerators};
+use crate::app::lua_panels::LuaPanels;
+use crate::app::lua_scripts::{ScriptLoader, LUA_FOLDER};
+use crate::app::mapper::{self, SharedMap};
+use crate::app::miniwindow::{self, HotspotCall, Miniwindows};
+use crate::app::script_errors::ScriptErrors;
+use crate::app::script_limits::{limited_lua, ScriptGuard};
+use crate::app::session_log::{self, LogFiles, SessionLog};
 use crate::app::telnet::TelnetClient;
-use mlua::{Lua, Result};
+use egui::Color32;
+use mlua::{Error, Function, Lua, RegistryKey, Result, StdLib, Table, Value};
 use std::env;
-use std::fs;
 use std::sync::{Arc, Mutex};
+/// Lua helpers for the REPL, kept in the registry so scripts can't see or replace them.
+///
+/// `capture(f, ...)` runs `f` with the printing functions redirected into a string and returns
+/// that string with the packed `xpcall` results. `show(value)` formats a returned value,
+/// printing tables with the bundled `tprint`.
+const REPL_HELPERS: &str = r##"
+local names = { "print", "color_print", "Note", "Tell", "ColourNote", "ColourTell", "AnsiNote" }
+
+local function capture(f, ...)
+    local output = {}
+    local function plain(newline)
+        return function(...)
+            local parts = {}
+            for i = 1, select("#", ...) do
+                parts[i] = tostring((select(i, ...)))
+            end
+            output[#output + 1] = table.concat(parts, " ") .. (newline and "\n" or "")
+        end
+    end
+    local function styled(newline)
+        return function(...)
+            local parts = {}
+            for i = 3, select("#", ...), 3 do
+                parts[#parts + 1] = tostring((select(i, ...)))
+            end
+            output[#output + 1] = table.concat(parts) .. (newline and "\n" or "")
+        end
+    end
+
+    local saved = {}
+    for _, name in ipairs(names) do
+        saved[name] = _G[name]
+    end
+    print, color_print, Note, AnsiNote = plain(true), plain(true), plain(true), plain(true)
+    Tell = plain(false)
+    ColourNote, ColourTell = styled(true), styled(false)
+
+    local results = table.pack(xpcall(f, debug.traceback, ...))
+
+    for _, name in ipairs(names) do
+        _G[name] = saved[name]
+    end
+    return table.concat(output), results
+end
+
+local function show(value)
+    if type(value) == "table" and type(tprint) == "function" then
+        local output, results = capture(tprint, value)
+        if results[1] then
+            return "{\n" .. output .. "}"
+        end
+    elseif type(value) == "string" then
+        return string.format("%q", value)
+    end
+    return tostring(value)
+end
+
+return capture, show
+"##;
+
+/// What happened to a chunk typed into the REPL.
+pub enum ReplResult {
+    /// The chunk is not finished yet; more lines are needed.
+    Incomplete,
+    Done {
+        output: String,
+        results: Vec<String>,
+    },
+    Error {
+        output: String,
+        message: String,
+    },
+}
+
 pub struct LuaExecutor {
     lua: Lua,
-    output_buffer: Arc<Mutex<String>>,
+    guard: ScriptGuard,
+    capture: RegistryKey,
+    show: RegistryKey,
+    scripts: ScriptLoader,
+    panels: LuaPanels,
+    errors: ScriptErrors,
+    telnet_client: Arc<Mutex<TelnetClient>>,
 }
 
 impl Default for LuaExecutor {
     fn default() -> Self {
-        Self::new().expect("Failed to initialize Lua executor")
+        Self::new(
+            Arc::new(Mutex::new(TelnetClient::new())),
+            ScriptErrors::default(),
+            Miniwindows::default(),
+            SessionLog::default(),
+            Accelerators::default(),
+            SharedMap::default(),
+            SendQueue::default(),
+        )
+        .expect("Failed to initialize Lua executor")
     }
 }
 
 impl LuaExecutor {
-    pub fn new() -> Result<Self> {
-        let lua = Lua::new();
-        let output_buffer = Arc::new(Mutex::new(String::new()));
-        let telnet_client = Arc::new(Mutex::new(TelnetClient::new())); // Create a new TelnetClient instance
-        init_lua(&lua, telnet_client)?; // Call init_lua to expose custom functions
+    pub fn new(
+        telnet_client: Arc<Mutex<TelnetClient>>,
+        errors: ScriptErrors,
+        miniwindows: Miniwindows,
+        session_log: SessionLog,
+        accelerators: Accelerators,
+        map: SharedMap,
+        sends: SendQueue,
+    ) -> Result<Self> {
+        let (lua, guard) = limited_lua(StdLib::ALL_SAFE)?;
+        init_lua(&lua, telnet_client.clone(), sends)?; // Call init_lua to expose custom functions
+        miniwindow::register_functions(&lua, miniwindows, "")?;
+        session_log::register_functions(&lua, session_log, LogFiles::Anywhere)?;
+        keybindings::register_functions(&lua, accelerators)?;
+        mapper::register_functions(&lua, map)?;
+        let panels = LuaPanels::default();
+        panels.register(&lua)?;
+        set_package_path(&lua)?;
 
-        // Get the current working directory
-        let current_dir = env::current_dir().unwrap();
-        let lua_dir = current_dir.join("lua");
+        let (capture, show) = lua
+            .load(REPL_HELPERS)
+            .set_name("REPL helpers")
+            .eval::<(Function<'_>, Function<'_>)>()?;
+        let capture = lua.create_registry_value(capture)?;
+        let show = lua.create_registry_value(show)?;
 
-        // Set LUA_PATH to include the lua directory
-        let lua_path = format!("{}/?.lua", lua_dir.to_str().unwrap());
+        // Load Lua scripts from the "lua" folder
+        let mut scripts = ScriptLoader::new(LUA_FOLDER);
+        scripts.register(&lua)?;
+        scripts.load_all(&lua, &guard, &errors);
 
-        // Set the LUA_PATH in the Lua state
-        lua.load(&format!(r#"package.path = "{}""#, lua_path))
-            .exec()?;
+        Ok(Self {
+            lua,
+            guard,
+            capture,
+            show,
+            scripts,
+            panels,
+            errors,
+            telnet_client,
+        })
+    }
 
-        // Load Lua scripts from the "lua" folder
-        load_lua_scripts(&lua, "lua")?;
+    /// Runs every script in the `lua` folder again.
+    pub fn reload_scripts(&mut self) {
+        let loaded = self.scripts.load_all(&self.lua, &self.guard, &self.errors);
+        self.note(&format!("Reloaded {} Lua scripts", loaded.len()));
+    }
 
-        Ok(Self { lua, output_buffer })
+    /// Reloads scripts that changed on disk or that asked for it with `ReloadScripts()`.
+    pub fn poll_scripts(&mut self) {
+        for name in self.scripts.poll(&self.lua, &self.guard, &self.errors) {
+            self.note(&format!("Reloaded {}", name));
+        }
     }
 
-    pub fn execute(&self, code: &str) -> Result<()> {
-        let modified_lua_code = format!(
-            "local old_print = print; \
-             local old_color_print = color_print; \
-             local old_note = Note; \
-             local old_tell = Tell; \
-             local old_colour_note = colour_note; \
-             local old_colour_tell = ColourTell; \
-             local old_ansi_note = AnsiNote; \
-             local output = ''; \
-             print = function(...) old_print(...); output = output .. table.concat({{...}}, ' ') .. '\\n'; end; \
-             color_print = function(...) old_color_print(...); output = output .. table.concat({{...}}, ' ') .. '\\n'; end; \
-             Note = function(...) old_note(...); output = output .. table.concat({{...}}, ' ') .. '\\n'; end; \
-             Tell = function(...) old_tell(...); output = output .. table.concat({{...}}, ' '); end; \
-             colour_note = function(...) old_colour_note(...); output = output .. table.concat({{...}}, ' ') .. '\\n'; end; \
-             ColourTell = function(...) old_colour_tell(...); output = output .. table.concat({{...}}, ' '); end; \
-             AnsiNote = function(...) old_ansi_note(...); output = output .. table.concat({{...}}, ' ') .. '\\n'; end; \
-             {} \
-             print = old_print; \
-             color_print = old_color_print; \
-             Note = old_note; \
-             Tell = old_tell; \
-             colour_note = old_colour_note; \
-             ColourTell = old_colour_tell; \
-             AnsiNote = old_ansi_note; \
-             return output",
-            code
-        );
-
-        let output = self.lua.load(&modified_lua_code).eval::<String>()?;
-        *self.output_buffer.lock().unwrap() = output;
-        Ok(())
+    /// Draws the panels registered with `RegisterPanel`.
+    pub fn show_panels(&self, ui: &mut egui::Ui) {
+        self.panels
+            .show(ui, &self.lua, &self.guard, &self.errors, "");
     }
-    pub fn take_output(&self) -> String {
-        self.output_buffer.lock().unwrap().clone()
+
+    /// Runs a miniwindow hotspot callback defined by the scripts in the `lua` folder.
+    pub fn hotspot_callback(&self, call: &HotspotCall) {
+        let result = self.guard.run(&self.lua, || {
+            match self
+                .lua
+                .globals()
+                .get::<_, Option<Function<'_>>>(call.callback.as_str())?
+            {
+                Some(function) => function.call::<_, ()>((call.flags, call.hotspot_id.as_str())),
+                None => Err(Error::RuntimeError(format!(
+                    "hotspot callback {} not found",
+                    call.callback
+                ))),
+            }
+        });
+        if let Err(e) = result {
+            self.errors.report(&call.callback, &e.to_string());
+        }
     }
-}
 
-fn load_lua_scripts(lua: &Lua, lua_folder: &str) -> mlua::Result<()> {
-    let paths = match fs::read_dir(lua_folder) {
-        Ok(paths) => paths,
-        Err(e) => {
-            eprintln!("Failed to read Lua directory: {}", e);
-            return Ok(());
+    /// Runs a chunk of Lua, such as a key binding's, reporting errors under `name`.
+    pub fn run(&self, name: &str, code: &str) {
+        let result = self
+            .guard
+            .run(&self.lua, || self.lua.load(code).set_name(name).exec());
+        if let Err(e) = result {
+            self.errors.report(name, &e.to_string());
         }
-    };
+    }
 
-    for path in paths {
-        let path = match path {
-            Ok(path) => path.path(),
+    /// Asks the scripts' `OnTabComplete(word)` for completions of `word`, if it is defined.
+    pub fn tab_completions(&self, word: &str) -> Vec<String> {
+        let result = self.guard.run(&self.lua, || {
+            match self
+                .lua
+                .globals()
+                .get::<_, Option<Function<'_>>>("OnTabComplete")?
+            {
+                Some(function) => function.call::<_, Option<Vec<String>>>(word),
+                None => Ok(None),
+            }
+        });
+        match result {
+            Ok(words) => words.unwrap_or_default(),
             Err(e) => {
-                eprintln!("Failed to read path in Lua directory: {}", e);
-                continue;
+                self.errors.report("OnTabComplete", &e.to_string());
+                Vec::new()
             }
-        };
+        }
+    }
 
-        if path.extension().and_then(|ext| ext.to_str()) == Some("lua") {
-            let script = match fs::read_to_string(&path) {
-                Ok(script) => script,
+    fn note(&self, text: &str) {
+        self.telnet_client
+            .lock()
+            .unwrap()
+            .append_text(&format!("{}\n", text), Color32::GRAY);
+    }
+
+    /// Runs one REPL chunk. Expressions are tried first so `1 + 1` or `some_table` print a value.
+    pub fn eval(&self, code: &str) -> ReplResult {
+        let expression = self
+            .lua
+            .load(format!("return {}", code))
+            .set_name("REPL")
+            .into_function();
+        let function = match expression {
+            Ok(function) => function,
+            Err(_) => match self.lua.load(code).set_name("REPL").into_function() {
+                Ok(function) => function,
+                Err(Error::SyntaxError {
+                    incomplete_input: true,
+                    ..
+                }) => return ReplResult::Incomplete,
                 Err(e) => {
-                    eprintln!("Failed to read Lua file {:?}: {}", path, e);
-                    continue;
+                    return ReplResult::Error {
+                        output: String::new(),
+                        message: e.to_string(),
+                    }
                 }
-            };
+            },
+        };
+
+        match self.guard.run(&self.lua, || self.call_captured(function)) {
+            Ok(result) => result,
+            Err(e) => ReplResult::Error {
+                output: String::new(),
+                message: e.to_string(),
+            },
+        }
+    }
+
+    fn call_captured(&self, function: Function<'_>) -> Result<ReplResult> {
+        let capture: Function<'_> = self.lua.registry_value(&self.capture)?;
+        let show: Function<'_> = self.lua.registry_value(&self.show)?;
+        let (output, results) = capture.call::<_, (String, Table<'_>)>(function)?;
+
+        let count: i64 = results.get("n")?;
+        if !results.get::<_, bool>(1)? {
+            return Ok(ReplResult::Error {
+                output,
+                message: results.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            });
+        }
+        let results = (2..=count)
+            .map(|i| show.call::<_, String>(results.get::<_, Value<'_>>(i)?))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ReplResult::Done { output, results })
+    }
 
-            if let Err(e) = lua.load(&script).exec() {
-                eprintln!("Error executing Lua script {:?}: {}", path, e);
+    /// Names that could complete `prefix` inside the table reached by following `path` from
+    /// the globals. With `methods`, only functions are offered.
+    pub fn completions(&self, path: &[&str], prefix: &str, methods: bool) -> Vec<String> {
+        let mut table = self.lua.globals();
+        for key in path {
+            match table.get::<_, Value<'_>>(*key) {
+                Ok(Value::Table(next)) => table = next,
+                _ => return Vec::new(),
             }
         }
+
+        let mut names: Vec<String> = table
+            .pairs::<Value<'_>, Value<'_>>()
+            .filter_map(|pair| pair.ok())
+            .filter(|(_, value)| !methods || matches!(value, Value::Function(_)))
+            .filter_map(|(key, _)| match key {
+                Value::String(key) => key.to_str().ok().map(str::to_string),
+                _ => None,
+            })
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names
     }
+}
+
+/// Points `package.path` at the `lua` folder so scripts can `require` the bundled modules.
+pub fn set_package_path(lua: &Lua) -> Result<()> {
+    // Get the current working directory
+    let current_dir = env::current_dir().unwrap();
+    let lua_dir = current_dir.join("lua");
+
+    // Set LUA_PATH to include the lua directory
+    let lua_path = format!("{}/?.lua", lua_dir.to_str().unwrap());
 
-    Ok(())
+    // Set the LUA_PATH in the Lua state
+    lua.load(format!(r#"package.path = "{}""#, lua_path)).exec()
 }
@@ -0,0 +1,289 @@
+use crate::app::miniwindow::colour;
+use crate::app::script_errors::ScriptErrors;
+use crate::app::script_limits::ScriptGuard;
+use egui::{Align, CollapsingHeader, Grid, Layout, ProgressBar, RichText, TextEdit, Ui};
+use egui_plot::{Legend, Line, Plot, PlotPoints};
+use mlua::{Function, Lua, RegistryKey, Result, Table, UserDataMethods, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+const DEFAULT_PLOT_HEIGHT: f32 = 120.0;
+
+struct Panel {
+    function: RegistryKey,
+    open: bool,
+}
+
+/// Panels drawn every frame by a Lua function, registered with `RegisterPanel(title, fn)`, and
+/// shown in the Script Panels pane.
+///
+/// The function gets a `ui` userdata wrapping the panel's `egui::Ui`, so scripts build their
+/// interface the same immediate-mode way Rust code does.
+#[derive(Clone, Default)]
+pub struct LuaPanels {
+    panels: Rc<RefCell<BTreeMap<String, Panel>>>,
+}
+
+impl LuaPanels {
+    /// Adds `RegisterPanel`, `UnregisterPanel` and `ShowPanel`, and the methods of the `ui`
+    /// userdata.
+    pub fn register(&self, lua: &Lua) -> Result<()> {
+        register_ui_type(lua)?;
+        let globals = lua.globals();
+
+        let panels = self.panels.clone();
+        globals.set(
+            "RegisterPanel",
+            lua.create_function(move |lua, (title, function): (String, Function<'_>)| {
+                let function = lua.create_registry_value(function)?;
+                panels.borrow_mut().insert(
+                    title,
+                    Panel {
+                        function,
+                        open: true,
+                    },
+                );
+                Ok(())
+            })?,
+        )?;
+
+        let panels = self.panels.clone();
+        globals.set(
+            "UnregisterPanel",
+            lua.create_function(move |_, title: String| {
+                Ok(panels.borrow_mut().remove(&title).is_some())
+            })?,
+        )?;
+
+        let panels = self.panels.clone();
+        globals.set(
+            "ShowPanel",
+            lua.create_function(move |_, (title, show): (String, Option<bool>)| {
+                Ok(match panels.borrow_mut().get_mut(&title) {
+                    Some(panel) => {
+                        panel.open = show.unwrap_or(true);
+                        true
+                    }
+                    None => false,
+                })
+            })?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Draws the open panels. A panel whose function fails is closed and the error reported,
+    /// rather than repeating it every frame.
+    pub fn show(
+        &self,
+        ui: &mut Ui,
+        lua: &Lua,
+        guard: &ScriptGuard,
+        errors: &ScriptErrors,
+        owner: &str,
+    ) {
+        // Collect first: the panel functions may register or remove panels themselves.
+        let open: Vec<(String, Function<'_>)> = self
+            .panels
+            .borrow()
+            .iter()
+            .filter(|(_, panel)| panel.open)
+            .filter_map(|(title, panel)| {
+                let function = lua.registry_value(&panel.function).ok()?;
+                Some((title.clone(), function))
+            })
+            .collect();
+
+        for (title, function) in open {
+            let mut keep_open = true;
+            let mut result = Ok(());
+            ui.push_id(("lua_panel", owner, title.as_str()), |ui| {
+                ui.group(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.strong(&title);
+                        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                            if ui.small_button("✖").clicked() {
+                                keep_open = false;
+                            }
+                        });
+                    });
+                    ui.separator();
+                    result = guard.run(lua, || call_with_ui(lua, ui, &function));
+                });
+            });
+
+            if let Err(e) = &result {
+                let source = if owner.is_empty() {
+                    format!("Panel {}", title)
+                } else {
+                    format!("Plugin {} panel {}", owner, title)
+                };
+                errors.report(&source, &e.to_string());
+            }
+            if !keep_open || result.is_err() {
+                if let Some(panel) = self.panels.borrow_mut().get_mut(&title) {
+                    panel.open = false;
+                }
+            }
+        }
+    }
+}
+
+/// Calls `function` with a `ui` userdata that is only valid during the call.
+fn call_with_ui(lua: &Lua, ui: &mut Ui, function: &Function<'_>) -> Result<()> {
+    lua.scope(|scope| {
+        let ui = scope.create_any_userdata_ref_mut(ui)?;
+        function.call::<_, ()>(ui)
+    })
+}
+
+fn register_ui_type(lua: &Lua) -> Result<()> {
+    lua.register_userdata_type::<Ui>(|registry| {
+        registry.add_method_mut(
+            "label",
+            |_, ui, (text, text_colour): (String, Option<i64>)| {
+                match text_colour {
+                    Some(text_colour) => ui.colored_label(colour(text_colour), text),
+                    None => ui.label(text),
+                };
+                Ok(())
+            },
+        );
+
+        registry.add_method_mut("heading", |_, ui, text: String| {
+            ui.heading(text);
+            Ok(())
+        });
+
+        registry.add_method_mut("separator", |_, ui, ()| {
+            ui.separator();
+            Ok(())
+        });
+
+        registry.add_method_mut("button", |_, ui, text: String| {
+            Ok(ui.button(text).clicked())
+        });
+
+        registry.add_method_mut("checkbox", |_, ui, (text, mut checked): (String, bool)| {
+            ui.checkbox(&mut checked, text);
+            Ok(checked)
+        });
+
+        registry.add_method_mut(
+            "progress",
+            |_, ui, (fraction, text): (f32, Option<String>)| {
+                let mut bar = ProgressBar::new(fraction.clamp(0.0, 1.0));
+                if let Some(text) = text {
+                    bar = bar.text(text);
+                }
+                ui.add(bar);
+                Ok(())
+            },
+        );
+
+        // `text, submitted = ui:text_input(text, hint)`: returns the edited text, and whether
+        // Enter was pressed in the box.
+        registry.add_method_mut(
+            "text_input",
+            |_, ui, (mut text, hint): (Option<String>, Option<String>)| {
+                let text = text.get_or_insert_with(String::new);
+                let response =
+                    ui.add(TextEdit::singleline(text).hint_text(hint.unwrap_or_default()));
+                let submitted =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                Ok((text.clone(), submitted))
+            },
+        );
+
+        registry.add_method_mut("horizontal", |lua, ui, function: Function<'_>| {
+            ui.horizontal(|ui| call_with_ui(lua, ui, &function)).inner
+        });
+
+        // Returns whether the header is expanded.
+        registry.add_method_mut(
+            "collapsing",
+            |lua, ui, (title, function, default_open): (String, Function<'_>, Option<bool>)| {
+                let response = CollapsingHeader::new(RichText::new(title))
+                    .default_open(default_open.unwrap_or(false))
+                    .show(ui, |ui| call_with_ui(lua, ui, &function));
+                match response.body_returned {
+                    Some(result) => result.map(|()| true),
+                    None => Ok(false),
+                }
+            },
+        );
+
+        // `ui:table(headers, rows)`: rows is a list of lists of cell text.
+        registry.add_method_mut(
+            "table",
+            |_, ui, (headers, rows): (Option<Vec<String>>, Vec<Vec<String>>)| {
+                Grid::new(ui.next_auto_id()).striped(true).show(ui, |ui| {
+                    if let Some(headers) = headers {
+                        for header in headers {
+                            ui.strong(header);
+                        }
+                        ui.end_row();
+                    }
+                    for row in rows {
+                        for cell in row {
+                            ui.label(cell);
+                        }
+                        ui.end_row();
+                    }
+                });
+                Ok(())
+            },
+        );
+
+        // `ui:plot(series, height)`: series maps a line name to a list of y values or of
+        // `{x, y}` pairs.
+        registry.add_method_mut(
+            "plot",
+            |_, ui, (series, height): (Table<'_>, Option<f32>)| {
+                let mut lines = series
+                    .pairs::<String, Table<'_>>()
+                    .map(|pair| {
+                        let (name, values) = pair?;
+                        Ok((name, plot_points(values)?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                // Sorted so each line keeps its colour from frame to frame.
+                lines.sort_by(|a, b| a.0.cmp(&b.0));
+
+                Plot::new(ui.next_auto_id())
+                    .height(height.unwrap_or(DEFAULT_PLOT_HEIGHT))
+                    .legend(Legend::default())
+                    .show(ui, |plot_ui| {
+                        for (name, points) in lines {
+                            plot_ui.line(Line::new(points).name(name));
+                        }
+                    });
+                Ok(())
+            },
+        );
+    })
+}
+
+fn plot_points(values: Table<'_>) -> Result<PlotPoints> {
+    let mut points = Vec::new();
+    for (index, value) in values.sequence_values::<Value<'_>>().enumerate() {
+        match value? {
+            Value::Table(pair) => points.push([pair.get(1)?, pair.get(2)?]),
+            value => {
+                let y = match value {
+                    Value::Integer(y) => y as f64,
+                    Value::Number(y) => y,
+                    other => {
+                        return Err(mlua::Error::RuntimeError(format!(
+                            "plot values must be numbers or {{x, y}} pairs, not {}",
+                            other.type_name()
+                        )))
+                    }
+                };
+                points.push([index as f64, y]);
+            }
+        }
+    }
+    Ok(PlotPoints::new(points))
+}
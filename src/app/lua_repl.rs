@@ -0,0 +1,223 @@
+use crate::app::lua_execution::{LuaExecutor, ReplResult};
+use egui::text::{CCursor, CCursorRange};
+use egui::{Color32, Key, Modifiers, RichText, ScrollArea, TextEdit, TextStyle};
+
+const MAX_HISTORY: usize = 500;
+const MAX_CANDIDATES: usize = 50;
+
+enum ReplEntry {
+    Input { text: String, continuation: bool },
+    Output(String),
+    Result(String),
+    Error(String),
+}
+
+/// The Lua REPL pane: evaluates chunks in the script state and keeps a transcript.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+pub struct LuaRepl {
+    history: Vec<String>,
+    #[serde(skip)]
+    entries: Vec<ReplEntry>,
+    #[serde(skip)]
+    input: String,
+    #[serde(skip)]
+    pending: String,
+    #[serde(skip)]
+    history_index: Option<usize>,
+    #[serde(skip)]
+    candidates: Vec<String>,
+}
+
+impl LuaRepl {
+    pub fn ui(&mut self, ui: &mut egui::Ui, executor: &LuaExecutor) {
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        let reserved = row_height * if self.candidates.is_empty() { 2.0 } else { 4.0 };
+
+        ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .stick_to_bottom(true)
+            .max_height((ui.available_height() - reserved).max(row_height))
+            .show(ui, |ui| {
+                for entry in &self.entries {
+                    let text = match entry {
+                        ReplEntry::Input { text, continuation } => {
+                            let prompt = if *continuation { ">>" } else { ">" };
+                            RichText::new(format!("{} {}", prompt, text)).color(Color32::GRAY)
+                        }
+                        ReplEntry::Output(text) => RichText::new(text),
+                        ReplEntry::Result(text) => RichText::new(text).color(Color32::KHAKI),
+                        ReplEntry::Error(text) => RichText::new(text).color(Color32::RED),
+                    };
+                    ui.label(text.monospace());
+                }
+            });
+
+        if !self.candidates.is_empty() {
+            ui.separator();
+            ui.label(RichText::new(self.candidates.join("  ")).monospace().weak());
+        }
+
+        ui.horizontal(|ui| {
+            ui.monospace(if self.pending.is_empty() { ">" } else { ">>" });
+
+            let id = ui.make_persistent_id("lua_repl_input");
+            let focused = ui.memory(|memory| memory.has_focus(id));
+            // The arrows move between lines of a chunk being edited, and recall history
+            // otherwise. Shift+Enter starts a new line.
+            let recall = !self.input.contains('\n') || self.history_index.is_some();
+            let (tab, up, down, enter) = if focused {
+                ui.input_mut(|input| {
+                    (
+                        input.consume_key(Modifiers::NONE, Key::Tab),
+                        recall && input.consume_key(Modifiers::NONE, Key::ArrowUp),
+                        recall && input.consume_key(Modifiers::NONE, Key::ArrowDown),
+                        input.consume_key(Modifiers::NONE, Key::Enter),
+                    )
+                })
+            } else {
+                (false, false, false, false)
+            };
+
+            let response = ui.add(
+                TextEdit::multiline(&mut self.input)
+                    .id(id)
+                    .font(TextStyle::Monospace)
+                    .desired_rows(1)
+                    .desired_width(f32::INFINITY)
+                    .lock_focus(true),
+            );
+            if response.changed() {
+                self.history_index = None;
+            }
+
+            if tab {
+                self.complete(executor);
+            }
+            if up || down {
+                self.recall(up);
+            }
+            if tab || up || down {
+                move_cursor_to_end(ui.ctx(), id, &self.input);
+            }
+
+            if enter {
+                self.submit(executor);
+            }
+        });
+    }
+
+    fn submit(&mut self, executor: &LuaExecutor) {
+        let line = std::mem::take(&mut self.input);
+        if line.trim().is_empty() && self.pending.is_empty() {
+            return;
+        }
+        self.entries.push(ReplEntry::Input {
+            text: line.clone(),
+            continuation: !self.pending.is_empty(),
+        });
+        if !self.pending.is_empty() {
+            self.pending.push('\n');
+        }
+        self.pending.push_str(&line);
+        self.candidates.clear();
+        self.history_index = None;
+
+        let (output, results) = match executor.eval(&self.pending) {
+            ReplResult::Incomplete => return,
+            ReplResult::Done { output, results } => (output, Ok(results)),
+            ReplResult::Error { output, message } => (output, Err(message)),
+        };
+        if !output.is_empty() {
+            self.entries
+                .push(ReplEntry::Output(output.trim_end_matches('\n').to_string()));
+        }
+        match results {
+            Ok(results) => self
+                .entries
+                .extend(results.into_iter().map(ReplEntry::Result)),
+            Err(message) => self.entries.push(ReplEntry::Error(message)),
+        }
+
+        let chunk = std::mem::take(&mut self.pending);
+        if self.history.last() != Some(&chunk) {
+            self.history.push(chunk);
+        }
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+    }
+
+    /// Steps through earlier chunks with the arrow keys, keeping their line breaks.
+    fn recall(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        let index = match (self.history_index, older) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < self.history.len() => Some(index + 1),
+            (Some(_), false) => None,
+        };
+        self.history_index = index;
+        self.input = index
+            .map(|index| self.history[index].clone())
+            .unwrap_or_default();
+    }
+
+    /// Completes the identifier before the cursor from the live Lua state, following `.` and
+    /// `:` into tables.
+    fn complete(&mut self, executor: &LuaExecutor) {
+        let start = self
+            .input
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !(c.is_alphanumeric() || matches!(c, '_' | '.' | ':')))
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        let word = &self.input[start..];
+
+        let (path, prefix, methods) = match word.rfind(['.', ':']) {
+            Some(separator) => (
+                word[..separator].split(['.', ':']).collect::<Vec<_>>(),
+                &word[separator + 1..],
+                word[separator..].starts_with(':'),
+            ),
+            None => (Vec::new(), word, false),
+        };
+
+        let mut candidates = executor.completions(&path, prefix, methods);
+        let completed = match candidates.as_slice() {
+            [] => None,
+            [only] => Some(only.clone()),
+            [first, rest @ ..] => Some(rest.iter().fold(first.clone(), |common, candidate| {
+                common
+                    .chars()
+                    .zip(candidate.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a)
+                    .collect()
+            })),
+        };
+        if let Some(completed) = completed {
+            let keep = self.input.len() - prefix.len();
+            self.input.truncate(keep);
+            self.input.push_str(&completed);
+        }
+
+        if candidates.len() > 1 {
+            candidates.truncate(MAX_CANDIDATES);
+            self.candidates = candidates;
+        } else {
+            self.candidates.clear();
+        }
+    }
+}
+
+fn move_cursor_to_end(ctx: &egui::Context, id: egui::Id, text: &str) {
+    if let Some(mut state) = TextEdit::load_state(ctx, id) {
+        let end = CCursor::new(text.chars().count());
+        state.cursor.set_char_range(Some(CCursorRange::one(end)));
+        state.store(ctx, id);
+    }
+}
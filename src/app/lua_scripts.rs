@@ -0,0 +1,268 @@
+use crate::app::script_errors::ScriptErrors;
+use crate::app::script_limits::ScriptGuard;
+use mlua::{Lua, Result, Table};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const LUA_FOLDER: &str = "lua";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// A leading comment such as `-- depends: string_split, tprint` loads those scripts first.
+const DEPENDS_HINT: &str = "-- depends:";
+
+struct ScriptFile {
+    path: PathBuf,
+    name: String,
+    source: String,
+    modified: Option<SystemTime>,
+}
+
+impl ScriptFile {
+    fn read(path: PathBuf) -> Option<Self> {
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Failed to read Lua file {:?}: {}", path, e);
+                return None;
+            }
+        };
+        let stem = path.file_stem()?.to_str()?;
+        Some(Self {
+            name: script_name(stem).to_string(),
+            modified: modified_time(&path),
+            source,
+            path,
+        })
+    }
+
+    fn dependencies(&self) -> Vec<&str> {
+        self.source
+            .lines()
+            .map(str::trim)
+            .take_while(|line| line.is_empty() || line.starts_with("--"))
+            .filter_map(|line| line.strip_prefix(DEPENDS_HINT))
+            .flat_map(|names| names.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// Sort key: scripts with a numeric prefix (`10_colors.lua`) come first in numeric order,
+    /// then the rest alphabetically.
+    fn sort_key(&self) -> (u32, String) {
+        let file_name = self
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let digits: String = file_name.chars().take_while(char::is_ascii_digit).collect();
+        (digits.parse().unwrap_or(u32::MAX), file_name.to_string())
+    }
+}
+
+/// Loads the scripts in the `lua` folder in a deterministic order and reloads them when they
+/// change on disk.
+pub struct ScriptLoader {
+    folder: PathBuf,
+    modified: HashMap<PathBuf, Option<SystemTime>>,
+    last_poll: Instant,
+    reload_requested: Rc<Cell<bool>>,
+}
+
+impl ScriptLoader {
+    pub fn new(folder: impl Into<PathBuf>) -> Self {
+        Self {
+            folder: folder.into(),
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+            reload_requested: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Adds `ReloadScripts()`, which reloads every script once the running one has finished.
+    pub fn register(&self, lua: &Lua) -> Result<()> {
+        let reload_requested = self.reload_requested.clone();
+        lua.globals().set(
+            "ReloadScripts",
+            lua.create_function(move |_, ()| {
+                reload_requested.set(true);
+                Ok(())
+            })?,
+        )
+    }
+
+    /// Runs every script in load order and returns the names of the ones that were run.
+    pub fn load_all(
+        &mut self,
+        lua: &Lua,
+        guard: &ScriptGuard,
+        errors: &ScriptErrors,
+    ) -> Vec<String> {
+        let scripts = self.read_scripts(|_| true);
+        self.modified = scripts
+            .iter()
+            .map(|script| (script.path.clone(), script.modified))
+            .collect();
+
+        let known: HashSet<&str> = scripts.iter().map(|script| script.name.as_str()).collect();
+        for script in &scripts {
+            for dependency in script.dependencies() {
+                if !known.contains(dependency) {
+                    errors.report(
+                        &script.path.display().to_string(),
+                        &format!("depends on unknown script {:?}", dependency),
+                    );
+                }
+            }
+        }
+
+        let loaded = self.run_scripts(lua, guard, errors, scripts);
+        // A script calling ReloadScripts() while being loaded must not loop forever.
+        self.reload_requested.set(false);
+        loaded
+    }
+
+    /// Checks the folder for changed or new scripts about once a second and reloads them.
+    /// Returns the names of the scripts that were run.
+    pub fn poll(&mut self, lua: &Lua, guard: &ScriptGuard, errors: &ScriptErrors) -> Vec<String> {
+        if self.reload_requested.get() {
+            return self.load_all(lua, guard, errors);
+        }
+        if self.last_poll.elapsed() < POLL_INTERVAL || !self.folder.is_dir() {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let paths = self.script_paths();
+        self.modified.retain(|path, _| paths.contains(path));
+        let changed: HashSet<PathBuf> = paths
+            .into_iter()
+            .filter(|path| self.modified.get(path) != Some(&modified_time(path)))
+            .collect();
+        if changed.is_empty() {
+            return Vec::new();
+        }
+
+        let scripts = self.read_scripts(|path| changed.contains(path));
+        for script in &scripts {
+            self.modified.insert(script.path.clone(), script.modified);
+        }
+        self.run_scripts(lua, guard, errors, scripts)
+    }
+
+    fn run_scripts(
+        &self,
+        lua: &Lua,
+        guard: &ScriptGuard,
+        errors: &ScriptErrors,
+        scripts: Vec<ScriptFile>,
+    ) -> Vec<String> {
+        let (ordered, order_errors) = load_order(scripts);
+        for (path, message) in order_errors {
+            errors.report(&path.display().to_string(), &message);
+        }
+
+        let mut loaded = Vec::new();
+        for script in ordered {
+            let name = script.path.display().to_string();
+            let result = guard.run(lua, || {
+                // Let a later `require` pick up the new version instead of the cached one.
+                let package_loaded: Table<'_> = lua
+                    .globals()
+                    .get::<_, Table<'_>>("package")?
+                    .get("loaded")?;
+                package_loaded.set(script.name.as_str(), mlua::Value::Nil)?;
+                lua.load(&script.source).set_name(name.as_str()).exec()
+            });
+            match result {
+                Ok(()) => loaded.push(name),
+                Err(e) => errors.report(&name, &e.to_string()),
+            }
+        }
+        loaded
+    }
+
+    fn script_paths(&self) -> Vec<PathBuf> {
+        let entries = match fs::read_dir(&self.folder) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Failed to read Lua directory: {}", e);
+                return Vec::new();
+            }
+        };
+
+        entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry.path()),
+                Err(e) => {
+                    eprintln!("Failed to read path in Lua directory: {}", e);
+                    None
+                }
+            })
+            .filter(|path| {
+                path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("lua")
+            })
+            .collect()
+    }
+
+    fn read_scripts(&self, wanted: impl Fn(&Path) -> bool) -> Vec<ScriptFile> {
+        self.script_paths()
+            .into_iter()
+            .filter(|path| wanted(path))
+            .filter_map(ScriptFile::read)
+            .collect()
+    }
+}
+
+/// The name other scripts use in a dependency hint: the file stem without its numeric prefix,
+/// so `10_colors.lua` is `colors`.
+fn script_name(stem: &str) -> &str {
+    let rest = stem.trim_start_matches(|c: char| c.is_ascii_digit());
+    match rest.strip_prefix(['_', '-']) {
+        Some(name) if rest.len() < stem.len() && !name.is_empty() => name,
+        _ => stem,
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Sorts scripts by prefix and name, then moves each one after the scripts it depends on.
+///
+/// Dependencies outside `scripts` are treated as already loaded. Scripts caught in a cycle are
+/// still loaded, in sorted order, and reported.
+fn load_order(mut scripts: Vec<ScriptFile>) -> (Vec<ScriptFile>, Vec<(PathBuf, String)>) {
+    scripts.sort_by_key(ScriptFile::sort_key);
+    let present: HashSet<String> = scripts.iter().map(|script| script.name.clone()).collect();
+    let mut placed = HashSet::new();
+    let mut ordered = Vec::new();
+
+    while let Some(index) = scripts.iter().position(|script| {
+        script
+            .dependencies()
+            .iter()
+            .all(|dependency| !present.contains(*dependency) || placed.contains(*dependency))
+    }) {
+        let script = scripts.remove(index);
+        placed.insert(script.name.clone());
+        ordered.push(script);
+    }
+
+    let errors = scripts
+        .iter()
+        .map(|script| {
+            (
+                script.path.clone(),
+                "circular script dependency".to_string(),
+            )
+        })
+        .collect();
+    ordered.extend(scripts);
+    (ordered, errors)
+}
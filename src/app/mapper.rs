@@ -0,0 +1,1376 @@
+use crate::app::session_log::file_safe;
+use crate::app::settings::MapperSettings;
+use egui::{pos2, vec2, ComboBox, DragValue, Grid, Pos2, Rect, Sense, Stroke, Ui, Vec2, Window};
+use mlua::{Lua, Result as LuaResult};
+use regex::Regex;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAP_FOLDER: &str = "maps";
+const ROOM_SIZE: f32 = 10.0; // At zoom 1
+const ROOM_SPACING: f32 = 24.0;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+const ZOOM_STEP: f32 = 1.25;
+const MOVE_TIMEOUT: Duration = Duration::from_secs(10); // A move with no room after this failed
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_REROUTES: u32 = 5; // Gives up a walk that keeps being turned back
+
+/// Map coordinates: east is +x, north is +y and up is +z.
+type Position = (i32, i32, i32);
+
+/// The short name, long name and map offset of each direction.
+const DIRECTIONS: [(&str, &str, Position); 12] = [
+    ("n", "north", (0, 1, 0)),
+    ("ne", "northeast", (1, 1, 0)),
+    ("e", "east", (1, 0, 0)),
+    ("se", "southeast", (1, -1, 0)),
+    ("s", "south", (0, -1, 0)),
+    ("sw", "southwest", (-1, -1, 0)),
+    ("w", "west", (-1, 0, 0)),
+    ("nw", "northwest", (-1, 1, 0)),
+    ("u", "up", (0, 0, 1)),
+    ("d", "down", (0, 0, -1)),
+    ("in", "in", (0, 0, 0)),
+    ("out", "out", (0, 0, 0)),
+];
+
+/// The short form of a direction, like `n` for `North`, or `None` if it isn't one.
+pub fn normalize_direction(word: &str) -> Option<&'static str> {
+    let word = word.trim().to_lowercase();
+    DIRECTIONS
+        .iter()
+        .find(|(short, long, _)| word == *short || word == *long)
+        .map(|(short, _, _)| *short)
+}
+
+/// How far a step in `direction` moves on the map, if it moves somewhere that can be drawn.
+fn offset(direction: &str) -> Option<Position> {
+    DIRECTIONS
+        .iter()
+        .find(|(short, _, _)| *short == direction)
+        .map(|(_, _, offset)| *offset)
+        .filter(|offset| *offset != (0, 0, 0))
+}
+
+#[derive(Clone)]
+pub struct Exit {
+    pub to: Option<String>, // The room it leads to, once known
+    pub weight: u32,        // What taking it costs when finding paths
+    pub command: String,    // Sent instead of the direction, like `unlock door;open door;n`
+    pub locked: bool,       // Only walked through when it has a command
+    pub avoid: bool,        // Never walked through
+}
+
+impl Default for Exit {
+    fn default() -> Self {
+        Self {
+            to: None,
+            weight: 1,
+            command: String::new(),
+            locked: false,
+            avoid: false,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Room {
+    pub name: String,
+    pub area: String,
+    pub exits: BTreeMap<String, Exit>, // By short direction, or the MUD's own exit name
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub avoid: bool, // Paths don't pass through it, though they may end there
+}
+
+impl Room {
+    fn position(&self) -> Position {
+        (self.x, self.y, self.z)
+    }
+}
+
+/// Every room mapped in one world, by the MUD's room number or, for rooms recognised from
+/// the text, an id of our own.
+#[derive(Default)]
+pub struct RoomGraph {
+    pub rooms: BTreeMap<String, Room>,
+    next_id: u64,
+}
+
+impl RoomGraph {
+    fn new_id(&mut self) -> String {
+        loop {
+            self.next_id += 1;
+            let id = format!("m{}", self.next_id);
+            if !self.rooms.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// The cheapest way from one room to another. Avoided rooms and exits, locked exits with
+    /// no command and the `blocked` exits (by room and direction) are left out.
+    pub fn find_path(
+        &self,
+        from: &str,
+        to: &str,
+        blocked: &BTreeSet<(String, String)>,
+    ) -> Option<Path> {
+        let (from, _) = self.rooms.get_key_value(from)?;
+        let (to, _) = self.rooms.get_key_value(to)?;
+        let search = self.search(from, Some(to), blocked);
+        let cost = *search.costs.get(to)?;
+        let mut steps = VecDeque::new();
+        let mut at = to;
+        while at != from {
+            let (previous, direction) = search.came_from[at];
+            let exit = &self.rooms[previous].exits[direction];
+            steps.push_front(Step {
+                command: if exit.command.is_empty() {
+                    direction.clone()
+                } else {
+                    exit.command.clone()
+                },
+                direction: direction.clone(),
+                to: at.clone(),
+            });
+            at = previous;
+        }
+        Some(Path { steps, cost })
+    }
+
+    /// Dijkstra over the exit weights from `from`, stopping once `to` is reached.
+    fn search<'a>(
+        &'a self,
+        from: &'a String,
+        to: Option<&String>,
+        blocked: &BTreeSet<(String, String)>,
+    ) -> Search<'a> {
+        let mut search = Search {
+            costs: BTreeMap::from([(from, 0)]),
+            came_from: BTreeMap::new(),
+        };
+        let mut queue = BinaryHeap::from([Reverse((0, from))]);
+        while let Some(Reverse((cost, id))) = queue.pop() {
+            if Some(id) == to {
+                break;
+            }
+            let room = &self.rooms[id];
+            if cost > search.costs[id] || (id != from && room.avoid) {
+                continue;
+            }
+            for (direction, exit) in &room.exits {
+                let Some((next, _)) = exit.to.as_ref().and_then(|to| self.rooms.get_key_value(to))
+                else {
+                    continue;
+                };
+                if exit.avoid
+                    || (exit.locked && exit.command.is_empty())
+                    || blocked.contains(&(id.clone(), direction.clone()))
+                {
+                    continue;
+                }
+                let next_cost = cost.saturating_add(exit.weight);
+                if search
+                    .costs
+                    .get(next)
+                    .is_none_or(|&known| next_cost < known)
+                {
+                    search.costs.insert(next, next_cost);
+                    search.came_from.insert(next, (id, direction));
+                    queue.push(Reverse((next_cost, next)));
+                }
+            }
+        }
+        search
+    }
+
+    /// The room `query` names: a room id, else the nearest room to `from` with that name, or
+    /// failing that with a name containing it.
+    fn resolve(&self, query: &str, from: Option<&str>) -> Option<String> {
+        if self.rooms.contains_key(query) {
+            return Some(query.to_string());
+        }
+        let query = query.to_lowercase();
+        let exact: Vec<&String> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| room.name.to_lowercase() == query)
+            .map(|(id, _)| id)
+            .collect();
+        let matches = if exact.is_empty() {
+            self.rooms
+                .iter()
+                .filter(|(_, room)| room.name.to_lowercase().contains(&query))
+                .map(|(id, _)| id)
+                .collect()
+        } else {
+            exact
+        };
+        let costs = from
+            .and_then(|from| self.rooms.get_key_value(from))
+            .map(|(from, _)| self.search(from, None, &BTreeSet::new()).costs)
+            .unwrap_or_default();
+        matches
+            .into_iter()
+            .min_by_key(|id| costs.get(id).copied().unwrap_or(u32::MAX))
+            .cloned()
+    }
+
+    fn room_at(&self, position: Position, name: &str) -> Option<String> {
+        self.rooms
+            .iter()
+            .find(|(_, room)| room.position() == position && room.name == name)
+            .map(|(id, _)| id.clone())
+    }
+}
+
+/// One move of a path: what to send and the room it should lead to.
+#[derive(Clone)]
+pub struct Step {
+    pub direction: String,
+    pub command: String,
+    pub to: String,
+}
+
+impl Step {
+    /// The commands to send for this step: an exit's command is split at `;` here, so walks
+    /// don't depend on the command separator setting.
+    fn commands(&self) -> Vec<String> {
+        self.command
+            .split(';')
+            .map(str::trim)
+            .filter(|command| !command.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+pub struct Path {
+    pub steps: VecDeque<Step>,
+    pub cost: u32,
+}
+
+/// The cheapest known cost of each room reached, and the room and exit it was reached by.
+struct Search<'a> {
+    costs: BTreeMap<&'a String, u32>,
+    came_from: BTreeMap<&'a String, (&'a String, &'a String)>,
+}
+
+/// The last step sent on a walk, waiting for its room.
+struct SentStep {
+    from: String,
+    step: Step,
+    at: Instant,
+}
+
+/// A walk to a room, one step at a time so it can re-route when a step goes wrong.
+struct Walk {
+    target: String,
+    steps: VecDeque<Step>,
+    sent: Option<SentStep>,
+    blocked: BTreeSet<(String, String)>, // Exits that turned us back on this walk
+    reroutes: u32,
+}
+
+/// The map and where the character is, shared with the `mapper` Lua table.
+#[derive(Default)]
+pub struct MapState {
+    pub graph: RoomGraph,
+    pub current: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct SharedMap {
+    state: Arc<Mutex<MapState>>,
+    walks: Arc<Mutex<Vec<String>>>, // Rooms scripts asked to walk to
+}
+
+/// `mapper.find_path(from, to)` returns the commands that walk from one room to another and
+/// the path's total weight, or nil if there is no way; `from` may be nil for the current room.
+/// `mapper.goto(room)` walks there and returns whether a path was found.
+pub fn register_functions(lua: &Lua, map: SharedMap) -> LuaResult<()> {
+    let mapper = lua.create_table()?;
+
+    let find_map = map.clone();
+    mapper.set(
+        "find_path",
+        lua.create_function(move |_, (from, to): (Option<String>, String)| {
+            let state = find_map.state.lock().unwrap();
+            let Some(from) = from.or_else(|| state.current.clone()) else {
+                return Ok((None, None));
+            };
+            let Some(path) = state.graph.find_path(&from, &to, &BTreeSet::new()) else {
+                return Ok((None, None));
+            };
+            let commands: Vec<String> = path.steps.iter().flat_map(Step::commands).collect();
+            Ok((Some(commands), Some(path.cost)))
+        })?,
+    )?;
+
+    mapper.set(
+        "goto",
+        lua.create_function(move |_, to: String| {
+            let state = map.state.lock().unwrap();
+            let found = state
+                .current
+                .as_ref()
+                .is_some_and(|from| state.graph.find_path(from, &to, &BTreeSet::new()).is_some());
+            if found {
+                map.walks.lock().unwrap().push(to);
+            }
+            Ok(found)
+        })?,
+    )?;
+
+    lua.globals().set("mapper", mapper)
+}
+
+/// The compiled mapper patterns, kept until the settings change. Empty patterns are off.
+struct Patterns {
+    sources: [String; 3],
+    regexes: PatternSet,
+}
+
+#[derive(Clone)]
+struct PatternSet {
+    room_name: Option<Regex>,
+    exits: Option<Regex>,
+    blocked: Option<Regex>,
+}
+
+impl MapState {
+    /// Records the room the character is now in, reached from the current room by `moved`.
+    fn enter_room(
+        &mut self,
+        id: String,
+        name: String,
+        area: String,
+        exits: BTreeMap<String, Exit>,
+        position: Option<Position>,
+        moved: Option<String>,
+    ) {
+        let previous = self.current.take();
+        let position = position
+            .or_else(|| self.graph.rooms.get(&id).map(Room::position))
+            .unwrap_or_else(|| self.place(previous.as_deref(), moved.as_deref(), &exits));
+
+        let room = self.graph.rooms.entry(id.clone()).or_default();
+        room.name = name;
+        room.area = area;
+        room.exits
+            .retain(|direction, _| exits.contains_key(direction));
+        for (direction, exit) in exits {
+            let known = room.exits.entry(direction).or_default();
+            if exit.to.is_some() {
+                known.to = exit.to;
+            }
+        }
+        (room.x, room.y, room.z) = position;
+
+        if let (Some(previous), Some(direction)) = (&previous, moved) {
+            if let Some(room) = self
+                .graph
+                .rooms
+                .get_mut(previous)
+                .filter(|_| *previous != id)
+            {
+                let exit = room.exits.entry(direction).or_default();
+                exit.to.get_or_insert(id.clone());
+            }
+        }
+        self.current = Some(id);
+    }
+
+    /// Where to put a new room with no coordinates of its own: a step from the room it was
+    /// reached from, or from a known room it leads to, or else beside the previous room.
+    fn place(
+        &self,
+        previous: Option<&str>,
+        moved: Option<&str>,
+        exits: &BTreeMap<String, Exit>,
+    ) -> Position {
+        let previous = previous.and_then(|id| self.graph.rooms.get(id));
+        if let (Some(room), Some((dx, dy, dz))) = (previous, moved.and_then(offset)) {
+            let (x, y, z) = room.position();
+            return (x + dx, y + dy, z + dz);
+        }
+        for (direction, exit) in exits {
+            let neighbour = exit.to.as_ref().and_then(|to| self.graph.rooms.get(to));
+            if let (Some(room), Some((dx, dy, dz))) = (neighbour, offset(direction)) {
+                let (x, y, z) = room.position();
+                return (x - dx, y - dy, z - dz);
+            }
+        }
+        let (x, y, z) = previous.map_or((0, 0, 0), Room::position);
+        let taken: BTreeSet<Position> = self.graph.rooms.values().map(Room::position).collect();
+        (1..)
+            .map(|step| (x + step, y, z))
+            .find(|position| !taken.contains(position))
+            .unwrap_or((x, y, z))
+    }
+
+    /// A room recognised from the text. Without room numbers it is matched by where the move
+    /// leads, or by name and position, and is otherwise new.
+    fn text_room(&mut self, name: String, exits_text: &str, moved: Option<String>) {
+        let exits: BTreeMap<String, Exit> = exits_text
+            .split(|c: char| !c.is_alphabetic())
+            .filter_map(normalize_direction)
+            .map(|direction| (direction.to_string(), Exit::default()))
+            .collect();
+        let current = self
+            .current
+            .as_ref()
+            .and_then(|id| Some((id.clone(), self.graph.rooms.get(id)?.clone())));
+        let id = match (&current, &moved) {
+            (Some((_, room)), Some(direction)) => room
+                .exits
+                .get(direction)
+                .and_then(|exit| exit.to.clone())
+                .filter(|to| self.graph.rooms.get(to).is_some_and(|to| to.name == name))
+                .or_else(|| {
+                    let (dx, dy, dz) = offset(direction)?;
+                    let (x, y, z) = room.position();
+                    self.graph.room_at((x + dx, y + dy, z + dz), &name)
+                }),
+            (Some((id, room)), None) if room.name == name => Some(id.clone()),
+            _ => None,
+        }
+        .unwrap_or_else(|| self.graph.new_id());
+        let area = current.map(|(_, room)| room.area).unwrap_or_default();
+        self.enter_room(id, name, area, exits, None, moved);
+    }
+}
+
+/// Builds a map of each world from GMCP `Room.Info`, or from room titles and exit lines when
+/// the MUD sends no GMCP, draws it in the Map pane and walks to rooms on it. Maps are saved
+/// as SQLite databases under `maps/`.
+pub struct Mapper {
+    world: String,
+    pub shared: SharedMap,
+    pending_moves: VecDeque<(String, Instant)>, // Directions sent, waiting for their rooms
+    pending_name: Option<String>,               // A possible room title seen in the text
+    gmcp_seen: bool,                            // Room.Info arrived, so the text is ignored
+    patterns: Option<Patterns>,
+    walk: Option<Walk>,
+    notes: Vec<String>,          // Walk progress for the output
+    area_filter: Option<String>, // None follows the current room
+    editing: Option<String>,     // The room open in the room editor
+    zoom: f32,
+    pan: Vec2,
+    dirty: bool,
+    last_save: Instant,
+}
+
+impl Default for Mapper {
+    fn default() -> Self {
+        Self {
+            world: String::new(),
+            shared: SharedMap::default(),
+            pending_moves: VecDeque::new(),
+            pending_name: None,
+            gmcp_seen: false,
+            patterns: None,
+            walk: None,
+            notes: Vec::new(),
+            area_filter: None,
+            editing: None,
+            zoom: 1.0,
+            pan: Vec2::ZERO,
+            dirty: false,
+            last_save: Instant::now(),
+        }
+    }
+}
+
+impl Mapper {
+    fn state(&self) -> std::sync::MutexGuard<'_, MapState> {
+        self.shared.state.lock().unwrap()
+    }
+
+    /// Saves the map of the world being left and loads the map of `world`.
+    pub fn switch_world(&mut self, world: &str) {
+        self.walk = None;
+        self.pending_moves.clear();
+        self.gmcp_seen = false;
+        if world == self.world && !self.world.is_empty() {
+            self.state().current = None;
+            return;
+        }
+        self.save();
+        *self.state() = MapState {
+            graph: load(world),
+            current: None,
+        };
+        self.world = world.to_string();
+        self.area_filter = None;
+        self.editing = None;
+        self.pan = Vec2::ZERO;
+    }
+
+    /// Writes the map if it changed since it was last saved.
+    pub fn save(&mut self) {
+        if !self.dirty || self.world.is_empty() {
+            return;
+        }
+        if let Err(e) = store(&self.world, &self.state().graph) {
+            eprintln!("{}", e);
+        }
+        self.dirty = false;
+        self.last_save = Instant::now();
+    }
+
+    /// Saves now and then while rooms are being mapped.
+    pub fn autosave(&mut self) {
+        if self.last_save.elapsed() >= AUTOSAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    /// Handles `mapper goto <room>` and `mapper stop`. Returns false for anything else, which
+    /// is sent to the world as usual.
+    pub fn command(&mut self, command: &str) -> bool {
+        let mut words = command.trim().splitn(3, char::is_whitespace);
+        if !words
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case("mapper"))
+        {
+            return false;
+        }
+        match (words.next().map(str::to_lowercase).as_deref(), words.next()) {
+            (Some("goto"), Some(room)) if !room.trim().is_empty() => self.goto(room.trim()),
+            (Some("stop"), None) => self.stop("Walk stopped."),
+            _ => self
+                .notes
+                .push("Usage: mapper goto <room number or name>, mapper stop".to_string()),
+        }
+        true
+    }
+
+    /// Starts walking to the room `query` names.
+    pub fn goto(&mut self, query: &str) {
+        let state = self.state();
+        let Some(from) = state.current.clone() else {
+            drop(state);
+            self.notes
+                .push("The mapper doesn't know where you are yet.".to_string());
+            return;
+        };
+        let found = state.graph.resolve(query, Some(&from)).and_then(|target| {
+            let path = state.graph.find_path(&from, &target, &BTreeSet::new())?;
+            Some((
+                target.clone(),
+                state.graph.rooms[&target].name.clone(),
+                path,
+            ))
+        });
+        drop(state);
+        let note = match found {
+            Some((target, name, path)) => {
+                let note = format!("Walking to {} ({} steps).", name, path.steps.len());
+                self.walk = Some(Walk {
+                    target,
+                    steps: path.steps,
+                    sent: None,
+                    blocked: BTreeSet::new(),
+                    reroutes: 0,
+                });
+                note
+            }
+            None => format!("No path to {}.", query),
+        };
+        self.notes.push(note);
+    }
+
+    fn stop(&mut self, note: &str) {
+        if self.walk.take().is_some() {
+            self.notes.push(note.to_string());
+        }
+    }
+
+    /// Finds a new way to the walk's target from the current room, leaving out the exits
+    /// that turned us back, or gives up.
+    fn reroute(&mut self, reason: &str) {
+        let state = self.shared.state.lock().unwrap();
+        let Some(walk) = &mut self.walk else {
+            return;
+        };
+        walk.reroutes += 1;
+        walk.sent = None;
+        let path = state
+            .current
+            .as_ref()
+            .filter(|_| walk.reroutes <= MAX_REROUTES)
+            .and_then(|from| state.graph.find_path(from, &walk.target, &walk.blocked));
+        drop(state);
+        match path {
+            Some(path) => {
+                walk.steps = path.steps;
+                self.notes.push(format!("{}, re-routing.", reason));
+            }
+            None => self.stop(&format!("{}, walk cancelled.", reason)),
+        }
+    }
+
+    /// The commands of the next step of the walk, once the last one has arrived. Call once a
+    /// frame.
+    pub fn walk_commands(&mut self) -> Vec<String> {
+        let requested: Vec<String> = std::mem::take(&mut *self.shared.walks.lock().unwrap());
+        for room in requested {
+            self.goto(&room);
+        }
+        let Some(walk) = &mut self.walk else {
+            return Vec::new();
+        };
+        let current = self.shared.state.lock().unwrap().current.clone();
+        if let Some(sent) = &walk.sent {
+            if current.as_ref() == Some(&sent.step.to) {
+                walk.sent = None;
+            } else if current.as_ref() != Some(&sent.from) {
+                self.reroute("Ended up somewhere unexpected");
+                return Vec::new();
+            } else if sent.at.elapsed() >= MOVE_TIMEOUT {
+                walk.blocked
+                    .insert((sent.from.clone(), sent.step.direction.clone()));
+                self.reroute("No room after moving");
+                return Vec::new();
+            } else {
+                return Vec::new();
+            }
+        }
+
+        let Some(from) = current else {
+            self.stop("Lost track of where you are, walk cancelled.");
+            return Vec::new();
+        };
+        if from == walk.target {
+            self.stop("You have arrived.");
+            return Vec::new();
+        }
+        let Some(step) = walk.steps.pop_front() else {
+            self.reroute("Not there yet");
+            return Vec::new();
+        };
+        let commands = step.commands();
+        walk.sent = Some(SentStep {
+            from,
+            step,
+            at: Instant::now(),
+        });
+        commands
+    }
+
+    /// Returns the walk messages for the output since the last call.
+    pub fn take_notes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notes)
+    }
+
+    /// Notes a direction sent to the world, so the room that follows can be placed and linked.
+    pub fn command_sent(&mut self, command: &str) {
+        if let Some(direction) = normalize_direction(command) {
+            self.pending_moves
+                .push_back((direction.to_string(), Instant::now()));
+        }
+    }
+
+    /// Handles a GMCP message. Only `Room.Info` is used.
+    pub fn gmcp(&mut self, package: &str, data: &str, settings: &MapperSettings) {
+        if !settings.enabled || !package.eq_ignore_ascii_case("Room.Info") {
+            return;
+        }
+        let info: Value = match serde_json::from_str(data) {
+            Ok(info) => info,
+            Err(e) => {
+                eprintln!("Invalid GMCP Room.Info: {}", e);
+                return;
+            }
+        };
+        let Some(id) = room_id(&info["num"]) else {
+            return;
+        };
+        self.gmcp_seen = true;
+        let exits = info["exits"]
+            .as_object()
+            .map(|exits| {
+                exits
+                    .iter()
+                    .map(|(direction, to)| {
+                        let direction = normalize_direction(direction)
+                            .map_or_else(|| direction.to_lowercase(), str::to_string);
+                        (
+                            direction,
+                            Exit {
+                                to: room_id(to),
+                                ..Exit::default()
+                            },
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let moved = if self.state().current.as_ref() == Some(&id) {
+            None
+        } else {
+            self.take_move()
+        };
+        self.state().enter_room(
+            id,
+            info["name"].as_str().unwrap_or_default().trim().to_string(),
+            info["area"].as_str().unwrap_or_default().trim().to_string(),
+            exits,
+            coordinates(&info),
+            moved,
+        );
+        self.dirty = true;
+    }
+
+    /// Watches the text for a walk being blocked and, when the MUD sends no GMCP, for room
+    /// titles and exit lines.
+    pub fn line_received(&mut self, line: &str, settings: &MapperSettings) {
+        if !settings.enabled {
+            return;
+        }
+        let patterns = self.patterns(settings);
+        let walking = self.walk.as_ref().is_some_and(|walk| walk.sent.is_some());
+        if walking
+            && patterns
+                .blocked
+                .is_some_and(|blocked| blocked.is_match(line))
+        {
+            if let Some(sent) = self.walk.as_mut().and_then(|walk| walk.sent.take()) {
+                let walk = self.walk.as_mut().unwrap();
+                walk.blocked.insert((sent.from, sent.step.direction));
+            }
+            self.reroute("Blocked");
+        }
+
+        if !settings.regex_fallback || self.gmcp_seen {
+            return;
+        }
+        let (Some(room_name), Some(exits)) = (patterns.room_name, patterns.exits) else {
+            return;
+        };
+        if let Some(captures) = exits.captures(line) {
+            if let Some(name) = self.pending_name.take() {
+                let exits = captures.get(1).map_or("", |m| m.as_str());
+                let moved = self.take_move();
+                self.state().text_room(name, exits, moved);
+                self.dirty = true;
+            }
+        } else if let Some(captures) = room_name.captures(line) {
+            let name = captures
+                .get(1)
+                .or(captures.get(0))
+                .map_or("", |m| m.as_str());
+            self.pending_name = Some(name.trim().to_string());
+        }
+    }
+
+    fn patterns(&mut self, settings: &MapperSettings) -> PatternSet {
+        let sources = [
+            settings.room_name_pattern.clone(),
+            settings.exits_pattern.clone(),
+            settings.blocked_pattern.clone(),
+        ];
+        if self.patterns.as_ref().map(|p| &p.sources) != Some(&sources) {
+            let compile = |pattern: &str| {
+                (!pattern.is_empty())
+                    .then(|| Regex::new(pattern).ok())
+                    .flatten()
+            };
+            let regexes = PatternSet {
+                room_name: compile(&sources[0]),
+                exits: compile(&sources[1]),
+                blocked: compile(&sources[2]),
+            };
+            self.patterns = Some(Patterns { sources, regexes });
+        }
+        self.patterns.as_ref().unwrap().regexes.clone()
+    }
+
+    fn take_move(&mut self) -> Option<String> {
+        while let Some((direction, sent)) = self.pending_moves.pop_front() {
+            if sent.elapsed() < MOVE_TIMEOUT {
+                return Some(direction);
+            }
+        }
+        None
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        let shared = self.shared.clone();
+        let mut state = shared.state.lock().unwrap();
+        if state.graph.rooms.is_empty() {
+            ui.weak("No map data.");
+            return;
+        }
+        let current = state
+            .current
+            .as_ref()
+            .and_then(|id| state.graph.rooms.get(id));
+        let area = self
+            .area_filter
+            .clone()
+            .or_else(|| current.map(|room| room.area.clone()))
+            .unwrap_or_else(|| state.graph.rooms.values().next().unwrap().area.clone());
+        let centre = current
+            .filter(|room| room.area == area)
+            .or_else(|| state.graph.rooms.values().find(|room| room.area == area))
+            .map_or((0, 0, 0), Room::position);
+
+        ui.horizontal(|ui| {
+            let areas: BTreeSet<&str> = state
+                .graph
+                .rooms
+                .values()
+                .map(|room| room.area.as_str())
+                .collect();
+            ComboBox::from_id_source("map_area")
+                .selected_text(area_label(&area))
+                .show_ui(ui, |ui| {
+                    if ui
+                        .selectable_label(self.area_filter.is_none(), "Current area")
+                        .clicked()
+                    {
+                        self.area_filter = None;
+                    }
+                    for name in areas {
+                        let selected = self.area_filter.as_deref() == Some(name);
+                        if ui.selectable_label(selected, area_label(name)).clicked() {
+                            self.area_filter = Some(name.to_string());
+                        }
+                    }
+                });
+            if ui.button("−").on_hover_text("Zoom out").clicked() {
+                self.zoom = (self.zoom / ZOOM_STEP).max(MIN_ZOOM);
+            }
+            if ui.button("+").on_hover_text("Zoom in").clicked() {
+                self.zoom = (self.zoom * ZOOM_STEP).min(MAX_ZOOM);
+            }
+            if ui
+                .button("Center")
+                .on_hover_text("Center the map on the current room")
+                .clicked()
+            {
+                self.pan = Vec2::ZERO;
+            }
+            ui.weak(format!("Level {}", centre.2));
+            if let Some(walk) = &self.walk {
+                let name = state
+                    .graph
+                    .rooms
+                    .get(&walk.target)
+                    .map_or("", |room| room.name.as_str());
+                ui.weak(format!("Walking to {}", name));
+            }
+        });
+
+        let (response, painter) = ui.allocate_painter(ui.available_size(), Sense::click_and_drag());
+        if response.dragged() {
+            self.pan += response.drag_delta();
+        }
+        if response.hovered() {
+            let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+            if scroll != 0.0 {
+                self.zoom = (self.zoom * (1.0 + scroll * 0.002)).clamp(MIN_ZOOM, MAX_ZOOM);
+            }
+        }
+
+        let spacing = ROOM_SPACING * self.zoom;
+        let size = Vec2::splat(ROOM_SIZE * self.zoom);
+        let origin = response.rect.center() + self.pan;
+        let to_screen = |room: &Room| -> Pos2 {
+            origin
+                + vec2(
+                    (room.x - centre.0) as f32 * spacing,
+                    (centre.1 - room.y) as f32 * spacing,
+                )
+        };
+        let visible: Vec<(&String, &Room)> = state
+            .graph
+            .rooms
+            .iter()
+            .filter(|(_, room)| room.area == area && room.z == centre.2)
+            .collect();
+
+        let visuals = ui.visuals();
+        let line = Stroke::new(1.0, visuals.weak_text_color());
+        for (_, room) in &visible {
+            let from = to_screen(room);
+            for (direction, exit) in &room.exits {
+                let target = exit.to.as_ref().and_then(|to| state.graph.rooms.get(to));
+                match (target, offset(direction)) {
+                    (Some(target), _) if target.area == area && target.z == room.z => {
+                        painter.line_segment([from, to_screen(target)], line);
+                    }
+                    (_, Some((dx, dy, 0))) => {
+                        let stub = vec2(dx as f32, -dy as f32) * spacing * 0.4;
+                        painter.line_segment([from, from + stub], line);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut hovered = None;
+        let pointer = response.hover_pos();
+        for (id, room) in &visible {
+            let rect = Rect::from_center_size(to_screen(room), size);
+            let fill = if state.current.as_ref() == Some(*id) {
+                visuals.selection.bg_fill
+            } else if self.walk.as_ref().is_some_and(|walk| walk.target == **id) {
+                visuals.warn_fg_color
+            } else {
+                visuals.widgets.inactive.bg_fill
+            };
+            painter.rect(rect, 1.0, fill, visuals.widgets.noninteractive.fg_stroke);
+            if room.exits.contains_key("u") || room.exits.contains_key("d") {
+                let mark = rect.shrink(rect.width() * 0.3);
+                painter.line_segment(
+                    [pos2(mark.center().x, mark.top()), mark.center_bottom()],
+                    line,
+                );
+            }
+            if pointer.is_some_and(|pointer| rect.expand(2.0).contains(pointer)) {
+                hovered = Some(((*id).clone(), *room));
+            }
+        }
+
+        let mut walk_to = None;
+        if let Some((id, room)) = hovered {
+            if response.clicked() {
+                walk_to = Some(id.clone());
+            } else if response.secondary_clicked() {
+                self.editing = Some(id.clone());
+            }
+            response.on_hover_ui_at_pointer(|ui| {
+                ui.strong(&room.name);
+                ui.weak(format!("Room {}", id));
+                let exits: Vec<&str> = room.exits.keys().map(String::as_str).collect();
+                ui.label(format!("Exits: {}", exits.join(", ")));
+                ui.weak("Click to walk here, right-click to edit");
+            });
+        }
+
+        if self.room_editor(ui.ctx(), &mut state.graph) {
+            self.dirty = true;
+        }
+        drop(state);
+        if let Some(id) = walk_to {
+            self.goto(&id);
+        }
+    }
+
+    /// The window for a room's path finding flags and its exits' weights, commands and flags.
+    /// Returns whether anything changed.
+    fn room_editor(&mut self, ctx: &egui::Context, graph: &mut RoomGraph) -> bool {
+        let Some(room) = self.editing.as_ref().and_then(|id| graph.rooms.get_mut(id)) else {
+            self.editing = None;
+            return false;
+        };
+        let mut open = true;
+        let mut changed = false;
+        Window::new("Room")
+            .id(egui::Id::new("map_room_editor"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.strong(&room.name);
+                ui.weak(format!(
+                    "Room {}",
+                    self.editing.as_deref().unwrap_or_default()
+                ));
+                changed |= ui
+                    .checkbox(&mut room.avoid, "Avoid when finding paths")
+                    .changed();
+                ui.separator();
+                Grid::new("map_room_exits")
+                    .num_columns(5)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Exit");
+                        ui.strong("Weight");
+                        ui.strong("Command");
+                        ui.strong("Locked");
+                        ui.strong("Avoid");
+                        ui.end_row();
+                        for (direction, exit) in &mut room.exits {
+                            ui.label(direction.as_str());
+                            changed |= ui
+                                .add(DragValue::new(&mut exit.weight).range(0..=1000))
+                                .changed();
+                            changed |= ui
+                                .add(
+                                    egui::TextEdit::singleline(&mut exit.command)
+                                        .hint_text(direction.as_str()),
+                                )
+                                .on_hover_text(
+                                    "Sent instead of the direction, like \
+                                     `unlock door;open door;north`",
+                                )
+                                .changed();
+                            changed |= ui.checkbox(&mut exit.locked, "").changed();
+                            changed |= ui.checkbox(&mut exit.avoid, "").changed();
+                            ui.end_row();
+                        }
+                    });
+            });
+        if !open {
+            self.editing = None;
+        }
+        changed
+    }
+}
+
+fn area_label(area: &str) -> &str {
+    if area.is_empty() {
+        "(no area)"
+    } else {
+        area
+    }
+}
+
+/// A room number, sent as a JSON number or string.
+fn room_id(value: &Value) -> Option<String> {
+    match value {
+        Value::Number(number) => Some(number.to_string()),
+        Value::String(text) if !text.trim().is_empty() => Some(text.trim().to_string()),
+        _ => None,
+    }
+}
+
+/// Coordinates from the IRE `coords` string, `"area,x,y,z"`, or a `coord` object.
+fn coordinates(info: &Value) -> Option<Position> {
+    if let Some(coords) = info["coords"].as_str() {
+        let parts: Option<Vec<i32>> = coords.split(',').map(|n| n.trim().parse().ok()).collect();
+        if let Some([_, x, y, z]) = parts.as_deref() {
+            return Some((*x, *y, *z));
+        }
+    }
+    let coord = &info["coord"];
+    let number = |key: &str| coord[key].as_i64().map(|n| n as i32);
+    Some((number("x")?, number("y")?, number("z").unwrap_or(0)))
+}
+
+fn map_path(world: &str) -> PathBuf {
+    PathBuf::from(MAP_FOLDER).join(format!("{}.db", file_safe(world)))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load(world: &str) -> RoomGraph {
+    let path = map_path(world);
+    if !path.exists() {
+        return RoomGraph::default();
+    }
+    read_graph(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to read the map {}: {}", path.display(), e);
+        RoomGraph::default()
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn load(_world: &str) -> RoomGraph {
+    RoomGraph::default()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn store(world: &str, graph: &RoomGraph) -> Result<(), String> {
+    let path = map_path(world);
+    fs::create_dir_all(MAP_FOLDER)
+        .map_err(|e| e.to_string())
+        .and_then(|_| write_graph(&path, graph).map_err(|e| e.to_string()))
+        .map_err(|e| format!("Failed to save the map to {}: {}", path.display(), e))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn store(_world: &str, _graph: &RoomGraph) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open_database(path: &std::path::Path) -> rusqlite::Result<rusqlite::Connection> {
+    let connection = rusqlite::Connection::open(path)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS rooms (
+             id TEXT PRIMARY KEY,
+             name TEXT NOT NULL,
+             area TEXT NOT NULL,
+             x INTEGER NOT NULL,
+             y INTEGER NOT NULL,
+             z INTEGER NOT NULL,
+             avoid INTEGER NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS exits (
+             room TEXT NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+             direction TEXT NOT NULL,
+             destination TEXT,
+             weight INTEGER NOT NULL,
+             command TEXT NOT NULL,
+             locked INTEGER NOT NULL,
+             avoid INTEGER NOT NULL,
+             PRIMARY KEY (room, direction)
+         );
+         CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value INTEGER NOT NULL);",
+    )?;
+    Ok(connection)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_graph(path: &std::path::Path) -> rusqlite::Result<RoomGraph> {
+    use rusqlite::OptionalExtension;
+    let connection = open_database(path)?;
+    let mut graph = RoomGraph::default();
+    let mut rooms = connection.prepare("SELECT id, name, area, x, y, z, avoid FROM rooms")?;
+    for row in rooms.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            Room {
+                name: row.get(1)?,
+                area: row.get(2)?,
+                exits: BTreeMap::new(),
+                x: row.get(3)?,
+                y: row.get(4)?,
+                z: row.get(5)?,
+                avoid: row.get(6)?,
+            },
+        ))
+    })? {
+        let (id, room) = row?;
+        graph.rooms.insert(id, room);
+    }
+    let mut exits = connection.prepare(
+        "SELECT room, direction, destination, weight, command, locked, avoid FROM exits",
+    )?;
+    for row in exits.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            Exit {
+                to: row.get(2)?,
+                weight: row.get(3)?,
+                command: row.get(4)?,
+                locked: row.get(5)?,
+                avoid: row.get(6)?,
+            },
+        ))
+    })? {
+        let (room, direction, exit) = row?;
+        if let Some(room) = graph.rooms.get_mut(&room) {
+            room.exits.insert(direction, exit);
+        }
+    }
+    graph.next_id = connection
+        .query_row("SELECT value FROM meta WHERE key = 'next_id'", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .optional()?
+        .unwrap_or(0) as u64;
+    Ok(graph)
+}
+
+/// Replaces everything in the database with `graph`, in one transaction.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_graph(path: &std::path::Path, graph: &RoomGraph) -> rusqlite::Result<()> {
+    use rusqlite::params;
+    let mut connection = open_database(path)?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch("DELETE FROM exits; DELETE FROM rooms;")?;
+    {
+        let mut insert_room = transaction.prepare(
+            "INSERT INTO rooms (id, name, area, x, y, z, avoid) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        let mut insert_exit = transaction.prepare(
+            "INSERT INTO exits (room, direction, destination, weight, command, locked, avoid)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for (id, room) in &graph.rooms {
+            insert_room.execute(params![
+                id, room.name, room.area, room.x, room.y, room.z, room.avoid
+            ])?;
+            for (direction, exit) in &room.exits {
+                insert_exit.execute(params![
+                    id,
+                    direction,
+                    exit.to,
+                    exit.weight,
+                    exit.command,
+                    exit.locked,
+                    exit.avoid
+                ])?;
+            }
+        }
+    }
+    transaction.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('next_id', ?1)",
+        params![graph.next_id as i64],
+    )?;
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two ways from `a` to `d`: north through `b` (weight 5 then 1) and south through `c`
+    /// (weight 1 then 1).
+    fn graph() -> RoomGraph {
+        let mut graph = RoomGraph::default();
+        for (id, exits) in [
+            ("a", vec![("n", "b", 5), ("s", "c", 1)]),
+            ("b", vec![("e", "d", 1)]),
+            ("c", vec![("e", "d", 1)]),
+            ("d", vec![]),
+        ] {
+            let mut room = Room {
+                name: id.to_uppercase(),
+                ..Default::default()
+            };
+            for (direction, to, weight) in exits {
+                let exit = Exit {
+                    to: Some(to.to_owned()),
+                    weight,
+                    ..Default::default()
+                };
+                room.exits.insert(direction.to_owned(), exit);
+            }
+            graph.rooms.insert(id.to_owned(), room);
+        }
+        graph
+    }
+
+    fn exit<'a>(graph: &'a mut RoomGraph, room: &str, direction: &str) -> &'a mut Exit {
+        graph
+            .rooms
+            .get_mut(room)
+            .unwrap()
+            .exits
+            .get_mut(direction)
+            .unwrap()
+    }
+
+    fn commands(path: &Path) -> Vec<&str> {
+        path.steps
+            .iter()
+            .map(|step| step.command.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn path_takes_the_lightest_exits() {
+        let path = graph().find_path("a", "d", &BTreeSet::new()).unwrap();
+        assert_eq!(commands(&path), ["s", "e"]);
+        assert_eq!(path.cost, 2);
+        assert_eq!(path.steps[0].to, "c");
+    }
+
+    #[test]
+    fn path_goes_around_avoided_rooms_and_exits() {
+        let mut avoid_room = graph();
+        avoid_room.rooms.get_mut("c").unwrap().avoid = true;
+        let path = avoid_room.find_path("a", "d", &BTreeSet::new()).unwrap();
+        assert_eq!((commands(&path), path.cost), (vec!["n", "e"], 6));
+        // An avoided room can still be walked to
+        assert!(avoid_room.find_path("a", "c", &BTreeSet::new()).is_some());
+
+        let mut avoid_exit = graph();
+        exit(&mut avoid_exit, "c", "e").avoid = true;
+        let path = avoid_exit.find_path("a", "d", &BTreeSet::new()).unwrap();
+        assert_eq!(commands(&path), ["n", "e"]);
+    }
+
+    #[test]
+    fn locked_exits_need_a_command() {
+        let mut graph = graph();
+        exit(&mut graph, "a", "s").locked = true;
+        let path = graph.find_path("a", "d", &BTreeSet::new()).unwrap();
+        assert_eq!(commands(&path), ["n", "e"]);
+
+        exit(&mut graph, "a", "s").command = "unlock gate;open gate;s".to_owned();
+        let path = graph.find_path("a", "d", &BTreeSet::new()).unwrap();
+        assert_eq!(commands(&path), ["unlock gate;open gate;s", "e"]);
+        assert_eq!(path.steps[0].direction, "s");
+        assert_eq!(path.steps[0].commands(), ["unlock gate", "open gate", "s"]);
+    }
+
+    #[test]
+    fn blocked_exits_reroute_or_leave_no_path() {
+        let graph = graph();
+        let mut blocked = BTreeSet::from([("a".to_owned(), "s".to_owned())]);
+        let path = graph.find_path("a", "d", &blocked).unwrap();
+        assert_eq!(commands(&path), ["n", "e"]);
+
+        blocked.insert(("a".to_owned(), "n".to_owned()));
+        assert!(graph.find_path("a", "d", &blocked).is_none());
+        assert!(graph.find_path("d", "a", &BTreeSet::new()).is_none());
+    }
+
+    #[test]
+    fn lua_find_path_splits_exit_commands() {
+        let map = SharedMap::default();
+        {
+            let mut state = map.state.lock().unwrap();
+            state.graph = graph();
+            exit(&mut state.graph, "a", "s").command = "open gate;s".to_owned();
+            state.current = Some("a".to_owned());
+        }
+        let lua = Lua::new();
+        register_functions(&lua, map).unwrap();
+        let (commands, cost): (Vec<String>, u32) = lua
+            .load("return mapper.find_path(nil, 'd')")
+            .eval()
+            .unwrap();
+        assert_eq!(
+            (commands, cost),
+            (vec!["open gate".into(), "s".into(), "e".into()], 2)
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn graph_round_trips_through_the_database() {
+        let mut graph = RoomGraph::default();
+        let mut room = Room {
+            name: "Gate".to_owned(),
+            area: "Town".to_owned(),
+            x: 1,
+            y: -2,
+            z: 3,
+            avoid: true,
+            ..Default::default()
+        };
+        let exit = Exit {
+            to: Some("2".to_owned()),
+            weight: 5,
+            command: "open gate;n".to_owned(),
+            locked: true,
+            avoid: false,
+        };
+        room.exits.insert("n".to_owned(), exit);
+        room.exits.insert("s".to_owned(), Exit::default());
+        graph.rooms.insert("1".to_owned(), room);
+        graph.next_id = 7;
+
+        let path = std::env::temp_dir().join(format!("map-test-{}.db", std::process::id()));
+        write_graph(&path, &graph).unwrap();
+        write_graph(&path, &graph).unwrap(); // Saving again replaces the rows
+        let read = read_graph(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(read.next_id, 7);
+        let room = &read.rooms["1"];
+        assert_eq!((room.name.as_str(), room.area.as_str()), ("Gate", "Town"));
+        assert_eq!(room.position(), (1, -2, 3));
+        assert!(room.avoid);
+        let north = &room.exits["n"];
+        assert_eq!(north.to.as_deref(), Some("2"));
+        assert_eq!((north.weight, north.command.as_str()), (5, "open gate;n"));
+        assert!(north.locked && !north.avoid);
+        assert_eq!(room.exits["s"].to, None);
+    }
+}
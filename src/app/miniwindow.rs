@@ -1,103 +1,751 @@
-use egui;
+mod functions;
 
-#[derive(serde::Deserialize, serde::Serialize, Default)]
-pub struct WindowResizeTest {
-    auto_sized_open: bool,
-    resizable_scroll_open: bool,
-    resizable_embedded_scroll_open: bool,
-    resizable_without_scroll_open: bool,
-    resizable_with_text_edit_open: bool,
-    freely_resized_open: bool,
-    text: String,
+pub use functions::register_functions;
+
+use egui::emath::TSTransform;
+use egui::epaint::{Mesh, PathShape};
+use egui::text::{LayoutJob, TextFormat};
+use egui::{
+    Area, Color32, CursorIcon, FontFamily, FontId, Id, Order, Painter, Pos2, Rect, Rounding, Sense,
+    Shape, Stroke, Vec2,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::f32::consts::TAU;
+use std::sync::{Arc, Mutex};
+
+// Return codes, numbered as in MUSHclient so ported scripts can check them.
+pub const E_OK: i64 = 0;
+pub const E_BAD_PARAMETER: i64 = 30046;
+pub const E_NO_SUCH_WINDOW: i64 = 30073;
+pub const E_NO_SUCH_HOTSPOT: i64 = 30075;
+pub const E_NO_SUCH_IMAGE: i64 = 30076;
+
+// WindowCreate and WindowPosition flags.
+const FLAG_ABSOLUTE_LOCATION: i64 = 2;
+const FLAG_TRANSPARENT: i64 = 4;
+const FLAG_IGNORE_MOUSE: i64 = 8;
+const FLAG_KEEP_HOTSPOTS: i64 = 16;
+
+// Flags passed to hotspot callbacks.
+const HOTSPOT_SHIFT: i64 = 0x01;
+const HOTSPOT_CONTROL: i64 = 0x02;
+const HOTSPOT_ALT: i64 = 0x04;
+const HOTSPOT_LEFT_MOUSE: i64 = 0x10;
+const HOTSPOT_RIGHT_MOUSE: i64 = 0x20;
+const HOTSPOT_DOUBLE_CLICK: i64 = 0x40;
+
+const PEN_NULL: i64 = 5;
+const BRUSH_NULL: i64 = 1;
+const ELLIPSE_SEGMENTS: usize = 64;
+// Oldest drawing is dropped past this, for windows that never clear themselves.
+const MAX_OPS: usize = 20_000;
+
+/// Decodes a colour argument packed as MUSHclient does, `0xBBGGRR`, the same way
+/// `ColourNameToRGB` returns it.
+pub fn colour(value: i64) -> Color32 {
+    Color32::from_rgb(value as u8, (value >> 8) as u8, (value >> 16) as u8)
+}
+
+#[derive(Clone)]
+pub struct WindowFont {
+    font_id: FontId,
+    italics: bool,
+    underline: bool,
+    strikeout: bool,
 }
 
-impl WindowResizeTest {
-    pub fn new() -> Self {
+impl WindowFont {
+    /// Maps a Windows font name and point size onto egui's font families.
+    pub fn new(name: &str, size: f32, italics: bool, underline: bool, strikeout: bool) -> Self {
+        let lower = name.to_lowercase();
+        let monospace = [
+            "mono",
+            "courier",
+            "consol",
+            "fixed",
+            "lucida console",
+            "terminal",
+        ]
+        .iter()
+        .any(|hint| lower.contains(hint));
+        let family = if monospace {
+            FontFamily::Monospace
+        } else {
+            FontFamily::Proportional
+        };
         Self {
-            auto_sized_open: false,
-            resizable_scroll_open: false,
-            resizable_embedded_scroll_open: false,
-            resizable_without_scroll_open: false,
-            resizable_with_text_edit_open: false,
-            freely_resized_open: false,
-            text: "Lorem ipsum dolor sit amet, consectetur adipiscing elit.".to_owned(),
-        }
-    }
-
-    pub fn show(&mut self, ctx: &egui::Context) {
-        use egui::*;
-
-        Window::new("↔ auto-sized")
-            .open(&mut self.auto_sized_open)
-            .auto_sized()
-            .show(ctx, |ui| {
-                ui.label("This window will auto-size based on its contents.");
-                ui.heading("Resize this area:");
-                Resize::default().show(ui, |ui| {
-                    ui.label(&self.text);
-                });
-                ui.heading("Resize the above area!");
-            });
+            font_id: FontId::new(size.max(1.0) * 96.0 / 72.0, family),
+            italics,
+            underline,
+            strikeout,
+        }
+    }
 
-        Window::new("↔ resizable + scroll")
-            .open(&mut self.resizable_scroll_open)
-            .vscroll(true)
-            .resizable(true)
-            .default_height(300.0)
-            .show(ctx, |ui| {
-                ui.label(
-                    "This window is resizable and has a scroll area. You can shrink it to any size.",
-                );
-                ui.separator();
-                ui.label(&self.text);
-            });
+    fn layout_job(&self, text: &str, color: Color32, scale: f32) -> LayoutJob {
+        let stroke = Stroke::new(1.0 * scale, color);
+        LayoutJob::single_section(
+            text.to_string(),
+            TextFormat {
+                font_id: FontId::new(self.font_id.size * scale, self.font_id.family.clone()),
+                color,
+                italics: self.italics,
+                underline: if self.underline { stroke } else { Stroke::NONE },
+                strikethrough: if self.strikeout { stroke } else { Stroke::NONE },
+                ..Default::default()
+            },
+        )
+    }
+}
 
-        Window::new("↔ resizable + embedded scroll")
-            .open(&mut self.resizable_embedded_scroll_open)
-            .vscroll(false)
-            .resizable(true)
-            .default_height(300.0)
-            .show(ctx, |ui| {
-                ui.label("This window is resizable but has no built-in scroll area.");
-                ui.label("However, we have a sub-region with a scroll bar:");
-                ui.separator();
-                ScrollArea::vertical().show(ui, |ui| {
-                    ui.label(&format!("{}\n\n{}", self.text, self.text));
-                });
-            });
+/// A window drawn as a snapshot by `WindowImageFromWindow`.
+#[derive(Clone)]
+pub struct Image {
+    size: Vec2,
+    ops: Vec<DrawOp>,
+}
 
-        Window::new("↔ resizable without scroll")
-            .open(&mut self.resizable_without_scroll_open)
-            .vscroll(false)
-            .resizable(true)
-            .show(ctx, |ui| {
-                ui.label("This window is resizable but has no scroll area. This means it can only be resized to a size where all the contents is visible.");
-                ui.label("egui will not clip the contents of a window, nor add whitespace to it.");
-                ui.separator();
-                ui.label(&self.text);
-            });
+/// One recorded drawing call. Text is laid out when painted so it follows font changes.
+#[derive(Clone)]
+enum DrawOp {
+    Shape(Shape),
+    Text {
+        pos: Pos2,
+        clip: Rect,
+        text: String,
+        font: WindowFont,
+        color: Color32,
+    },
+    Image {
+        image: Image,
+        rect: Rect,
+        scale: f32,
+    },
+}
+
+impl DrawOp {
+    /// The area the op can paint in, in window coordinates.
+    fn bounds(&self) -> Rect {
+        match self {
+            DrawOp::Shape(shape) => shape.visual_bounding_rect(),
+            DrawOp::Text { clip, .. } => *clip,
+            DrawOp::Image { rect, .. } => *rect,
+        }
+    }
+}
+
+pub struct Hotspot {
+    pub id: String,
+    pub owner: String,
+    pub rect: Rect,
+    pub mouse_over: Option<String>,
+    pub cancel_mouse_over: Option<String>,
+    pub mouse_down: Option<String>,
+    pub cancel_mouse_down: Option<String>,
+    pub mouse_up: Option<String>,
+    pub tooltip: String,
+    pub cursor: i64,
+}
+
+/// A hotspot callback waiting to run in the Lua state that added the hotspot.
+pub struct HotspotCall {
+    /// Plugin id, or empty for the main script state.
+    pub owner: String,
+    pub callback: String,
+    pub flags: i64,
+    pub hotspot_id: String,
+}
+
+/// A script-owned drawing surface. Drawing calls are recorded and replayed with egui's painter
+/// every frame, so the window acts as an offscreen canvas.
+pub struct Miniwindow {
+    left: f32,
+    top: f32,
+    size: Vec2,
+    position: i64,
+    flags: i64,
+    background: Color32,
+    visible: bool,
+    ops: Vec<DrawOp>,
+    fonts: HashMap<String, WindowFont>,
+    images: HashMap<String, Image>,
+    hotspots: Vec<Hotspot>,
+    hovered: Option<String>,
+    pressed: Option<String>,
+}
+
+impl Miniwindow {
+    /// Turns MUSHclient-style coordinates into a rectangle; a right or bottom of zero or less
+    /// is measured back from the window's right or bottom edge.
+    pub fn rect(&self, left: f32, top: f32, right: f32, bottom: f32) -> Rect {
+        let right = if right <= 0.0 {
+            self.size.x + right
+        } else {
+            right
+        };
+        let bottom = if bottom <= 0.0 {
+            self.size.y + bottom
+        } else {
+            bottom
+        };
+        Rect::from_min_max(Pos2::new(left, top), Pos2::new(right, bottom))
+    }
+
+    fn push(&mut self, op: DrawOp) {
+        self.ops.push(op);
+        if self.ops.len() > MAX_OPS {
+            self.ops.drain(..self.ops.len() - MAX_OPS);
+        }
+    }
+
+    fn push_shape(&mut self, shape: Shape) {
+        self.push(DrawOp::Shape(shape));
+    }
+
+    pub fn rect_op(&mut self, action: i64, rect: Rect, colour1: Color32, colour2: Color32) -> i64 {
+        match action {
+            // Frame
+            1 => self.push_shape(Shape::rect_stroke(
+                rect.shrink(0.5),
+                Rounding::ZERO,
+                Stroke::new(1.0, colour1),
+            )),
+            // Fill; an opaque fill hides everything drawn inside it before, so windows redrawn
+            // piece by piece don't keep every earlier frame.
+            2 => {
+                if colour1.is_opaque() {
+                    self.ops.retain(|op| !rect.contains_rect(op.bounds()));
+                }
+                self.push_shape(Shape::rect_filled(rect, Rounding::ZERO, colour1));
+            }
+            // 3D rectangle: colour1 on the top and left, colour2 on the bottom and right.
+            4 => self.bevel(rect, colour1, colour2),
+            // Draw edge, approximated as a raised bevel.
+            5 => self.bevel(rect, Color32::from_gray(220), Color32::from_gray(100)),
+            _ => return E_BAD_PARAMETER,
+        }
+        E_OK
+    }
+
+    fn bevel(&mut self, rect: Rect, light: Color32, dark: Color32) {
+        let rect = rect.shrink(0.5);
+        self.push_shape(Shape::line(
+            vec![rect.left_bottom(), rect.left_top(), rect.right_top()],
+            Stroke::new(1.0, light),
+        ));
+        self.push_shape(Shape::line(
+            vec![rect.right_top(), rect.right_bottom(), rect.left_bottom()],
+            Stroke::new(1.0, dark),
+        ));
+    }
+
+    pub fn line(&mut self, from: Pos2, to: Pos2, stroke: Stroke) {
+        self.push_shape(Shape::line_segment([from, to], stroke));
+    }
+
+    /// Ellipses, rectangles, rounded rectangles, chords and pies. `extra` holds the corner size
+    /// for rounded rectangles and the two radial end points for chords and pies.
+    pub fn circle_op(
+        &mut self,
+        action: i64,
+        rect: Rect,
+        stroke: Stroke,
+        fill: Color32,
+        extra: [f32; 4],
+    ) -> i64 {
+        let shapes = match action {
+            1 => vec![Shape::Path(PathShape::convex_polygon(
+                arc_points(rect, 0.0, TAU),
+                fill,
+                stroke,
+            ))],
+            2 | 3 => {
+                let rounding = if action == 3 {
+                    Rounding::same(extra[0].min(extra[1]) / 2.0)
+                } else {
+                    Rounding::ZERO
+                };
+                vec![
+                    Shape::rect_filled(rect, rounding, fill),
+                    Shape::rect_stroke(rect, rounding, stroke),
+                ]
+            }
+            4 | 5 => {
+                let start = radial_angle(rect, Pos2::new(extra[0], extra[1]));
+                let mut end = radial_angle(rect, Pos2::new(extra[2], extra[3]));
+                if end <= start {
+                    end += TAU;
+                }
+                let mut points = arc_points(rect, start, end);
+                if action == 5 {
+                    points.insert(0, rect.center());
+                }
+                vec![fan(&points, fill), Shape::closed_line(points, stroke)]
+            }
+            _ => return E_BAD_PARAMETER,
+        };
+        for shape in shapes {
+            self.push_shape(shape);
+        }
+        E_OK
+    }
+
+    pub fn gradient(&mut self, rect: Rect, start: Color32, end: Color32, mode: i64) -> i64 {
+        let (top_left, top_right, bottom_left, bottom_right) = match mode {
+            1 => (start, end, start, end),
+            2 => (start, start, end, end),
+            _ => return E_BAD_PARAMETER,
+        };
+        let mut mesh = Mesh::default();
+        mesh.colored_vertex(rect.left_top(), top_left);
+        mesh.colored_vertex(rect.right_top(), top_right);
+        mesh.colored_vertex(rect.left_bottom(), bottom_left);
+        mesh.colored_vertex(rect.right_bottom(), bottom_right);
+        mesh.add_triangle(0, 1, 2);
+        mesh.add_triangle(2, 1, 3);
+        self.push_shape(Shape::mesh(mesh));
+        E_OK
+    }
+
+    pub fn text(&mut self, font: WindowFont, text: &str, clip: Rect, color: Color32) {
+        self.push(DrawOp::Text {
+            pos: clip.min,
+            clip,
+            text: text.to_string(),
+            font,
+            color,
+        });
+    }
+
+    pub fn font(&self, id: &str) -> Option<&WindowFont> {
+        self.fonts.get(id)
+    }
+
+    pub fn image(&self, id: &str) -> Option<&Image> {
+        self.images.get(id)
+    }
+
+    /// Draws an image at `rect.min`; with `stretch` it is scaled to fit `rect`, keeping its
+    /// aspect ratio.
+    pub fn draw_image(&mut self, image: Image, rect: Rect, stretch: bool) {
+        let scale = if stretch && image.size.x > 0.0 && image.size.y > 0.0 {
+            (rect.width() / image.size.x).min(rect.height() / image.size.y)
+        } else {
+            1.0
+        };
+        let rect = Rect::from_min_size(rect.min, image.size * scale);
+        self.push(DrawOp::Image { image, rect, scale });
+    }
+
+    /// A copy of everything drawn so far, background included.
+    fn snapshot(&self) -> Image {
+        let mut ops = Vec::with_capacity(self.ops.len() + 1);
+        if self.flags & FLAG_TRANSPARENT == 0 {
+            ops.push(DrawOp::Shape(Shape::rect_filled(
+                Rect::from_min_size(Pos2::ZERO, self.size),
+                Rounding::ZERO,
+                self.background,
+            )));
+        }
+        ops.extend(self.ops.iter().cloned());
+        Image {
+            size: self.size,
+            ops,
+        }
+    }
+
+    pub fn add_hotspot(&mut self, hotspot: Hotspot) {
+        self.hotspots.retain(|existing| existing.id != hotspot.id);
+        self.hotspots.push(hotspot);
+    }
+
+    pub fn delete_hotspot(&mut self, id: &str) -> i64 {
+        let count = self.hotspots.len();
+        self.hotspots.retain(|hotspot| hotspot.id != id);
+        if self.hotspots.len() == count {
+            E_NO_SUCH_HOTSPOT
+        } else {
+            E_OK
+        }
+    }
+
+    fn screen_position(&self, output: Rect) -> Pos2 {
+        if self.flags & FLAG_ABSOLUTE_LOCATION != 0 {
+            return Pos2::new(self.left, self.top);
+        }
+        let free = output.size() - self.size;
+        let (x, y) = match self.position {
+            4 => (0.0, 0.0),
+            5 => (free.x / 2.0, 0.0),
+            6 => (free.x, 0.0),
+            7 => (free.x, free.y / 2.0),
+            8 => (free.x, free.y),
+            9 => (free.x / 2.0, free.y),
+            10 => (0.0, free.y),
+            11 => (0.0, free.y / 2.0),
+            // Stretching and tiling are not supported; those windows are centred instead.
+            _ => (free.x / 2.0, free.y / 2.0),
+        };
+        output.min + Vec2::new(x, y)
+    }
+
+    /// Queues the callbacks for this frame's mouse activity over the hotspots.
+    fn handle_hotspots(&mut self, ui: &egui::Ui, origin: Pos2, calls: &mut Vec<HotspotCall>) {
+        if self.flags & FLAG_IGNORE_MOUSE != 0 {
+            return;
+        }
+        let (modifiers, secondary, any_down) = ui.input(|input| {
+            (
+                input.modifiers,
+                input.pointer.secondary_down() || input.pointer.secondary_released(),
+                input.pointer.any_down(),
+            )
+        });
+        let mut flags = 0;
+        if modifiers.shift {
+            flags |= HOTSPOT_SHIFT;
+        }
+        if modifiers.ctrl {
+            flags |= HOTSPOT_CONTROL;
+        }
+        if modifiers.alt {
+            flags |= HOTSPOT_ALT;
+        }
+        flags |= if secondary {
+            HOTSPOT_RIGHT_MOUSE
+        } else {
+            HOTSPOT_LEFT_MOUSE
+        };
+
+        let mut hovered = None;
+        let mut pressed = None;
+        let mut released = None;
+        for hotspot in &self.hotspots {
+            let rect = hotspot.rect.translate(origin.to_vec2());
+            let response = ui.interact(rect, ui.id().with(&hotspot.id), Sense::click_and_drag());
+            if response.hovered() {
+                hovered = Some(hotspot.id.clone());
+                if hotspot.cursor >= 0 {
+                    ui.ctx().set_cursor_icon(cursor_icon(hotspot.cursor));
+                }
+            }
+            if response.is_pointer_button_down_on() && self.pressed.is_none() {
+                pressed = Some(hotspot.id.clone());
+            }
+            if response.double_clicked() {
+                released = Some((hotspot.id.clone(), HOTSPOT_DOUBLE_CLICK));
+            }
+            if !hotspot.tooltip.is_empty() {
+                response.on_hover_text(&hotspot.tooltip);
+            }
+        }
+
+        let call =
+            |hotspots: &[Hotspot], id: &str, pick: fn(&Hotspot) -> &Option<String>, flags| {
+                let hotspot = hotspots.iter().find(|hotspot| hotspot.id == id)?;
+                let callback = pick(hotspot).clone()?;
+                Some(HotspotCall {
+                    owner: hotspot.owner.clone(),
+                    callback,
+                    flags,
+                    hotspot_id: id.to_string(),
+                })
+            };
+
+        if hovered != self.hovered {
+            if let Some(old) = self.hovered.take() {
+                calls.extend(call(&self.hotspots, &old, |h| &h.cancel_mouse_over, 0));
+            }
+            if let Some(new) = &hovered {
+                calls.extend(call(&self.hotspots, new, |h| &h.mouse_over, 0));
+            }
+            self.hovered = hovered.clone();
+        }
+        if let Some(id) = pressed {
+            calls.extend(call(&self.hotspots, &id, |h| &h.mouse_down, flags));
+            self.pressed = Some(id);
+        }
+        if let Some((id, double)) = released {
+            calls.extend(call(&self.hotspots, &id, |h| &h.mouse_down, flags | double));
+        }
+        if !any_down {
+            if let Some(id) = self.pressed.take() {
+                if hovered.as_ref() == Some(&id) {
+                    calls.extend(call(&self.hotspots, &id, |h| &h.mouse_up, flags));
+                } else {
+                    calls.extend(call(&self.hotspots, &id, |h| &h.cancel_mouse_down, flags));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct MiniwindowStore {
+    // Sorted by name, which is also the drawing order, as in MUSHclient.
+    windows: BTreeMap<String, Miniwindow>,
+    calls: Vec<HotspotCall>,
+    // Available once the first frame has started, for measuring text.
+    ctx: Option<egui::Context>,
+}
+
+/// Every miniwindow, shared by the main script state and the plugins.
+#[derive(Clone, Default)]
+pub struct Miniwindows {
+    store: Arc<Mutex<MiniwindowStore>>,
+}
 
-        Window::new("↔ resizable with TextEdit")
-            .open(&mut self.resizable_with_text_edit_open)
-            .vscroll(false)
-            .resizable(true)
-            .default_height(300.0)
-            .show(ctx, |ui| {
-                ui.label("Shows how you can fill an area with a widget.");
-                ui.add_sized(ui.available_size(), TextEdit::multiline(&mut self.text));
+impl Miniwindows {
+    #[allow(clippy::too_many_arguments)]
+    pub fn create(
+        &self,
+        name: &str,
+        left: f32,
+        top: f32,
+        width: f32,
+        height: f32,
+        position: i64,
+        flags: i64,
+        background: Color32,
+    ) -> i64 {
+        if name.is_empty() || width < 0.0 || height < 0.0 {
+            return E_BAD_PARAMETER;
+        }
+        let mut store = self.store.lock().unwrap();
+        let window = store
+            .windows
+            .entry(name.to_string())
+            .or_insert_with(|| Miniwindow {
+                left,
+                top,
+                size: Vec2::ZERO,
+                position,
+                flags,
+                background,
+                visible: false,
+                ops: Vec::new(),
+                fonts: HashMap::new(),
+                images: HashMap::new(),
+                hotspots: Vec::new(),
+                hovered: None,
+                pressed: None,
             });
+        window.left = left;
+        window.top = top;
+        window.size = Vec2::new(width, height);
+        window.position = position;
+        window.flags = flags;
+        window.background = background;
+        window.ops.clear();
+        if flags & FLAG_KEEP_HOTSPOTS == 0 {
+            window.hotspots.clear();
+            window.hovered = None;
+            window.pressed = None;
+        }
+        E_OK
+    }
+
+    pub fn delete(&self, name: &str) -> i64 {
+        match self.store.lock().unwrap().windows.remove(name) {
+            Some(_) => E_OK,
+            None => E_NO_SUCH_WINDOW,
+        }
+    }
+
+    /// Runs `f` on the named window, or returns `E_NO_SUCH_WINDOW`.
+    pub fn with_window(&self, name: &str, f: impl FnOnce(&mut Miniwindow) -> i64) -> i64 {
+        match self.store.lock().unwrap().windows.get_mut(name) {
+            Some(window) => f(window),
+            None => E_NO_SUCH_WINDOW,
+        }
+    }
+
+    pub fn show_window(&self, name: &str, show: bool) -> i64 {
+        self.with_window(name, |window| {
+            window.visible = show;
+            E_OK
+        })
+    }
+
+    pub fn position(&self, name: &str, left: f32, top: f32, position: i64, flags: i64) -> i64 {
+        self.with_window(name, |window| {
+            window.left = left;
+            window.top = top;
+            window.position = position;
+            window.flags = flags;
+            E_OK
+        })
+    }
+
+    pub fn set_font(&self, name: &str, id: &str, font: Option<WindowFont>) -> i64 {
+        self.with_window(name, |window| {
+            match font {
+                Some(font) => window.fonts.insert(id.to_string(), font),
+                None => window.fonts.remove(id),
+            };
+            E_OK
+        })
+    }
+
+    /// Copies `source` as it looks now into `name`'s images.
+    pub fn image_from_window(&self, name: &str, image_id: &str, source: &str) -> i64 {
+        let mut store = self.store.lock().unwrap();
+        let Some(image) = store.windows.get(source).map(Miniwindow::snapshot) else {
+            return E_NO_SUCH_WINDOW;
+        };
+        match store.windows.get_mut(name) {
+            Some(window) => {
+                window.images.insert(image_id.to_string(), image);
+                E_OK
+            }
+            None => E_NO_SUCH_WINDOW,
+        }
+    }
+
+    /// The width `text` takes up in `font`. Before the first frame the fonts are not loaded
+    /// yet, so the width is estimated.
+    pub fn text_width(&self, font: &WindowFont, text: &str) -> f32 {
+        let ctx = self.store.lock().unwrap().ctx.clone();
+        match ctx {
+            Some(ctx) => {
+                let job = font.layout_job(text, Color32::WHITE, 1.0);
+                ctx.fonts(|fonts| fonts.layout_job(job)).size().x
+            }
+            None => text.chars().count() as f32 * font.font_id.size * 0.6,
+        }
+    }
+
+    /// Removes the hotspot callbacks queued by mouse activity.
+    pub fn take_calls(&self) -> Vec<HotspotCall> {
+        std::mem::take(&mut self.store.lock().unwrap().calls)
+    }
 
-        Window::new("↔ freely resized")
-            .open(&mut self.freely_resized_open)
-            .vscroll(false)
-            .resizable(true)
-            .default_size([250.0, 150.0])
-            .show(ctx, |ui| {
-                ui.label("This window has empty space that fills up the available space, preventing auto-shrink.");
-                ui.vertical_centered(|ui| {
-                    ui.label("Powered by egui and eframe.");
+    /// Paints the visible windows over `output`, the area left for the world output.
+    pub fn show(&self, ctx: &egui::Context, output: Rect) {
+        let mut store = self.store.lock().unwrap();
+        let store = &mut *store;
+        if store.ctx.is_none() {
+            store.ctx = Some(ctx.clone());
+        }
+
+        for (name, window) in store
+            .windows
+            .iter_mut()
+            .filter(|(_, window)| window.visible)
+        {
+            let origin = window.screen_position(output);
+            Area::new(Id::new(("miniwindow", name.as_str())))
+                .fixed_pos(origin)
+                .order(Order::Middle)
+                .interactable(!window.hotspots.is_empty())
+                .show(ctx, |ui| {
+                    let (rect, _) = ui.allocate_exact_size(window.size, Sense::hover());
+                    let painter = ui.painter_at(rect);
+                    if window.flags & FLAG_TRANSPARENT == 0 {
+                        painter.rect_filled(rect, Rounding::ZERO, window.background);
+                    }
+                    paint_ops(
+                        &painter,
+                        &window.ops,
+                        TSTransform::from_translation(rect.min.to_vec2()),
+                    );
+                    window.handle_hotspots(ui, rect.min, &mut store.calls);
                 });
-                ui.allocate_space(ui.available_size());
-            });
+        }
+    }
+}
+
+fn paint_ops(painter: &Painter, ops: &[DrawOp], transform: TSTransform) {
+    for op in ops {
+        match op {
+            DrawOp::Shape(shape) => {
+                let mut shape = shape.clone();
+                shape.transform(transform);
+                painter.add(shape);
+            }
+            DrawOp::Text {
+                pos,
+                clip,
+                text,
+                font,
+                color,
+            } => {
+                let clip = transform.mul_rect(*clip).intersect(painter.clip_rect());
+                let galley = painter.layout_job(font.layout_job(text, *color, transform.scaling));
+                painter
+                    .with_clip_rect(clip)
+                    .galley(transform * *pos, galley, *color);
+            }
+            DrawOp::Image { image, rect, scale } => {
+                let clip = transform.mul_rect(*rect).intersect(painter.clip_rect());
+                let inner = transform
+                    * TSTransform::from_translation(rect.min.to_vec2())
+                    * TSTransform::from_scaling(*scale);
+                paint_ops(&painter.with_clip_rect(clip), &image.ops, inner);
+            }
+        }
+    }
+}
+
+/// Points along the ellipse inscribed in `rect`, from angle `start` to `end` (radians,
+/// counter-clockwise with y up).
+fn arc_points(rect: Rect, start: f32, end: f32) -> Vec<Pos2> {
+    let steps = ((ELLIPSE_SEGMENTS as f32 * (end - start) / TAU).ceil() as usize).max(2);
+    let radius = rect.size() / 2.0;
+    (0..=steps)
+        .map(|i| {
+            let angle = start + (end - start) * i as f32 / steps as f32;
+            rect.center() + Vec2::new(radius.x * angle.cos(), -radius.y * angle.sin())
+        })
+        .collect()
+}
+
+fn radial_angle(rect: Rect, point: Pos2) -> f32 {
+    let delta = point - rect.center();
+    (-delta.y).atan2(delta.x).rem_euclid(TAU)
+}
+
+/// Fills a polygon that every point can see from its first point, such as a pie or chord.
+fn fan(points: &[Pos2], fill: Color32) -> Shape {
+    let mut mesh = Mesh::default();
+    for point in points {
+        mesh.colored_vertex(*point, fill);
+    }
+    for i in 1..points.len().saturating_sub(1) as u32 {
+        mesh.add_triangle(0, i, i + 1);
+    }
+    Shape::mesh(mesh)
+}
+
+fn cursor_icon(cursor: i64) -> CursorIcon {
+    match cursor {
+        1 => CursorIcon::PointingHand,
+        2 => CursorIcon::Text,
+        3 => CursorIcon::Crosshair,
+        4 => CursorIcon::Wait,
+        6 => CursorIcon::ResizeNwSe,
+        7 => CursorIcon::ResizeNeSw,
+        8 => CursorIcon::ResizeHorizontal,
+        9 => CursorIcon::ResizeVertical,
+        10 => CursorIcon::Move,
+        11 => CursorIcon::NotAllowed,
+        12 => CursorIcon::Help,
+        _ => CursorIcon::Default,
+    }
+}
+
+/// Pen and brush arguments as MUSHclient passes them.
+pub fn stroke(colour_value: i64, style: i64, width: f32) -> Stroke {
+    if style == PEN_NULL {
+        Stroke::NONE
+    } else {
+        Stroke::new(width.max(1.0), colour(colour_value))
+    }
+}
+
+pub fn fill(colour_value: i64, style: i64) -> Color32 {
+    if style == BRUSH_NULL {
+        Color32::TRANSPARENT
+    } else {
+        colour(colour_value)
     }
 }
@@ -0,0 +1,408 @@
+use super::{
+    colour, fill, stroke, Hotspot, Miniwindows, WindowFont, E_BAD_PARAMETER, E_NO_SUCH_IMAGE, E_OK,
+};
+use egui::Pos2;
+use mlua::prelude::*;
+
+type FontArgs = (
+    String,
+    String,
+    String,
+    f32,
+    Option<bool>,
+    Option<bool>,
+    Option<bool>,
+    Option<bool>,
+);
+type CircleOpArgs = (
+    String,
+    i64,
+    f32,
+    f32,
+    f32,
+    f32,
+    i64,
+    i64,
+    f32,
+    i64,
+    Option<i64>,
+    Option<f32>,
+    Option<f32>,
+    Option<f32>,
+    Option<f32>,
+);
+type HotspotArgs = (
+    String,
+    String,
+    f32,
+    f32,
+    f32,
+    f32,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+);
+
+/// Returned by `WindowText` and `WindowTextWidth` when the window does not exist.
+const TEXT_NO_SUCH_WINDOW: i64 = -1;
+/// Returned by `WindowText` and `WindowTextWidth` when the font id is unknown.
+const TEXT_NO_SUCH_FONT: i64 = -2;
+
+fn callback(name: Option<String>) -> Option<String> {
+    name.filter(|name| !name.is_empty())
+}
+
+/// Registers the MUSHclient miniwindow functions. Hotspot callbacks run in the state of the
+/// script that added the hotspot, identified by `owner` (a plugin id, or empty).
+pub fn register_functions(lua: &Lua, miniwindows: Miniwindows, owner: &str) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowCreate",
+        lua.create_function(
+            move |_,
+                  (name, left, top, width, height, position, flags, background): (
+                String,
+                f32,
+                f32,
+                f32,
+                f32,
+                i64,
+                Option<i64>,
+                Option<i64>,
+            )| {
+                Ok(windows.create(
+                    &name,
+                    left,
+                    top,
+                    width,
+                    height,
+                    position,
+                    flags.unwrap_or(0),
+                    colour(background.unwrap_or(0)),
+                ))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowShow",
+        lua.create_function(move |_, (name, show): (String, Option<bool>)| {
+            Ok(windows.show_window(&name, show.unwrap_or(true)))
+        })?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowPosition",
+        lua.create_function(
+            move |_, (name, left, top, position, flags): (String, f32, f32, i64, Option<i64>)| {
+                Ok(windows.position(&name, left, top, position, flags.unwrap_or(0)))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowDelete",
+        lua.create_function(move |_, name: String| Ok(windows.delete(&name)))?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowRectOp",
+        lua.create_function(
+            move |_,
+                  (name, action, left, top, right, bottom, colour1, colour2): (
+                String,
+                i64,
+                f32,
+                f32,
+                f32,
+                f32,
+                i64,
+                Option<i64>,
+            )| {
+                Ok(windows.with_window(&name, |window| {
+                    let rect = window.rect(left, top, right, bottom);
+                    window.rect_op(
+                        action,
+                        rect,
+                        colour(colour1),
+                        colour(colour2.unwrap_or(colour1)),
+                    )
+                }))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowFont",
+        lua.create_function(
+            move |_,
+                  (name, font_id, font_name, size, _bold, italic, underline, strikeout): FontArgs| {
+                // An empty name with size 0 deletes the font, as in MUSHclient.
+                let font = if font_name.is_empty() && size == 0.0 {
+                    None
+                } else if size <= 0.0 {
+                    return Ok(E_BAD_PARAMETER);
+                } else {
+                    Some(WindowFont::new(
+                        &font_name,
+                        size,
+                        italic.unwrap_or(false),
+                        underline.unwrap_or(false),
+                        strikeout.unwrap_or(false),
+                    ))
+                };
+                Ok(windows.set_font(&name, &font_id, font))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowText",
+        lua.create_function(
+            move |_,
+                  (name, font_id, text, left, top, right, bottom, text_colour): (
+                String,
+                String,
+                String,
+                f32,
+                f32,
+                f32,
+                f32,
+                i64,
+            )| {
+                let mut font = None;
+                let found = windows.with_window(&name, |window| {
+                    font = window.font(&font_id).cloned();
+                    E_OK
+                });
+                if found != E_OK {
+                    return Ok(TEXT_NO_SUCH_WINDOW);
+                }
+                let Some(font) = font else {
+                    return Ok(TEXT_NO_SUCH_FONT);
+                };
+                let mut width = windows.text_width(&font, &text);
+                windows.with_window(&name, |window| {
+                    let clip = window.rect(left, top, right, bottom);
+                    // Text is clipped at `right`, so the reported width never goes past it.
+                    width = width.min(clip.width().max(0.0));
+                    window.text(font, &text, clip, colour(text_colour));
+                    E_OK
+                });
+                Ok(width.round() as i64)
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowTextWidth",
+        lua.create_function(move |_, (name, font_id, text): (String, String, String)| {
+            let mut font = None;
+            let found = windows.with_window(&name, |window| {
+                font = window.font(&font_id).cloned();
+                E_OK
+            });
+            if found != E_OK {
+                return Ok(TEXT_NO_SUCH_WINDOW);
+            }
+            Ok(match font {
+                Some(font) => windows.text_width(&font, &text).round() as i64,
+                None => TEXT_NO_SUCH_FONT,
+            })
+        })?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowLine",
+        lua.create_function(
+            move |_,
+                  (name, x1, y1, x2, y2, pen_colour, pen_style, pen_width): (
+                String,
+                f32,
+                f32,
+                f32,
+                f32,
+                i64,
+                Option<i64>,
+                Option<f32>,
+            )| {
+                Ok(windows.with_window(&name, |window| {
+                    let pen = stroke(pen_colour, pen_style.unwrap_or(0), pen_width.unwrap_or(1.0));
+                    window.line(Pos2::new(x1, y1), Pos2::new(x2, y2), pen);
+                    E_OK
+                }))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowCircleOp",
+        lua.create_function(
+            move |_,
+                  (
+                name,
+                action,
+                left,
+                top,
+                right,
+                bottom,
+                pen_colour,
+                pen_style,
+                pen_width,
+                brush_colour,
+                brush_style,
+                extra1,
+                extra2,
+                extra3,
+                extra4,
+            ): CircleOpArgs| {
+                Ok(windows.with_window(&name, |window| {
+                    let rect = window.rect(left, top, right, bottom);
+                    let extra = [
+                        extra1.unwrap_or(0.0),
+                        extra2.unwrap_or(0.0),
+                        extra3.unwrap_or(0.0),
+                        extra4.unwrap_or(0.0),
+                    ];
+                    window.circle_op(
+                        action,
+                        rect,
+                        stroke(pen_colour, pen_style, pen_width),
+                        fill(brush_colour, brush_style.unwrap_or(0)),
+                        extra,
+                    )
+                }))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowGradient",
+        lua.create_function(
+            move |_,
+                  (name, left, top, right, bottom, start_colour, end_colour, mode): (
+                String,
+                f32,
+                f32,
+                f32,
+                f32,
+                i64,
+                i64,
+                i64,
+            )| {
+                Ok(windows.with_window(&name, |window| {
+                    let rect = window.rect(left, top, right, bottom);
+                    window.gradient(rect, colour(start_colour), colour(end_colour), mode)
+                }))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowImageFromWindow",
+        lua.create_function(
+            move |_, (name, image_id, source): (String, String, String)| {
+                Ok(windows.image_from_window(&name, &image_id, &source))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    globals.set(
+        "WindowDrawImage",
+        lua.create_function(
+            move |_,
+                  (name, image_id, left, top, right, bottom, mode): (
+                String,
+                String,
+                f32,
+                f32,
+                f32,
+                f32,
+                Option<i64>,
+            )| {
+                Ok(windows.with_window(&name, |window| {
+                    let Some(image) = window.image(&image_id).cloned() else {
+                        return E_NO_SUCH_IMAGE;
+                    };
+                    let rect = window.rect(left, top, right, bottom);
+                    match mode.unwrap_or(1) {
+                        1 => window.draw_image(image, rect, false),
+                        2 => window.draw_image(image, rect, true),
+                        _ => return E_BAD_PARAMETER,
+                    }
+                    E_OK
+                }))
+            },
+        )?,
+    )?;
+
+    let windows = miniwindows.clone();
+    let hotspot_owner = owner.to_string();
+    globals.set(
+        "WindowAddHotspot",
+        lua.create_function(
+            move |_,
+                  (
+                name,
+                id,
+                left,
+                top,
+                right,
+                bottom,
+                mouse_over,
+                cancel_mouse_over,
+                mouse_down,
+                cancel_mouse_down,
+                mouse_up,
+                tooltip,
+                cursor,
+                _flags,
+            ): HotspotArgs| {
+                Ok(windows.with_window(&name, |window| {
+                    let rect = window.rect(left, top, right, bottom);
+                    window.add_hotspot(Hotspot {
+                        id,
+                        owner: hotspot_owner.clone(),
+                        rect,
+                        mouse_over: callback(mouse_over),
+                        cancel_mouse_over: callback(cancel_mouse_over),
+                        mouse_down: callback(mouse_down),
+                        cancel_mouse_down: callback(cancel_mouse_down),
+                        mouse_up: callback(mouse_up),
+                        tooltip: tooltip.unwrap_or_default(),
+                        cursor: cursor.unwrap_or(0),
+                    });
+                    E_OK
+                }))
+            },
+        )?,
+    )?;
+
+    globals.set(
+        "WindowDeleteHotspot",
+        lua.create_function(move |_, (name, id): (String, String)| {
+            Ok(miniwindows.with_window(&name, |window| window.delete_hotspot(&id)))
+        })?,
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use egui::{DragValue, ScrollArea, TextEdit, Window};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const PREVIEW_LINES: usize = 5;
+
+/// Lines waiting to go to the world, each followed by the delay it was queued with so a long
+/// post doesn't flood it.
+#[derive(Default)]
+pub struct PacedLines {
+    queue: VecDeque<(String, Duration)>,
+    next: Option<Instant>,
+}
+
+impl PacedLines {
+    pub fn push(&mut self, lines: impl IntoIterator<Item = String>, delay: Duration) {
+        self.queue
+            .extend(lines.into_iter().map(|line| (line, delay)));
+    }
+
+    /// The lines whose turn has come. Call once a frame.
+    pub fn due(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        while self.next.is_none_or(|next| now >= next) {
+            let Some((line, delay)) = self.queue.pop_front() else {
+                self.next = None;
+                break;
+            };
+            due.push(line);
+            self.next = (!delay.is_zero()).then(|| now + delay);
+        }
+        due
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn cancel(&mut self) {
+        self.queue.clear();
+        self.next = None;
+    }
+}
+
+/// The "Paste to MUD" window: text pasted in is sent line by line, each with an optional
+/// prefix, after a preview of what will go out.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PasteDialog {
+    #[serde(skip)]
+    pub open: bool,
+    #[serde(skip)]
+    text: String,
+    prefix: String,
+    delay_ms: u64,
+    skip_blank: bool,
+}
+
+impl Default for PasteDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            text: String::new(),
+            prefix: String::new(),
+            delay_ms: 100,
+            skip_blank: false,
+        }
+    }
+}
+
+impl PasteDialog {
+    /// Returns the lines to send and the pause between them when Send is clicked.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<(Vec<String>, Duration)> {
+        let mut open = self.open;
+        let mut send = None;
+        Window::new("Paste to MUD")
+            .open(&mut open)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                    ui.add(
+                        TextEdit::multiline(&mut self.text)
+                            .hint_text("Paste the text to send here")
+                            .desired_width(f32::INFINITY)
+                            .desired_rows(8),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Prefix each line with:");
+                    ui.text_edit_singleline(&mut self.prefix);
+                });
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut self.delay_ms).range(0..=10_000));
+                    ui.label("Delay between lines (ms)");
+                });
+                ui.checkbox(&mut self.skip_blank, "Leave out blank lines");
+                ui.separator();
+
+                let lines = self.lines();
+                ui.label(format!(
+                    "{} line{} will be sent:",
+                    lines.len(),
+                    if lines.len() == 1 { "" } else { "s" }
+                ));
+                for line in lines.iter().take(PREVIEW_LINES) {
+                    ui.monospace(line);
+                }
+                if lines.len() > PREVIEW_LINES {
+                    ui.weak(format!("… and {} more", lines.len() - PREVIEW_LINES));
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!lines.is_empty(), egui::Button::new("Send"))
+                        .clicked()
+                    {
+                        send = Some((lines, Duration::from_millis(self.delay_ms)));
+                        self.text.clear();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.open = false;
+                    }
+                });
+            });
+        self.open &= open && send.is_none();
+        send
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.text
+            .lines()
+            .filter(|line| !self.skip_blank || !line.trim().is_empty())
+            .map(|line| format!("{}{}", self.prefix, line))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn lines_without_delay_wait_behind_a_delayed_batch() {
+        let mut paced = PacedLines::default();
+        paced.push(lines(&["say one", "say two"]), Duration::from_secs(3600));
+        paced.push(lines(&["north", "south"]), Duration::ZERO);
+        assert_eq!(paced.due(), ["say one"]);
+        assert!(paced.due().is_empty());
+        assert_eq!(paced.pending(), 3);
+
+        paced.next = Some(Instant::now());
+        assert_eq!(paced.due(), ["say two"]);
+        assert!(paced.due().is_empty());
+
+        paced.next = Some(Instant::now());
+        assert_eq!(paced.due(), ["north", "south"]);
+        assert_eq!(paced.pending(), 0);
+        assert_eq!(paced.next, None);
+    }
+}
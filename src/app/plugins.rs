@@ -0,0 +1,1069 @@
+mod mushclient;
+pub mod native;
+
+use crate::app::functions::{init_lua, SendQueue};
+use crate::app::keybindings::{self, Accelerators};
+use crate::app::lua_execution::set_package_path;
+use crate::app::lua_panels::LuaPanels;
+use crate::app::mapper::{self, SharedMap};
+use crate::app::miniwindow::{self, HotspotCall, Miniwindows};
+use crate::app::script_errors::ScriptErrors;
+use crate::app::script_limits::{limited_lua, ScriptGuard};
+use crate::app::session_log::{self, LogFiles, SessionLog};
+use crate::app::telnet::TelnetClient;
+use egui::{CollapsingHeader, Color32, Grid, Window};
+use mlua::prelude::*;
+use mlua::StdLib;
+use native::{Manifest, Permission};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const PLUGIN_FOLDER: &str = "plugins";
+
+/// Lua states of the enabled plugins by id, used by `CallPlugin` and `BroadcastPlugin`.
+type PluginRegistry = Rc<RefCell<HashMap<String, PluginHandle>>>;
+
+struct PluginHandle {
+    lua: Weak<Lua>,
+    guard: ScriptGuard,
+    format: PluginFormat,
+}
+
+impl PluginHandle {
+    fn upgrade(&self) -> Option<(Rc<Lua>, ScriptGuard)> {
+        Some((self.lua.upgrade()?, self.guard.clone()))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PluginFormat {
+    /// A MUSHclient `.xml` plugin.
+    MushClient,
+    /// A MudForge plugin directory with a `plugin.toml` manifest.
+    Native,
+}
+
+/// Where the `send` text of a trigger, alias or timer goes, using MUSHclient's `send_to` codes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SendTo {
+    World,
+    Output,
+    Execute,
+    Script,
+}
+
+impl SendTo {
+    pub fn from_mushclient(code: u32) -> Self {
+        match code {
+            2 => SendTo::Output,
+            10 => SendTo::Execute,
+            12 => SendTo::Script,
+            _ => SendTo::World,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Action {
+    pub send: String,
+    pub send_to: SendTo,
+    pub script: String,
+}
+
+/// A trigger or alias: a pattern matched against incoming lines or typed commands.
+pub struct Matcher {
+    pub name: String,
+    pub group: String,
+    pub pattern: Regex,
+    pub enabled: bool,
+    pub sequence: i32,
+    pub keep_evaluating: bool,
+    pub omit_from_log: bool,
+    pub action: Action,
+}
+
+pub struct Timer {
+    pub name: String,
+    pub group: String,
+    pub enabled: bool,
+    pub interval: Duration,
+    pub active_closed: bool,
+    pub next_fire: Instant,
+    pub action: Action,
+}
+
+#[derive(Clone, Default)]
+pub struct PluginInfo {
+    pub id: String,
+    pub name: String,
+    pub author: String,
+    pub version: String,
+    pub purpose: String,
+    pub description: String,
+}
+
+/// Everything a plugin file declares, before it is given a Lua state.
+pub struct PluginDefinition {
+    pub info: PluginInfo,
+    pub triggers: Vec<Matcher>,
+    pub aliases: Vec<Matcher>,
+    pub timers: Vec<Timer>,
+    pub variables: HashMap<String, String>,
+    pub script: String,
+}
+
+/// The parts of a plugin its own scripts can change at runtime.
+#[derive(Default)]
+struct PluginState {
+    triggers: Vec<Matcher>,
+    aliases: Vec<Matcher>,
+    timers: Vec<Timer>,
+    variables: HashMap<String, String>,
+}
+
+/// A trigger or alias that matched, with its wildcards (index 0 is the whole match).
+struct Fired {
+    name: String,
+    wildcards: Vec<String>,
+    omit_from_log: bool,
+    action: Action,
+}
+
+fn match_all(matchers: &[Matcher], text: &str) -> Vec<Fired> {
+    let mut fired = Vec::new();
+    for matcher in matchers.iter().filter(|matcher| matcher.enabled) {
+        if let Some(captures) = matcher.pattern.captures(text) {
+            fired.push(Fired {
+                name: matcher.name.clone(),
+                wildcards: captures
+                    .iter()
+                    .map(|capture| capture.map_or(String::new(), |c| c.as_str().to_string()))
+                    .collect(),
+                omit_from_log: matcher.omit_from_log,
+                action: matcher.action.clone(),
+            });
+            if !matcher.keep_evaluating {
+                break;
+            }
+        }
+    }
+    fired
+}
+
+/// Replaces `%0`..`%9` with the matching wildcards and `%%` with a single `%`.
+fn expand_wildcards(text: &str, wildcards: &[String]) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('%') => {
+                chars.next();
+                expanded.push('%');
+            }
+            Some(digit @ '0'..='9') => {
+                chars.next();
+                let index = digit.to_digit(10).unwrap() as usize;
+                expanded.push_str(wildcards.get(index).map_or("", String::as_str));
+            }
+            _ => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+fn set_enabled<'a>(
+    items: impl Iterator<Item = (&'a str, &'a mut bool)>,
+    name: &str,
+    on: bool,
+) -> bool {
+    let mut found = false;
+    for (item_name, enabled) in items {
+        if item_name == name {
+            *enabled = on;
+            found = true;
+        }
+    }
+    found
+}
+
+/// What every plugin needs from the client: the world connection, the other plugins and
+/// somewhere to report errors.
+#[derive(Clone, Default)]
+pub struct PluginContext {
+    pub telnet_client: Arc<Mutex<TelnetClient>>,
+    pub errors: ScriptErrors,
+    pub miniwindows: Miniwindows,
+    pub session_log: SessionLog,
+    pub accelerators: Accelerators,
+    pub map: SharedMap,
+    pub sends: SendQueue,
+    registry: PluginRegistry,
+    executed: Rc<RefCell<Vec<String>>>,
+}
+
+impl PluginContext {
+    pub fn new(
+        telnet_client: Arc<Mutex<TelnetClient>>,
+        errors: ScriptErrors,
+        miniwindows: Miniwindows,
+        session_log: SessionLog,
+        accelerators: Accelerators,
+        map: SharedMap,
+        sends: SendQueue,
+    ) -> Self {
+        Self {
+            telnet_client,
+            errors,
+            miniwindows,
+            session_log,
+            accelerators,
+            map,
+            sends,
+            registry: PluginRegistry::default(),
+            executed: Rc::default(),
+        }
+    }
+}
+
+pub struct Plugin {
+    pub info: PluginInfo,
+    pub path: PathBuf,
+    pub format: PluginFormat,
+    pub enabled: bool,
+    lua: Rc<Lua>,
+    guard: ScriptGuard,
+    state: Arc<Mutex<PluginState>>,
+    panels: LuaPanels,
+    context: PluginContext,
+    /// Ids of the plugins a native plugin depends on, from its manifest.
+    dependencies: Vec<String>,
+    /// OnPluginClose has run, ahead of a reload.
+    closed: bool,
+}
+
+impl Plugin {
+    /// Loads a MUSHclient `.xml` file, or a native plugin when `path` is a directory.
+    pub fn load(path: &Path, context: &PluginContext) -> Result<Self, String> {
+        if path.is_dir() {
+            let manifest = native::read_manifest(path)?;
+            return Self::load_native(path, &manifest, context);
+        }
+
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read plugin: {}", e))?;
+        let definition = mushclient::parse(&mushclient::decode(bytes))?;
+        let (lua, guard) = limited_lua(StdLib::ALL_SAFE).map_err(|e| e.to_string())?;
+        init_lua(&lua, context.telnet_client.clone(), context.sends.clone())
+            .map_err(|e| e.to_string())?;
+        session_log::register_functions(&lua, context.session_log.clone(), LogFiles::Anywhere)
+            .map_err(|e| e.to_string())?;
+        keybindings::register_functions(&lua, context.accelerators.clone())
+            .map_err(|e| e.to_string())?;
+        mapper::register_functions(&lua, context.map.clone()).map_err(|e| e.to_string())?;
+        set_package_path(&lua).map_err(|e| e.to_string())?;
+        Self::from_definition(
+            definition,
+            lua,
+            guard,
+            path,
+            PluginFormat::MushClient,
+            context,
+        )
+    }
+
+    fn load_native(
+        path: &Path,
+        manifest: &Manifest,
+        context: &PluginContext,
+    ) -> Result<Self, String> {
+        let definition = native::definition(path, manifest)?;
+        let (lua, guard) = native::sandboxed_lua().map_err(|e| e.to_string())?;
+        init_lua(&lua, context.telnet_client.clone(), context.sends.clone())
+            .map_err(|e| e.to_string())?;
+        native::apply_permissions(&lua, path, &manifest.permissions).map_err(|e| e.to_string())?;
+        // Only plugins allowed to write files get the log functions, and open logs in their data
+        // folder.
+        let files = if manifest.permissions.contains(&Permission::Files) {
+            LogFiles::DataFolder(path.join(native::DATA_FOLDER))
+        } else {
+            LogFiles::Nowhere
+        };
+        session_log::register_functions(&lua, context.session_log.clone(), files)
+            .map_err(|e| e.to_string())?;
+        // Accelerators and mapper walks send commands, so they need the send permission too.
+        if manifest.permissions.contains(&Permission::Send) {
+            keybindings::register_functions(&lua, context.accelerators.clone())
+                .map_err(|e| e.to_string())?;
+            mapper::register_functions(&lua, context.map.clone()).map_err(|e| e.to_string())?;
+        }
+        let mut plugin =
+            Self::from_definition(definition, lua, guard, path, PluginFormat::Native, context)?;
+        plugin.dependencies = manifest.dependencies.clone();
+        Ok(plugin)
+    }
+
+    fn from_definition(
+        definition: PluginDefinition,
+        lua: Lua,
+        guard: ScriptGuard,
+        path: &Path,
+        format: PluginFormat,
+        context: &PluginContext,
+    ) -> Result<Self, String> {
+        let state = Arc::new(Mutex::new(PluginState {
+            triggers: definition.triggers,
+            aliases: definition.aliases,
+            timers: definition.timers,
+            variables: definition.variables,
+        }));
+
+        register_plugin_functions(&lua, &definition.info, state.clone())
+            .map_err(|e| e.to_string())?;
+        register_interplugin_functions(&lua, &definition.info, format, context.registry.clone())
+            .map_err(|e| e.to_string())?;
+        miniwindow::register_functions(&lua, context.miniwindows.clone(), &definition.info.id)
+            .map_err(|e| e.to_string())?;
+        let panels = LuaPanels::default();
+        panels.register(&lua).map_err(|e| e.to_string())?;
+        guard
+            .run(&lua, || {
+                lua.load(&definition.script)
+                    .set_name(path.display().to_string())
+                    .exec()
+            })
+            .map_err(|e| format!("Script error: {}", e))?;
+
+        let plugin = Self {
+            info: definition.info,
+            path: path.to_path_buf(),
+            format,
+            enabled: true,
+            lua: Rc::new(lua),
+            guard,
+            state,
+            panels,
+            context: context.clone(),
+            dependencies: Vec::new(),
+            closed: false,
+        };
+        plugin.register();
+        plugin.call_callback("OnPluginInstall", ());
+        Ok(plugin)
+    }
+
+    fn register(&self) {
+        self.context.registry.borrow_mut().insert(
+            self.info.id.clone(),
+            PluginHandle {
+                lua: Rc::downgrade(&self.lua),
+                guard: self.guard.clone(),
+                format: self.format,
+            },
+        );
+    }
+
+    fn disable(&mut self) {
+        self.call_callback("OnPluginDisable", ());
+        self.unregister();
+        self.enabled = false;
+    }
+
+    fn close(&mut self) {
+        if !self.closed {
+            self.call_callback("OnPluginClose", ());
+            self.unregister();
+            self.closed = true;
+        }
+    }
+
+    fn unregister(&self) {
+        let mut registry = self.context.registry.borrow_mut();
+        if let Some(handle) = registry.get(&self.info.id) {
+            if handle.lua.ptr_eq(&Rc::downgrade(&self.lua)) {
+                registry.remove(&self.info.id);
+            }
+        }
+    }
+
+    /// Calls a global callback if the plugin defines it, reporting any error.
+    fn call_callback<'lua>(
+        &'lua self,
+        name: &str,
+        args: impl IntoLuaMulti<'lua>,
+    ) -> Option<LuaValue<'lua>> {
+        let callback = self
+            .lua
+            .globals()
+            .get::<_, Option<LuaFunction<'_>>>(name)
+            .ok()
+            .flatten()?;
+        match self
+            .guard
+            .run(&self.lua, || callback.call::<_, LuaValue<'_>>(args))
+        {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.report_error(name, &e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Sends the full error to the script errors pane and a one-line notice to the output.
+    fn report_error(&self, context: &str, error: &str) {
+        let source = format!("Plugin {} ({})", self.info.name, context);
+        self.context.errors.report(&source, error);
+        let summary = error.lines().next().unwrap_or_default();
+        let message = format!("{}: {}\n", source, summary);
+        let mut telnet_client = self.context.telnet_client.lock().unwrap();
+        telnet_client.append_text(&message, Color32::RED);
+    }
+
+    fn run_action(&self, name: &str, line: &str, wildcards: &[String], action: &Action) {
+        if !action.send.is_empty() {
+            let text = expand_wildcards(&action.send, wildcards);
+            match action.send_to {
+                SendTo::World => {
+                    for command in text.lines() {
+                        self.context.sends.push(command);
+                    }
+                }
+                SendTo::Execute => {
+                    let mut executed = self.context.executed.borrow_mut();
+                    executed.extend(text.lines().map(str::to_string));
+                }
+                SendTo::Output => {
+                    let mut telnet_client = self.context.telnet_client.lock().unwrap();
+                    telnet_client.append_text(&format!("{}\n", text), Color32::WHITE);
+                }
+                SendTo::Script => {
+                    let result = self
+                        .guard
+                        .run(&self.lua, || self.lua.load(&text).set_name(name).exec());
+                    if let Err(e) = result {
+                        self.report_error(name, &e.to_string());
+                    }
+                }
+            }
+        }
+
+        if !action.script.is_empty() {
+            let function = self
+                .lua
+                .globals()
+                .get::<_, Option<LuaFunction<'_>>>(action.script.as_str());
+            let result = match function {
+                Ok(Some(function)) => self
+                    .lua
+                    .create_sequence_from(wildcards.iter().skip(1).cloned())
+                    .and_then(|table| {
+                        if let Some(whole) = wildcards.first() {
+                            table.raw_set(0, whole.as_str())?;
+                        }
+                        self.guard
+                            .run(&self.lua, || function.call::<_, ()>((name, line, table)))
+                    }),
+                _ => Err(LuaError::RuntimeError(format!(
+                    "script function {} not found",
+                    action.script
+                ))),
+            };
+            if let Err(e) = result {
+                self.report_error(&action.script, &e.to_string());
+            }
+        }
+    }
+
+    /// Runs matching triggers and returns true if one of them omits the line from the log.
+    fn line_received(&self, line: &str) -> bool {
+        self.call_callback("OnPluginLineReceived", line);
+        let fired = match_all(&self.state.lock().unwrap().triggers, line);
+        for trigger in &fired {
+            self.run_action(&trigger.name, line, &trigger.wildcards, &trigger.action);
+        }
+        fired.iter().any(|trigger| trigger.omit_from_log)
+    }
+
+    /// Runs matching aliases and returns true if any of them consumed the command.
+    fn command_entered(&self, command: &str) -> bool {
+        let fired = match_all(&self.state.lock().unwrap().aliases, command);
+        for alias in &fired {
+            self.run_action(&alias.name, command, &alias.wildcards, &alias.action);
+        }
+        !fired.is_empty()
+    }
+
+    fn tick(&self, now: Instant, connected: bool) {
+        let due: Vec<(String, Action)> = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .timers
+                .iter_mut()
+                .filter(|timer| timer.enabled && (connected || timer.active_closed))
+                .filter(|timer| now >= timer.next_fire)
+                .map(|timer| {
+                    timer.next_fire = now + timer.interval;
+                    (timer.name.clone(), timer.action.clone())
+                })
+                .collect()
+        };
+        for (name, action) in due {
+            self.run_action(&name, "", &[], &action);
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Functions that only make sense inside a plugin's own Lua state.
+fn register_plugin_functions(
+    lua: &Lua,
+    info: &PluginInfo,
+    state: Arc<Mutex<PluginState>>,
+) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    let id = info.id.clone();
+    globals.set(
+        "GetPluginID",
+        lua.create_function(move |_, ()| Ok(id.clone()))?,
+    )?;
+
+    let name = info.name.clone();
+    globals.set(
+        "GetPluginName",
+        lua.create_function(move |_, ()| Ok(name.clone()))?,
+    )?;
+
+    let variables_state = state.clone();
+    globals.set(
+        "GetVariable",
+        lua.create_function(move |_, name: String| {
+            Ok(variables_state
+                .lock()
+                .unwrap()
+                .variables
+                .get(&name)
+                .cloned())
+        })?,
+    )?;
+
+    let variables_state = state.clone();
+    globals.set(
+        "SetVariable",
+        lua.create_function(move |_, (name, value): (String, String)| {
+            variables_state
+                .lock()
+                .unwrap()
+                .variables
+                .insert(name, value);
+            Ok(())
+        })?,
+    )?;
+
+    let variables_state = state.clone();
+    globals.set(
+        "DeleteVariable",
+        lua.create_function(move |_, name: String| {
+            Ok(variables_state
+                .lock()
+                .unwrap()
+                .variables
+                .remove(&name)
+                .is_some())
+        })?,
+    )?;
+
+    let trigger_state = state.clone();
+    globals.set(
+        "EnableTrigger",
+        lua.create_function(move |_, (name, on): (String, Option<bool>)| {
+            let mut state = trigger_state.lock().unwrap();
+            let triggers = state
+                .triggers
+                .iter_mut()
+                .map(|trigger| (trigger.name.as_str(), &mut trigger.enabled));
+            Ok(set_enabled(triggers, &name, on.unwrap_or(true)))
+        })?,
+    )?;
+
+    let alias_state = state.clone();
+    globals.set(
+        "EnableAlias",
+        lua.create_function(move |_, (name, on): (String, Option<bool>)| {
+            let mut state = alias_state.lock().unwrap();
+            let aliases = state
+                .aliases
+                .iter_mut()
+                .map(|alias| (alias.name.as_str(), &mut alias.enabled));
+            Ok(set_enabled(aliases, &name, on.unwrap_or(true)))
+        })?,
+    )?;
+
+    let timer_state = state.clone();
+    globals.set(
+        "EnableTimer",
+        lua.create_function(move |_, (name, on): (String, Option<bool>)| {
+            let mut state = timer_state.lock().unwrap();
+            let timers = state
+                .timers
+                .iter_mut()
+                .map(|timer| (timer.name.as_str(), &mut timer.enabled));
+            Ok(set_enabled(timers, &name, on.unwrap_or(true)))
+        })?,
+    )?;
+
+    globals.set(
+        "EnableGroup",
+        lua.create_function(move |_, (group, on): (String, Option<bool>)| {
+            let mut state = state.lock().unwrap();
+            let state = &mut *state;
+            let on = on.unwrap_or(true);
+            let matchers = state.triggers.iter_mut().chain(state.aliases.iter_mut());
+            let found = set_enabled(
+                matchers.map(|matcher| (matcher.group.as_str(), &mut matcher.enabled)),
+                &group,
+                on,
+            );
+            let timers = state
+                .timers
+                .iter_mut()
+                .map(|timer| (timer.group.as_str(), &mut timer.enabled));
+            Ok(set_enabled(timers, &group, on) || found)
+        })?,
+    )?;
+
+    Ok(())
+}
+
+/// Copies a value from one plugin's Lua state into another's.
+///
+/// Only strings, numbers, booleans and tables of those can cross between plugins.
+fn copy_value<'to>(value: LuaValue<'_>, to: &'to Lua, depth: usize) -> LuaResult<LuaValue<'to>> {
+    Ok(match value {
+        LuaValue::Nil => LuaValue::Nil,
+        LuaValue::Boolean(b) => LuaValue::Boolean(b),
+        LuaValue::Integer(i) => LuaValue::Integer(i),
+        LuaValue::Number(n) => LuaValue::Number(n),
+        LuaValue::String(s) => LuaValue::String(to.create_string(s.as_bytes())?),
+        LuaValue::Table(table) if depth < 16 => {
+            let copy = to.create_table()?;
+            for pair in table.pairs::<LuaValue<'_>, LuaValue<'_>>() {
+                let (key, value) = pair?;
+                copy.raw_set(
+                    copy_value(key, to, depth + 1)?,
+                    copy_value(value, to, depth + 1)?,
+                )?;
+            }
+            LuaValue::Table(copy)
+        }
+        other => {
+            return Err(LuaError::RuntimeError(format!(
+                "cannot pass a {} between plugins",
+                other.type_name()
+            )))
+        }
+    })
+}
+
+/// `CallPlugin` and `BroadcastPlugin`, shared by both plugin formats. Sandboxed native plugins
+/// cannot reach MUSHclient plugins, whose unrestricted state would let them escape the sandbox.
+fn register_interplugin_functions(
+    lua: &Lua,
+    info: &PluginInfo,
+    format: PluginFormat,
+    registry: PluginRegistry,
+) -> LuaResult<()> {
+    let sandboxed = format == PluginFormat::Native;
+    let globals = lua.globals();
+
+    let call_registry = registry.clone();
+    globals.set(
+        "CallPlugin",
+        lua.create_function(
+            move |lua, (id, function, args): (String, String, LuaMultiValue<'_>)| {
+                let target = match call_registry.borrow().get(&id) {
+                    Some(handle) if sandboxed && handle.format == PluginFormat::MushClient => {
+                        return Err(LuaError::RuntimeError(format!(
+                            "native plugins cannot call MUSHclient plugin {}",
+                            id
+                        )));
+                    }
+                    handle => handle.and_then(PluginHandle::upgrade),
+                };
+                let (target, guard) = target.ok_or_else(|| {
+                    LuaError::RuntimeError(format!("plugin {} is not installed or enabled", id))
+                })?;
+                let callee = target
+                    .globals()
+                    .get::<_, Option<LuaFunction<'_>>>(function.as_str())?
+                    .ok_or_else(|| {
+                        LuaError::RuntimeError(format!(
+                            "plugin {} has no function {}",
+                            id, function
+                        ))
+                    })?;
+                let args = args
+                    .into_iter()
+                    .map(|arg| copy_value(arg, &target, 0))
+                    .collect::<LuaResult<Vec<_>>>()?;
+                let results = guard.run(&target, || {
+                    callee.call::<_, LuaMultiValue<'_>>(LuaMultiValue::from_vec(args))
+                })?;
+                results
+                    .into_iter()
+                    .map(|result| copy_value(result, lua, 0))
+                    .collect::<LuaResult<Vec<_>>>()
+                    .map(LuaMultiValue::from_vec)
+            },
+        )?,
+    )?;
+
+    let id = info.id.clone();
+    let name = info.name.clone();
+    globals.set(
+        "BroadcastPlugin",
+        lua.create_function(move |_, (message, text): (i64, Option<String>)| {
+            let targets: Vec<(Rc<Lua>, ScriptGuard)> = registry
+                .borrow()
+                .iter()
+                .filter(|(target_id, _)| **target_id != id)
+                .filter(|(_, handle)| !sandboxed || handle.format == PluginFormat::Native)
+                .filter_map(|(_, handle)| handle.upgrade())
+                .collect();
+            let text = text.unwrap_or_default();
+            for (target, guard) in targets {
+                let callback = target
+                    .globals()
+                    .get::<_, Option<LuaFunction<'_>>>("OnPluginBroadcast")?;
+                if let Some(callback) = callback {
+                    guard.run(&target, || {
+                        callback.call::<_, ()>((message, id.as_str(), name.as_str(), text.as_str()))
+                    })?;
+                }
+            }
+            Ok(())
+        })?,
+    )?;
+
+    Ok(())
+}
+
+enum PluginCommand {
+    Enable(usize),
+    Disable(usize),
+    Reload(usize),
+}
+
+#[derive(Default)]
+pub struct PluginManager {
+    pub plugins: Vec<Plugin>,
+    pub load_errors: Vec<String>,
+    pub open: bool,
+    context: PluginContext,
+}
+
+impl PluginManager {
+    /// Loads every `.xml` plugin in `folder` in file name order, then every native plugin
+    /// directory after the plugins it depends on.
+    pub fn load_directory(&mut self, folder: &str, context: PluginContext) {
+        self.context = context;
+        let mut paths: Vec<PathBuf> = match fs::read_dir(folder) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect(),
+            Err(e) => {
+                eprintln!("Failed to read plugin directory: {}", e);
+                return;
+            }
+        };
+        paths.sort();
+
+        let mut manifests = Vec::new();
+        for path in paths {
+            if path.join(native::MANIFEST_FILE).is_file() {
+                match native::read_manifest(&path) {
+                    Ok(manifest) => manifests.push((path, manifest)),
+                    Err(e) => self.load_errors.push(format!("{}: {}", path.display(), e)),
+                }
+            } else if path.extension().and_then(|ext| ext.to_str()) == Some("xml") {
+                match Plugin::load(&path, &self.context) {
+                    Ok(plugin) => self.plugins.push(plugin),
+                    Err(e) => self.load_errors.push(format!("{}: {}", path.display(), e)),
+                }
+            }
+        }
+
+        let (ordered, errors) = native::load_order(manifests, &self.loaded_ids());
+        self.load_errors.extend(errors);
+        for (path, manifest) in ordered {
+            let loaded = self.loaded_ids();
+            if let Some(missing) = manifest
+                .dependencies
+                .iter()
+                .find(|dependency| !loaded.contains(*dependency))
+            {
+                self.load_errors.push(format!(
+                    "{}: dependency {} failed to load",
+                    path.display(),
+                    missing
+                ));
+                continue;
+            }
+            match Plugin::load_native(&path, &manifest, &self.context) {
+                Ok(plugin) => self.plugins.push(plugin),
+                Err(e) => self.load_errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    fn loaded_ids(&self) -> HashSet<String> {
+        self.plugins
+            .iter()
+            .map(|plugin| plugin.info.id.clone())
+            .collect()
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &Plugin> {
+        self.plugins.iter().filter(|plugin| plugin.enabled)
+    }
+
+    /// Removes the text trigger and alias actions sent to Execute, for the client to run as if
+    /// typed.
+    pub fn take_executed(&self) -> Vec<String> {
+        std::mem::take(&mut self.context.executed.borrow_mut())
+    }
+
+    pub fn on_connect(&self) {
+        for plugin in self.enabled() {
+            plugin.call_callback("OnPluginConnect", ());
+        }
+    }
+
+    /// Offers a received line to plugin triggers. Returns true if it should be left out of the log.
+    pub fn line_received(&self, line: &str) -> bool {
+        let mut omit = false;
+        for plugin in self.enabled() {
+            omit |= plugin.line_received(line);
+        }
+        omit
+    }
+
+    /// Offers a typed command to plugin aliases. Returns true if one of them handled it.
+    pub fn command_entered(&self, command: &str) -> bool {
+        self.enabled().any(|plugin| plugin.command_entered(command))
+    }
+
+    /// Asks `OnPluginSend` whether the command may go to the world; any plugin returning false vetoes it.
+    pub fn allow_send(&self, command: &str) -> bool {
+        self.enabled().all(|plugin| {
+            !matches!(
+                plugin.call_callback("OnPluginSend", command),
+                Some(LuaValue::Boolean(false))
+            )
+        })
+    }
+
+    /// Collects the words plugins offer from `OnPluginTabComplete(word)` to complete `word`.
+    pub fn tab_completions(&self, word: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        for plugin in self.enabled() {
+            if let Some(LuaValue::Table(table)) = plugin.call_callback("OnPluginTabComplete", word)
+            {
+                words.extend(
+                    table
+                        .sequence_values::<String>()
+                        .filter_map(|word| word.ok()),
+                );
+            }
+        }
+        words
+    }
+
+    pub fn sent(&self, command: &str) {
+        for plugin in self.enabled() {
+            plugin.call_callback("OnPluginSent", command);
+        }
+    }
+
+    pub fn tick(&self, connected: bool) {
+        let now = Instant::now();
+        for plugin in self.enabled() {
+            plugin.tick(now, connected);
+        }
+    }
+
+    pub fn show_panels(&self, ui: &mut egui::Ui) {
+        for plugin in self.enabled() {
+            plugin.panels.show(
+                ui,
+                &plugin.lua,
+                &plugin.guard,
+                &plugin.context.errors,
+                &plugin.info.id,
+            );
+        }
+    }
+
+    /// Lists the variables of every enabled plugin.
+    pub fn variables_ui(&self, ui: &mut egui::Ui) {
+        for plugin in self.enabled() {
+            let state = plugin.state.lock().unwrap();
+            let mut variables: Vec<_> = state.variables.iter().collect();
+            variables.sort();
+            CollapsingHeader::new(format!("{} ({})", plugin.info.name, variables.len()))
+                .id_source(("plugin_variables", &plugin.info.id))
+                .show(ui, |ui| {
+                    Grid::new(("plugin_variables_grid", &plugin.info.id))
+                        .num_columns(2)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (name, value) in variables {
+                                ui.monospace(name);
+                                ui.label(value);
+                                ui.end_row();
+                            }
+                        });
+                });
+        }
+        if self.plugins.is_empty() {
+            ui.weak("No plugins loaded.");
+        }
+    }
+
+    /// Runs a miniwindow hotspot callback in the plugin that added the hotspot.
+    pub fn hotspot_callback(&self, call: &HotspotCall) {
+        if let Some(plugin) = self.enabled().find(|plugin| plugin.info.id == call.owner) {
+            plugin.call_callback(&call.callback, (call.flags, call.hotspot_id.as_str()));
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let mut open = self.open;
+        let mut command = None;
+
+        Window::new("Plugins")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                Grid::new("plugin_list")
+                    .num_columns(5)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Name");
+                        ui.strong("Version");
+                        ui.strong("Author");
+                        ui.strong("Type");
+                        ui.strong("");
+                        ui.end_row();
+
+                        for (index, plugin) in self.plugins.iter().enumerate() {
+                            let name = ui.label(&plugin.info.name);
+                            if !plugin.info.description.is_empty() {
+                                name.on_hover_text(&plugin.info.description);
+                            } else if !plugin.info.purpose.is_empty() {
+                                name.on_hover_text(&plugin.info.purpose);
+                            }
+                            ui.label(&plugin.info.version);
+                            ui.label(&plugin.info.author);
+                            ui.label(match plugin.format {
+                                PluginFormat::MushClient => "MUSHclient",
+                                PluginFormat::Native => "Native",
+                            });
+                            ui.horizontal(|ui| {
+                                if plugin.enabled {
+                                    if ui.button("Disable").clicked() {
+                                        command = Some(PluginCommand::Disable(index));
+                                    }
+                                } else if ui.button("Enable").clicked() {
+                                    command = Some(PluginCommand::Enable(index));
+                                }
+                                if ui.button("Reload").clicked() {
+                                    command = Some(PluginCommand::Reload(index));
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+
+                if self.plugins.is_empty() {
+                    ui.label(format!("No plugins found in the {} folder.", PLUGIN_FOLDER));
+                }
+
+                for error in &self.load_errors {
+                    ui.colored_label(Color32::RED, error);
+                }
+            });
+
+        self.open = open;
+        if let Some(command) = command {
+            self.run_command(command);
+        }
+    }
+
+    fn run_command(&mut self, command: PluginCommand) {
+        match command {
+            PluginCommand::Enable(index) => {
+                let plugin = &mut self.plugins[index];
+                plugin.enabled = true;
+                plugin.register();
+                plugin.call_callback("OnPluginEnable", ());
+            }
+            PluginCommand::Disable(index) => self.plugins[index].disable(),
+            PluginCommand::Reload(index) => {
+                // Plugins that depend on this one keep values from its old state, so reload
+                // them after it, in load order.
+                let mut ids = vec![self.plugins[index].info.id.clone()];
+                for plugin in &self.plugins[index + 1..] {
+                    if plugin.dependencies.iter().any(|id| ids.contains(id)) {
+                        ids.push(plugin.info.id.clone());
+                    }
+                }
+                for id in ids {
+                    if let Some(index) = self.plugins.iter().position(|p| p.info.id == id) {
+                        self.reload(index);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reloads a plugin from its file, keeping it enabled or disabled. If the new version
+    /// fails to load the old one stays in the list, so it can be fixed and reloaded again.
+    fn reload(&mut self, index: usize) {
+        let old = &mut self.plugins[index];
+        let path = old.path.clone();
+        let enabled = old.enabled;
+        // Close the old state first so OnPluginClose runs before OnPluginInstall.
+        old.close();
+        let prefix = format!("{}: ", path.display());
+        self.load_errors.retain(|error| !error.starts_with(&prefix));
+        match Plugin::load(&path, &self.context) {
+            Ok(mut plugin) => {
+                if !enabled {
+                    plugin.disable();
+                }
+                self.plugins[index] = plugin;
+            }
+            Err(e) => {
+                if enabled {
+                    self.plugins[index].register();
+                }
+                self.load_errors.push(format!("{}{}", prefix, e));
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use super::{Action, Matcher, PluginDefinition, PluginInfo, SendTo, Timer};
+use regex::Regex;
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// MUSHclient writes plugins as ISO-8859-1, so fall back to Latin-1 when the file is not UTF-8.
+pub fn decode(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes)
+        .unwrap_or_else(|e| e.into_bytes().iter().map(|&byte| byte as char).collect())
+}
+
+/// Parses a MUSHclient plugin `.xml` file into a plugin definition.
+pub fn parse(source: &str) -> Result<PluginDefinition, String> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let document = Document::parse_with_options(source, options)
+        .map_err(|e| format!("Invalid plugin XML: {}", e))?;
+
+    let mut info = None;
+    let mut triggers = Vec::new();
+    let mut aliases = Vec::new();
+    let mut timers = Vec::new();
+    let mut variables = HashMap::new();
+    let mut script = String::new();
+
+    for section in document.root_element().children().filter(Node::is_element) {
+        match section.tag_name().name() {
+            "plugin" => info = Some(parse_info(section)?),
+            "triggers" => {
+                for node in children_named(section, "trigger") {
+                    triggers.push(parse_matcher(node, "trigger")?);
+                }
+            }
+            "aliases" => {
+                for node in children_named(section, "alias") {
+                    aliases.push(parse_matcher(node, "alias")?);
+                }
+            }
+            "timers" => {
+                for node in children_named(section, "timer") {
+                    if let Some(timer) = parse_timer(node)? {
+                        timers.push(timer);
+                    }
+                }
+            }
+            "variables" => {
+                for node in children_named(section, "variable") {
+                    if let Some(name) = node.attribute("name") {
+                        variables.insert(name.to_string(), node_text(node));
+                    }
+                }
+            }
+            "script" => script.push_str(&node_text(section)),
+            _ => {}
+        }
+    }
+
+    triggers.sort_by_key(|trigger| trigger.sequence);
+    aliases.sort_by_key(|alias| alias.sequence);
+
+    Ok(PluginDefinition {
+        info: info.ok_or("Missing <plugin> section")?,
+        triggers,
+        aliases,
+        timers,
+        variables,
+        script,
+    })
+}
+
+fn parse_info(node: Node<'_, '_>) -> Result<PluginInfo, String> {
+    let name = node
+        .attribute("name")
+        .ok_or("<plugin> has no name attribute")?;
+    if let Some(language) = node.attribute("language") {
+        if !language.eq_ignore_ascii_case("lua") {
+            return Err(format!("Unsupported script language: {}", language));
+        }
+    }
+    Ok(PluginInfo {
+        id: node.attribute("id").unwrap_or(name).to_string(),
+        name: name.to_string(),
+        author: node.attribute("author").unwrap_or_default().to_string(),
+        version: node.attribute("version").unwrap_or_default().to_string(),
+        purpose: node.attribute("purpose").unwrap_or_default().to_string(),
+        description: children_named(node, "description")
+            .next()
+            .map(|description| node_text(description).trim().to_string())
+            .unwrap_or_default(),
+    })
+}
+
+fn parse_matcher(node: Node<'_, '_>, kind: &str) -> Result<Matcher, String> {
+    let pattern = node
+        .attribute("match")
+        .ok_or_else(|| format!("<{}> has no match attribute", kind))?;
+    let pattern = if flag(node, "regexp", false) {
+        pattern.to_string()
+    } else {
+        wildcard_to_regex(pattern)
+    };
+    let pattern = if flag(node, "ignore_case", false) {
+        format!("(?i){}", pattern)
+    } else {
+        pattern
+    };
+
+    Ok(Matcher {
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        group: node.attribute("group").unwrap_or_default().to_string(),
+        pattern: Regex::new(&pattern).map_err(|e| format!("Bad {} pattern: {}", kind, e))?,
+        enabled: flag(node, "enabled", true),
+        sequence: number(node, "sequence").unwrap_or(100.0) as i32,
+        keep_evaluating: flag(node, "keep_evaluating", false),
+        omit_from_log: flag(node, "omit_from_log", false),
+        action: parse_action(node),
+    })
+}
+
+fn parse_timer(node: Node<'_, '_>) -> Result<Option<Timer>, String> {
+    // Timers that fire at a time of day are not supported yet.
+    if flag(node, "at_time", false) {
+        return Ok(None);
+    }
+    let seconds = number(node, "hour").unwrap_or(0.0) * 3600.0
+        + number(node, "minute").unwrap_or(0.0) * 60.0
+        + number(node, "second").unwrap_or(0.0);
+    if seconds <= 0.0 {
+        return Err(format!(
+            "Timer {:?} has no interval",
+            node.attribute("name").unwrap_or_default()
+        ));
+    }
+    let interval = Duration::from_secs_f64(seconds);
+
+    Ok(Some(Timer {
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        group: node.attribute("group").unwrap_or_default().to_string(),
+        enabled: flag(node, "enabled", true),
+        interval,
+        active_closed: flag(node, "active_closed", false),
+        next_fire: Instant::now() + interval,
+        action: parse_action(node),
+    }))
+}
+
+fn parse_action(node: Node<'_, '_>) -> Action {
+    Action {
+        send: children_named(node, "send")
+            .next()
+            .map(node_text)
+            .unwrap_or_default(),
+        send_to: SendTo::from_mushclient(number(node, "send_to").unwrap_or(0.0) as u32),
+        script: node.attribute("script").unwrap_or_default().to_string(),
+    }
+}
+
+/// Converts a MUSHclient `*` wildcard pattern into an anchored regular expression.
+fn wildcard_to_regex(pattern: &str) -> String {
+    let parts: Vec<String> = pattern.split('*').map(regex::escape).collect();
+    format!("^{}$", parts.join("(.*?)"))
+}
+
+fn children_named<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn node_text(node: Node<'_, '_>) -> String {
+    node.children().filter_map(|child| child.text()).collect()
+}
+
+fn flag(node: Node<'_, '_>, name: &str, default: bool) -> bool {
+    match node.attribute(name) {
+        Some(value) => matches!(value, "y" | "Y" | "yes" | "1" | "true"),
+        None => default,
+    }
+}
+
+fn number(node: Node<'_, '_>, name: &str) -> Option<f64> {
+    node.attribute(name)
+        .and_then(|value| value.trim().parse().ok())
+}
@@ -0,0 +1,210 @@
+use super::{PluginDefinition, PluginInfo};
+use crate::app::script_limits::{limited_lua, ScriptGuard};
+use mlua::prelude::*;
+use mlua::StdLib;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "plugin.toml";
+pub const DATA_FOLDER: &str = "data";
+
+/// The `plugin.toml` at the root of a native plugin directory.
+#[derive(serde::Deserialize)]
+pub struct Manifest {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_entry")]
+    pub entry: String,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+fn default_entry() -> String {
+    "main.lua".to_string()
+}
+
+/// Capabilities a native plugin has to ask for in its manifest.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Send commands to the world with `Send`, accelerators and the mapper.
+    Send,
+    /// Read and write files inside the plugin's `data` folder.
+    Files,
+}
+
+pub fn read_manifest(dir: &Path) -> Result<Manifest, String> {
+    let source = fs::read_to_string(dir.join(MANIFEST_FILE))
+        .map_err(|e| format!("Failed to read {}: {}", MANIFEST_FILE, e))?;
+    toml::from_str(&source).map_err(|e| format!("Invalid {}: {}", MANIFEST_FILE, e))
+}
+
+/// Reads the entry script named by the manifest into a plugin definition.
+pub fn definition(dir: &Path, manifest: &Manifest) -> Result<PluginDefinition, String> {
+    let entry = relative_path(dir, &manifest.entry)
+        .ok_or_else(|| format!("Entry script {:?} is outside the plugin", manifest.entry))?;
+    let script = fs::read_to_string(&entry)
+        .map_err(|e| format!("Failed to read {}: {}", manifest.entry, e))?;
+
+    Ok(PluginDefinition {
+        info: PluginInfo {
+            id: manifest.id.clone(),
+            name: if manifest.name.is_empty() {
+                manifest.id.clone()
+            } else {
+                manifest.name.clone()
+            },
+            author: manifest.author.clone(),
+            version: manifest.version.clone(),
+            purpose: String::new(),
+            description: manifest.description.clone(),
+        },
+        triggers: Vec::new(),
+        aliases: Vec::new(),
+        timers: Vec::new(),
+        variables: HashMap::new(),
+        script,
+    })
+}
+
+/// A Lua state without `require`, `io` or `debug`; only the pure-computation libraries are loaded.
+pub fn sandboxed_lua() -> LuaResult<(Lua, ScriptGuard)> {
+    let libs = StdLib::COROUTINE
+        | StdLib::TABLE
+        | StdLib::OS
+        | StdLib::STRING
+        | StdLib::UTF8
+        | StdLib::BIT
+        | StdLib::MATH;
+    limited_lua(libs)
+}
+
+/// Removes or adds the global functions gated behind manifest permissions.
+pub fn apply_permissions(lua: &Lua, dir: &Path, permissions: &[Permission]) -> LuaResult<()> {
+    let globals = lua.globals();
+    if !permissions.contains(&Permission::Send) {
+        globals.set("Send", LuaNil)?;
+    }
+    if permissions.contains(&Permission::Files) {
+        let data_dir = dir.join(DATA_FOLDER);
+        fs::create_dir_all(&data_dir).map_err(LuaError::external)?;
+        register_file_functions(lua, data_dir)?;
+    }
+    Ok(())
+}
+
+fn register_file_functions(lua: &Lua, data_dir: PathBuf) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    let read_dir = data_dir.clone();
+    globals.set(
+        "ReadFile",
+        lua.create_function(move |_, name: String| {
+            let path = data_path(&read_dir, &name)?;
+            match fs::read_to_string(path) {
+                Ok(text) => Ok(Some(text)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(LuaError::external(e)),
+            }
+        })?,
+    )?;
+
+    let write_dir = data_dir.clone();
+    globals.set(
+        "WriteFile",
+        lua.create_function(move |_, (name, text): (String, String)| {
+            let path = data_path(&write_dir, &name)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(LuaError::external)?;
+            }
+            fs::write(path, text).map_err(LuaError::external)
+        })?,
+    )?;
+
+    globals.set(
+        "DeleteFile",
+        lua.create_function(move |_, name: String| {
+            let path = data_path(&data_dir, &name)?;
+            Ok(fs::remove_file(path).is_ok())
+        })?,
+    )?;
+
+    Ok(())
+}
+
+/// Resolves a file name given by a plugin inside its data folder.
+pub fn data_path(data_dir: &Path, name: &str) -> LuaResult<PathBuf> {
+    relative_path(data_dir, name).ok_or_else(|| {
+        LuaError::RuntimeError(format!("{:?} is outside the plugin data folder", name))
+    })
+}
+
+/// Joins `name` onto `base`, refusing absolute paths and `..` so the result stays inside `base`.
+fn relative_path(base: &Path, name: &str) -> Option<PathBuf> {
+    let name = Path::new(name);
+    let inside = name
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if inside && name.components().next().is_some() {
+        Some(base.join(name))
+    } else {
+        None
+    }
+}
+
+/// Orders native plugins so each one loads after its dependencies.
+///
+/// `loaded` holds the ids that are already available. Plugins whose dependencies are missing
+/// or circular are returned as errors instead.
+pub fn load_order(
+    mut pending: Vec<(PathBuf, Manifest)>,
+    loaded: &HashSet<String>,
+) -> (Vec<(PathBuf, Manifest)>, Vec<String>) {
+    let mut available = loaded.clone();
+    let mut ordered = Vec::new();
+
+    loop {
+        let (ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(_, manifest)| {
+            manifest
+                .dependencies
+                .iter()
+                .all(|dependency| available.contains(dependency))
+        });
+        pending = waiting;
+        if ready.is_empty() {
+            break;
+        }
+        for (path, manifest) in ready {
+            available.insert(manifest.id.clone());
+            ordered.push((path, manifest));
+        }
+    }
+
+    let errors = pending
+        .iter()
+        .map(|(path, manifest)| {
+            let missing: Vec<&str> = manifest
+                .dependencies
+                .iter()
+                .filter(|dependency| !available.contains(*dependency))
+                .map(String::as_str)
+                .collect();
+            format!(
+                "{}: unmet dependencies {}",
+                path.display(),
+                missing.join(", ")
+            )
+        })
+        .collect();
+
+    (ordered, errors)
+}
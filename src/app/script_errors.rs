@@ -0,0 +1,68 @@
+use egui::{CollapsingHeader, Color32, ScrollArea};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+const MAX_ERRORS: usize = 200;
+
+pub struct ScriptError {
+    pub number: usize,
+    pub source: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct ErrorLog {
+    errors: VecDeque<ScriptError>,
+    total: usize,
+}
+
+/// Errors raised by Lua scripts and plugins, shared by everything that runs Lua.
+#[derive(Clone, Default)]
+pub struct ScriptErrors {
+    log: Arc<Mutex<ErrorLog>>,
+}
+
+impl ScriptErrors {
+    /// Records an error with its full message and traceback.
+    pub fn report(&self, source: &str, message: &str) {
+        let mut log = self.log.lock().unwrap();
+        log.total += 1;
+        let number = log.total;
+        log.errors.push_back(ScriptError {
+            number,
+            source: source.to_string(),
+            message: message.to_string(),
+        });
+        if log.errors.len() > MAX_ERRORS {
+            log.errors.pop_front();
+        }
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let mut log = self.log.lock().unwrap();
+        if ui.button("Clear").clicked() {
+            log.errors.clear();
+        }
+        ui.separator();
+
+        if log.errors.is_empty() {
+            ui.label("No script errors.");
+        }
+        ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for error in log.errors.iter() {
+                    let summary = error.message.lines().next().unwrap_or_default();
+                    CollapsingHeader::new(format!(
+                        "#{} {}: {}",
+                        error.number, error.source, summary
+                    ))
+                    .id_source(error.number)
+                    .show(ui, |ui| {
+                        ui.colored_label(Color32::RED, &error.message);
+                    });
+                }
+            });
+    }
+}
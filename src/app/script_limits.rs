@@ -0,0 +1,68 @@
+use mlua::{Lua, LuaOptions, Result, StdLib, VmState};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How long one call from the client into Lua may run before it is aborted.
+pub const TIME_LIMIT: Duration = Duration::from_secs(1);
+/// How much memory one Lua state may allocate.
+pub const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Aborts a Lua state's running code once the current call passes its deadline.
+///
+/// The deadline is armed by [`ScriptGuard::run`], so code only counts against the limit while
+/// the client is waiting on it.
+#[derive(Clone)]
+pub struct ScriptGuard {
+    deadline: Rc<Cell<Option<Instant>>>,
+}
+
+impl ScriptGuard {
+    pub fn install(lua: &Lua) -> Result<Self> {
+        lua.set_memory_limit(MEMORY_LIMIT)?;
+
+        let deadline: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+        let interrupt_deadline = deadline.clone();
+        lua.set_interrupt(move |_| match interrupt_deadline.get() {
+            Some(deadline) if Instant::now() > deadline => Err(mlua::Error::RuntimeError(format!(
+                "script exceeded the {} ms time limit",
+                TIME_LIMIT.as_millis()
+            ))),
+            _ => Ok(VmState::Continue),
+        });
+
+        Ok(Self { deadline })
+    }
+
+    /// Runs `f` against `lua` with the deadline armed. Nested calls share the outermost deadline.
+    ///
+    /// After an error the state is collected straight away: if it ran out of memory, Luau cannot
+    /// even load the next chunk until the garbage is gone.
+    pub fn run<T>(&self, lua: &Lua, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let outer = self.deadline.get();
+        if outer.is_none() {
+            self.deadline.set(Some(Instant::now() + TIME_LIMIT));
+        }
+        let result = f();
+        self.deadline.set(outer);
+
+        if result.is_err() {
+            let _ = lua.gc_collect();
+            let _ = lua.gc_collect();
+        }
+        result.map_err(|e| match e {
+            mlua::Error::MemoryError(_) => mlua::Error::MemoryError(format!(
+                "script exceeded the {} MB memory limit",
+                MEMORY_LIMIT / (1024 * 1024)
+            )),
+            e => e,
+        })
+    }
+}
+
+/// Creates a Lua state with the time and memory limits already installed.
+pub fn limited_lua(libs: StdLib) -> Result<(Lua, ScriptGuard)> {
+    let lua = Lua::new_with(libs, LuaOptions::default())?;
+    let guard = ScriptGuard::install(&lua)?;
+    Ok((lua, guard))
+}
@@ -0,0 +1,406 @@
+use egui::{Color32, Key, Modifiers, TextEdit, Ui};
+use regex::Regex;
+use std::ops::Range;
+
+const MATCH_BACKGROUND: Color32 = Color32::from_rgb(90, 80, 0);
+const CURRENT_BACKGROUND: Color32 = Color32::from_rgb(200, 120, 0);
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    Plain,
+    IgnoreCase,
+    Regex,
+}
+
+/// A hit, as the byte ranges it covers in the plain text of each output entry it spans. Text
+/// arrives in chunks, so one line can be spread over several entries.
+struct Match {
+    pieces: Vec<(usize, Range<usize>)>,
+}
+
+impl Match {
+    fn first(&self) -> usize {
+        self.pieces[0].0
+    }
+
+    fn last(&self) -> usize {
+        self.pieces[self.pieces.len() - 1].0
+    }
+}
+
+/// Where a part of the line being searched came from: its start in the line, the output entry
+/// and its offset in that entry's plain text.
+struct Segment {
+    start: usize,
+    entry: usize,
+    offset: usize,
+}
+
+/// Ctrl+F search over the output scrollback. The output entries are split into lines at `\n`
+/// and each line is searched whole, across color boundaries and the chunks it arrived in, so
+/// `^` and `$` anchor to its ends. New lines are searched as they arrive.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ScrollbackSearch {
+    #[serde(skip)]
+    pub open: bool,
+    mode: SearchMode,
+    #[serde(skip)]
+    query: String,
+    #[serde(skip)]
+    pattern: Option<Regex>,
+    #[serde(skip)]
+    error: Option<String>,
+    #[serde(skip)]
+    matches: Vec<Match>,
+    #[serde(skip)]
+    current: Option<usize>,
+    #[serde(skip)]
+    searched: usize, // Entries already searched with the current pattern
+    #[serde(skip)]
+    line: String, // The text since the last newline, not yet complete
+    #[serde(skip)]
+    segments: Vec<Segment>, // Where each part of `line` came from
+    #[serde(skip)]
+    partial: usize, // Matches at the end found in `line`, redone as it grows
+    #[serde(skip)]
+    scroll_to: Option<usize>,
+    #[serde(skip)]
+    focus: bool,
+}
+
+impl Default for ScrollbackSearch {
+    fn default() -> Self {
+        Self {
+            open: false,
+            mode: SearchMode::IgnoreCase,
+            query: String::new(),
+            pattern: None,
+            error: None,
+            matches: Vec::new(),
+            current: None,
+            searched: 0,
+            line: String::new(),
+            segments: Vec::new(),
+            partial: 0,
+            scroll_to: None,
+            focus: false,
+        }
+    }
+}
+
+impl ScrollbackSearch {
+    pub fn show(&mut self) {
+        self.open = true;
+        self.focus = true;
+    }
+
+    /// Opens the search bar looking for `text` as typed.
+    pub fn search_for(&mut self, text: &str) {
+        self.show();
+        self.query = text.to_string();
+        if self.mode == SearchMode::Regex {
+            self.mode = SearchMode::IgnoreCase;
+        }
+        self.compile();
+    }
+
+    /// Searches the entries added since the last call.
+    pub fn update(&mut self, lines: &[Vec<(String, Color32)>]) {
+        if lines.len() < self.searched {
+            // The scrollback was cleared.
+            self.restart();
+        }
+        if self.pattern.is_none() {
+            self.searched = lines.len();
+            return;
+        }
+        if lines.len() == self.searched {
+            return;
+        }
+        self.matches.truncate(self.matches.len() - self.partial);
+        for (entry, line) in lines.iter().enumerate().skip(self.searched) {
+            let mut offset = 0;
+            for piece in line.iter().flat_map(|(text, _)| text.split_inclusive('\n')) {
+                let text = piece.strip_suffix('\n').unwrap_or(piece);
+                if !text.is_empty() {
+                    self.segments.push(Segment {
+                        start: self.line.len(),
+                        entry,
+                        offset,
+                    });
+                    self.line.push_str(text);
+                }
+                offset += piece.len();
+                if text.len() < piece.len() {
+                    self.search_line();
+                    self.line.clear();
+                    self.segments.clear();
+                }
+            }
+        }
+        let complete = self.matches.len();
+        self.search_line();
+        self.partial = self.matches.len() - complete;
+        self.searched = lines.len();
+        if self
+            .current
+            .is_some_and(|current| current >= self.matches.len())
+        {
+            self.current = None;
+        }
+        if self.current.is_none() && !self.matches.is_empty() {
+            // Start from the most recent hit, like searching up from the bottom.
+            self.select(self.matches.len() - 1);
+        }
+    }
+
+    /// Adds the matches in `line`, mapped back to the entries its text came from.
+    fn search_line(&mut self) {
+        let Some(pattern) = &self.pattern else {
+            return;
+        };
+        let text = self.line.strip_suffix('\r').unwrap_or(&self.line);
+        let found: Vec<Range<usize>> = pattern
+            .find_iter(text)
+            .filter(|found| !found.is_empty())
+            .map(|found| found.range())
+            .collect();
+        for range in found {
+            let pieces = self
+                .segments
+                .iter()
+                .enumerate()
+                .filter_map(|(index, segment)| {
+                    let end = self
+                        .segments
+                        .get(index + 1)
+                        .map_or(self.line.len(), |next| next.start);
+                    let start = range.start.max(segment.start);
+                    let end = range.end.min(end);
+                    (start < end).then(|| {
+                        let offset = |at: usize| at - segment.start + segment.offset;
+                        (segment.entry, offset(start)..offset(end))
+                    })
+                })
+                .collect();
+            self.matches.push(Match { pieces });
+        }
+    }
+
+    /// The search bar: query, mode, previous/next and the hit count.
+    pub fn bar_ui(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+        let mut step: Option<bool> = None; // Some(true) for next, Some(false) for previous
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                TextEdit::singleline(&mut self.query)
+                    .hint_text("Search")
+                    .desired_width(200.0),
+            );
+            if std::mem::take(&mut self.focus) {
+                response.request_focus();
+            }
+            changed |= response.changed();
+            if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                step = Some(!ui.input(|i| i.modifiers.shift));
+                response.request_focus();
+            }
+            if (response.has_focus() || response.lost_focus())
+                && ui.input(|i| i.key_pressed(Key::Escape))
+            {
+                self.open = false;
+            }
+
+            for (mode, label, hover) in [
+                (SearchMode::Plain, "Aa", "Match case"),
+                (SearchMode::IgnoreCase, "aa", "Ignore case"),
+                (SearchMode::Regex, ".*", "Regular expression"),
+            ] {
+                changed |= ui
+                    .selectable_value(&mut self.mode, mode, label)
+                    .on_hover_text(hover)
+                    .changed();
+            }
+            if ui
+                .button("⏶")
+                .on_hover_text("Previous (Shift+Enter)")
+                .clicked()
+            {
+                step = Some(false);
+            }
+            if ui.button("⏷").on_hover_text("Next (Enter)").clicked() {
+                step = Some(true);
+            }
+
+            match (&self.error, self.current) {
+                (Some(error), _) => {
+                    ui.colored_label(Color32::RED, error);
+                }
+                (None, Some(current)) => {
+                    ui.label(format!("{} of {}", current + 1, self.matches.len()));
+                }
+                (None, None) if !self.query.is_empty() => {
+                    ui.label("No matches");
+                }
+                _ => {}
+            }
+            if ui.button("✖").clicked() {
+                self.open = false;
+            }
+        });
+        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::F3)) {
+            step = Some(true);
+        }
+        if ui.input_mut(|i| i.consume_key(Modifiers::SHIFT, Key::F3)) {
+            step = Some(false);
+        }
+
+        if changed {
+            self.compile();
+        }
+        if let (Some(forward), Some(current)) = (step, self.current) {
+            let count = self.matches.len();
+            self.select(if forward {
+                (current + 1) % count
+            } else {
+                (current + count - 1) % count
+            });
+        }
+    }
+
+    /// The highlighted ranges of an output entry, with their background colors. Empty while
+    /// closed.
+    pub fn highlights(&self, entry: usize) -> Vec<(Range<usize>, Color32)> {
+        if !self.open {
+            return Vec::new();
+        }
+        let start = self.matches.partition_point(|found| found.last() < entry);
+        self.matches[start..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, found)| found.first() <= entry)
+            .flat_map(|(offset, found)| {
+                let background = if self.current == Some(start + offset) {
+                    CURRENT_BACKGROUND
+                } else {
+                    MATCH_BACKGROUND
+                };
+                found
+                    .pieces
+                    .iter()
+                    .filter(move |(piece_entry, _)| *piece_entry == entry)
+                    .map(move |(_, range)| (range.clone(), background))
+            })
+            .collect()
+    }
+
+    /// The entry to scroll to, once, after moving to a hit.
+    pub fn take_scroll_target(&mut self) -> Option<usize> {
+        self.scroll_to.take().filter(|_| self.open)
+    }
+
+    fn select(&mut self, index: usize) {
+        self.current = Some(index);
+        self.scroll_to = Some(self.matches[index].first());
+    }
+
+    fn compile(&mut self) {
+        self.error = None;
+        self.pattern = None;
+        if !self.query.is_empty() {
+            let pattern = match self.mode {
+                SearchMode::Plain => regex::escape(&self.query),
+                SearchMode::IgnoreCase => format!("(?i){}", regex::escape(&self.query)),
+                SearchMode::Regex => self.query.clone(),
+            };
+            match Regex::new(&pattern) {
+                Ok(pattern) => self.pattern = Some(pattern),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+        self.restart();
+    }
+
+    fn restart(&mut self) {
+        self.matches.clear();
+        self.current = None;
+        self.searched = 0;
+        self.line.clear();
+        self.segments.clear();
+        self.partial = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(chunks: &[&str]) -> Vec<Vec<(String, Color32)>> {
+        chunks
+            .iter()
+            .map(|chunk| vec![(chunk.to_string(), Color32::WHITE)])
+            .collect()
+    }
+
+    fn searched(mode: SearchMode, query: &str, chunks: &[&str]) -> ScrollbackSearch {
+        let mut search = ScrollbackSearch {
+            open: true,
+            mode,
+            query: query.to_owned(),
+            ..Default::default()
+        };
+        search.compile();
+        search.update(&entries(chunks));
+        search
+    }
+
+    fn ranges(search: &ScrollbackSearch, entry: usize) -> Vec<(usize, usize)> {
+        search
+            .highlights(entry)
+            .into_iter()
+            .map(|(range, _)| (range.start, range.end))
+            .collect()
+    }
+
+    #[test]
+    fn finds_words_split_across_chunks() {
+        let mut search = searched(SearchMode::Plain, "dragon", &["A red dra", "gon lands.\n"]);
+        assert_eq!(search.matches.len(), 1);
+        assert_eq!(ranges(&search, 0), [(6, 9)]);
+        assert_eq!(ranges(&search, 1), [(0, 3)]);
+        assert_eq!(search.take_scroll_target(), Some(0));
+    }
+
+    #[test]
+    fn anchors_and_counts_follow_lines_not_chunks() {
+        let search = searched(
+            SearchMode::Regex,
+            "^You",
+            &["You hit.\r\nYou miss.\nThey see You.\n"],
+        );
+        assert_eq!(search.matches.len(), 2);
+        assert_eq!(ranges(&search, 0), [(0, 3), (10, 13)]);
+
+        let search = searched(
+            SearchMode::Regex,
+            "miss\\.$",
+            &["You mi", "ss.\r\nYou miss. Again\n"],
+        );
+        assert_eq!(search.matches.len(), 1);
+        assert_eq!(ranges(&search, 0), [(4, 6)]);
+        assert_eq!(ranges(&search, 1), [(0, 3)]);
+    }
+
+    #[test]
+    fn an_incomplete_line_is_searched_again_as_it_grows() {
+        let mut search = searched(SearchMode::IgnoreCase, "hp", &["HP: 10 > "]);
+        assert_eq!(search.matches.len(), 1);
+        let mut chunks = entries(&["HP: 10 > ", "hp potion\n", "HP: 12 > "]);
+        search.update(&chunks);
+        assert_eq!(search.matches.len(), 3);
+        chunks.clear();
+        search.update(&chunks);
+        assert!(search.matches.is_empty());
+    }
+}
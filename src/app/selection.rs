@@ -0,0 +1,255 @@
+use crate::app::ansi_color;
+use crate::app::session_log::{css_colour, escape_html};
+use crate::app::telnet::Link;
+use egui::{Color32, Event, Galley, Rect, Response, Ui};
+use std::ops::Range;
+
+/// A point in the output: the line index and a byte offset into its plain text.
+type TextPos = (usize, usize);
+
+/// What clicks and the context menu in the output ask of the rest of the app.
+pub enum OutputAction {
+    Search(String),
+    SendToInput(String),
+    OpenLink(String),
+}
+
+/// One output line as laid out on screen, with its links.
+pub struct LineLayout<'a> {
+    pub galley: &'a Galley,
+    pub rect: Rect,
+    pub index: usize,
+    pub links: &'a [Link],
+}
+
+/// Drag-selection across the output lines, with copying as plain text, ANSI or HTML.
+#[derive(Default)]
+pub struct OutputSelection {
+    anchor: Option<TextPos>,
+    cursor: Option<TextPos>,
+    dragging: bool,
+    action: Option<OutputAction>,
+}
+
+impl OutputSelection {
+    /// The selected range of `line`, in bytes of its plain text.
+    pub fn highlight(&self, line: usize, len: usize) -> Option<Range<usize>> {
+        let ((start_line, start), (end_line, end)) = self.range()?;
+        if line < start_line || line > end_line {
+            return None;
+        }
+        let start = if line == start_line { start } else { 0 };
+        let end = if line == end_line { end } else { len };
+        (start < end).then_some(start..end)
+    }
+
+    /// Handles dragging over, clicking and right-clicking one line, and hovering its links.
+    pub fn line_ui(
+        &mut self,
+        ui: &mut Ui,
+        response: &Response,
+        layout: &LineLayout<'_>,
+        lines: &[Vec<(String, Color32)>],
+    ) {
+        let LineLayout {
+            galley,
+            rect,
+            index: line,
+            links,
+        } = *layout;
+        let text = plain(&lines[line]);
+        let pos_at = |pos: egui::Pos2| {
+            let index = galley.cursor_from_pos(pos - rect.min).ccursor.index;
+            let offset = text
+                .char_indices()
+                .nth(index)
+                .map_or(text.len(), |(offset, _)| offset);
+            (line, offset)
+        };
+        let pointer = ui.input(|i| i.pointer.interact_pos());
+
+        // Links show their target on hover and open, after confirmation, on click.
+        let hovered_link = response
+            .hover_pos()
+            .filter(|_| !self.dragging)
+            .and_then(|pos| {
+                let (_, offset) = pos_at(pos);
+                links.iter().find(|(range, _)| range.contains(&offset))
+            });
+        if let Some((_, target)) = hovered_link {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+            egui::show_tooltip_at_pointer(
+                ui.ctx(),
+                ui.layer_id(),
+                response.id.with("link"),
+                |ui| {
+                    ui.label(target);
+                },
+            );
+            if response.clicked() {
+                self.action = Some(OutputAction::OpenLink(target.clone()));
+                return;
+            }
+        }
+
+        if response.drag_started() {
+            if let Some(pos) = pointer {
+                self.anchor = Some(pos_at(pos));
+                self.cursor = self.anchor;
+                self.dragging = true;
+                // Leave the input line so Ctrl+C copies the output selection.
+                ui.memory_mut(|memory| {
+                    if let Some(id) = memory.focused() {
+                        memory.surrender_focus(id);
+                    }
+                });
+            }
+        }
+        if self.dragging {
+            if !ui.input(|i| i.pointer.primary_down()) {
+                self.dragging = false;
+            } else if let Some(pos) = pointer.filter(|pos| rect.y_range().contains(pos.y)) {
+                self.cursor = Some(pos_at(pos));
+            }
+        }
+        if response.clicked() {
+            self.anchor = None;
+            self.cursor = None;
+        }
+
+        response.context_menu(|ui| {
+            let selected = self.selected_spans(lines);
+            let has_selection = !selected.is_empty();
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Copy"))
+                .clicked()
+            {
+                ui.ctx().copy_text(plain(&selected));
+                ui.close_menu();
+            }
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Copy as ANSI"))
+                .clicked()
+            {
+                ui.ctx().copy_text(to_ansi(&selected));
+                ui.close_menu();
+            }
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Copy as HTML"))
+                .clicked()
+            {
+                ui.ctx().copy_text(to_html(&selected));
+                ui.close_menu();
+            }
+            ui.separator();
+            if ui.button("Select all").clicked() {
+                self.select_all(lines);
+                ui.close_menu();
+            }
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Search selection"))
+                .clicked()
+            {
+                self.action = Some(OutputAction::Search(plain(&selected)));
+                ui.close_menu();
+            }
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Send selection to input"))
+                .clicked()
+            {
+                self.action = Some(OutputAction::SendToInput(plain(&selected)));
+                ui.close_menu();
+            }
+        });
+    }
+
+    /// Copies the selection as plain text on Ctrl+C, unless a text field has the focus.
+    pub fn handle_copy(&self, ui: &Ui, lines: &[Vec<(String, Color32)>]) {
+        let copy = ui.input(|i| i.events.iter().any(|event| matches!(event, Event::Copy)));
+        if copy && ui.memory(|memory| memory.focused().is_none()) {
+            let selected = self.selected_spans(lines);
+            if !selected.is_empty() {
+                ui.ctx().copy_text(plain(&selected));
+            }
+        }
+    }
+
+    pub fn take_action(&mut self) -> Option<OutputAction> {
+        self.action.take()
+    }
+
+    fn select_all(&mut self, lines: &[Vec<(String, Color32)>]) {
+        if let Some(last) = lines.len().checked_sub(1) {
+            self.anchor = Some((0, 0));
+            self.cursor = Some((last, plain(&lines[last]).len()));
+        }
+    }
+
+    /// The anchor and cursor in reading order.
+    fn range(&self) -> Option<(TextPos, TextPos)> {
+        let (anchor, cursor) = (self.anchor?, self.cursor?);
+        Some(if anchor <= cursor {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        })
+    }
+
+    /// The selected text as colored spans.
+    fn selected_spans(&self, lines: &[Vec<(String, Color32)>]) -> Vec<(String, Color32)> {
+        let Some(((start_line, _), (end_line, _))) = self.range() else {
+            return Vec::new();
+        };
+        let mut selected = Vec::new();
+        for (index, line) in lines.iter().enumerate().take(end_line + 1).skip(start_line) {
+            let Some(range) = self.highlight(index, plain(line).len()) else {
+                continue;
+            };
+            let mut offset = 0;
+            for (text, color) in line {
+                let from = range.start.saturating_sub(offset).min(text.len());
+                let to = range.end.saturating_sub(offset).min(text.len());
+                if from < to {
+                    selected.push((text[from..to].to_string(), *color));
+                }
+                offset += text.len();
+            }
+        }
+        selected
+    }
+}
+
+fn plain(spans: &[(String, Color32)]) -> String {
+    spans.iter().map(|(text, _)| text.as_str()).collect()
+}
+
+/// Rebuilds SGR escape codes from the span colors.
+fn to_ansi(spans: &[(String, Color32)]) -> String {
+    let mut ansi = String::new();
+    for (text, color) in spans {
+        ansi.push_str(&format!(
+            "\x1b[{}m{}",
+            ansi_color::palette_code(*color),
+            text
+        ));
+    }
+    ansi.push_str("\x1b[0m");
+    ansi
+}
+
+fn to_html(spans: &[(String, Color32)]) -> String {
+    let mut html = format!(
+        "<pre style=\"background:{};color:{}\">",
+        css_colour(ansi_color::default_background()),
+        css_colour(ansi_color::default_foreground())
+    );
+    for (text, color) in spans {
+        html.push_str(&format!(
+            "<span style=\"color:{}\">{}</span>",
+            css_colour(*color),
+            escape_html(text)
+        ));
+    }
+    html.push_str("</pre>");
+    html
+}
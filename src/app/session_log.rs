@@ -0,0 +1,371 @@
+use crate::app::ansi_color;
+use crate::app::miniwindow::E_OK;
+use crate::app::plugins::native;
+use crate::app::settings::{LogFormat, LogRotation, LoggingSettings};
+use chrono::{Local, NaiveDate};
+use egui::Color32;
+use mlua::prelude::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const E_COULD_NOT_OPEN_FILE: i64 = 30013;
+pub const E_LOG_FILE_NOT_OPEN: i64 = 30014;
+pub const E_LOG_FILE_ALREADY_OPEN: i64 = 30015;
+pub const E_LOG_FILE_BAD_WRITE: i64 = 30016;
+
+/// One line for the log: plain text, the text as received with its ANSI codes, and the colored
+/// spans shown on screen.
+pub struct LogLine<'a> {
+    pub text: &'a str,
+    pub raw: &'a str,
+    pub spans: &'a [(String, Color32)],
+}
+
+struct LogFile {
+    file: File,
+    path: PathBuf,
+    format: LogFormat,
+    date: NaiveDate,
+    part: u32,
+    size: u64,
+}
+
+#[derive(Default)]
+struct LogState {
+    settings: LoggingSettings,
+    world: String,
+    character: String,
+    file: Option<LogFile>,
+    /// Set while a script has the log open with `OpenLog`, so the settings don't close it.
+    opened_by_script: bool,
+    /// Set by `CloseLog` to stop automatic logging until the next connection.
+    suspended: bool,
+}
+
+/// The session log: received lines, and optionally typed commands, written per world as plain
+/// text, raw ANSI or HTML. Shared with Lua's `OpenLog`, `WriteLog`, `CloseLog` and `IsLogOpen`.
+#[derive(Clone, Default)]
+pub struct SessionLog {
+    state: Arc<Mutex<LogState>>,
+}
+
+impl SessionLog {
+    /// Updates the settings and the world and character used in file names.
+    pub fn configure(&self, settings: &LoggingSettings, world: &str, character: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.settings != *settings || state.world != world || state.character != character {
+            state.settings = settings.clone();
+            state.world = world.to_string();
+            state.character = character.to_string();
+            if !settings.enabled && !state.opened_by_script {
+                state.close();
+            }
+        }
+    }
+
+    /// Starts automatic logging again after a script closed the log.
+    pub fn on_connect(&self) {
+        self.state.lock().unwrap().suspended = false;
+    }
+
+    pub fn write_received(&self, line: &LogLine<'_>) {
+        if let Err(e) = self.state.lock().unwrap().write(line) {
+            eprintln!("Failed to write log: {}", e);
+        }
+    }
+
+    /// Logs a sent command, in `color` like its echo, if input is logged.
+    pub fn write_input(&self, command: &str, color: Color32) {
+        let mut state = self.state.lock().unwrap();
+        if state.settings.log_input {
+            let spans = [(command.to_string(), color)];
+            let line = LogLine {
+                text: command,
+                raw: command,
+                spans: &spans,
+            };
+            if let Err(e) = state.write(&line) {
+                eprintln!("Failed to write log: {}", e);
+            }
+        }
+    }
+
+    pub fn open(&self, path: Option<PathBuf>, append: bool) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = match path {
+            Some(path) => path,
+            None => state.template_path(0),
+        };
+        state.close();
+        state.open(path, append)?;
+        state.opened_by_script = true;
+        state.suspended = false;
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.close();
+        state.suspended = true;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().file.is_some()
+    }
+}
+
+impl LogState {
+    fn write(&mut self, line: &LogLine<'_>) -> io::Result<()> {
+        if !self.opened_by_script {
+            if !self.settings.enabled || self.suspended || self.world.is_empty() {
+                self.close();
+                return Ok(());
+            }
+            self.rotate()?;
+        }
+
+        let Some(log) = &mut self.file else {
+            return Ok(());
+        };
+        let timestamp = if self.settings.timestamps {
+            Local::now().format("[%H:%M:%S] ").to_string()
+        } else {
+            String::new()
+        };
+        let text = match log.format {
+            LogFormat::Text => format!("{}{}\n", timestamp, line.text),
+            LogFormat::Ansi => format!("{}{}\x1b[0m\n", timestamp, line.raw),
+            LogFormat::Html => {
+                let mut html = escape_html(&timestamp);
+                for (text, colour) in line.spans {
+                    html.push_str(&format!(
+                        "<span style=\"color:{}\">{}</span>",
+                        css_colour(*colour),
+                        escape_html(text)
+                    ));
+                }
+                html.push('\n');
+                html
+            }
+        };
+        log.file.write_all(text.as_bytes())?;
+        log.size += text.len() as u64;
+        Ok(())
+    }
+
+    /// Opens the automatic log, or moves to a new file when the day or the size limit says so.
+    fn rotate(&mut self) -> io::Result<()> {
+        let today = Local::now().date_naive();
+        let (part, reopen) = match &self.file {
+            None => (0, true),
+            Some(log) if log.format != self.settings.format => (0, true),
+            Some(log) => match self.settings.rotation {
+                LogRotation::Daily if log.date != today => (0, true),
+                LogRotation::Size if log.size >= self.settings.max_size_kb * 1024 => {
+                    (log.part + 1, true)
+                }
+                _ => (log.part, log.path != self.template_path(log.part)),
+            },
+        };
+        if reopen {
+            self.close();
+            let mut part = part;
+            // Skip parts already filled up by an earlier session.
+            while self.settings.rotation == LogRotation::Size
+                && fs::metadata(self.template_path(part))
+                    .is_ok_and(|meta| meta.len() >= self.settings.max_size_kb * 1024)
+            {
+                part += 1;
+            }
+            let path = self.template_path(part);
+            self.open(path, true)?;
+            if let Some(log) = &mut self.file {
+                log.part = part;
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, path: PathBuf, append: bool) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(&path)?;
+        let size = file.metadata()?.len();
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html" | "htm") => LogFormat::Html,
+            Some("ans") => LogFormat::Ansi,
+            Some("txt" | "log") => LogFormat::Text,
+            _ => self.settings.format,
+        };
+        if format == LogFormat::Html && size == 0 {
+            file.write_all(html_header(&self.world).as_bytes())?;
+        }
+        self.file = Some(LogFile {
+            file,
+            path,
+            format,
+            date: Local::now().date_naive(),
+            part: 0,
+            size,
+        });
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some(mut log) = self.file.take() {
+            if log.format == LogFormat::Html {
+                let _ = log.file.write_all(b"</pre></body></html>\n");
+            }
+        }
+        self.opened_by_script = false;
+    }
+
+    /// The file name template with `{world}`, `{character}` and `{date}` filled in, inside
+    /// the log directory. Daily rotation adds the date if the template has none.
+    fn template_path(&self, part: u32) -> PathBuf {
+        let settings = &self.settings;
+        let date = Local::now().format("%Y-%m-%d").to_string();
+        let mut name = settings.file_name.clone();
+        if settings.rotation == LogRotation::Daily && !name.contains("{date}") {
+            name.push_str("-{date}");
+        }
+        let mut name = name
+            .replace("{world}", &file_safe(&self.world))
+            .replace("{character}", &file_safe(&self.character))
+            .replace("{date}", &date);
+        if part > 0 {
+            name.push_str(&format!(".{}", part));
+        }
+        let extension = match settings.format {
+            LogFormat::Text => "txt",
+            LogFormat::Ansi => "ans",
+            LogFormat::Html => "html",
+        };
+        Path::new(&settings.directory).join(format!("{}.{}", name, extension))
+    }
+}
+
+pub fn file_safe(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn css_colour(colour: Color32) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour.r(), colour.g(), colour.b())
+}
+
+fn html_header(world: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title></head>\n\
+         <body style=\"background:{};color:{}\"><pre style=\"font-family:monospace\">\n",
+        escape_html(world),
+        css_colour(ansi_color::default_background()),
+        css_colour(ansi_color::default_foreground()),
+    )
+}
+
+/// Where `OpenLog` may create files.
+pub enum LogFiles {
+    /// Any path, for the main script state and MUSHclient plugins.
+    Anywhere,
+    /// The default log, or names inside a native plugin's data folder.
+    DataFolder(PathBuf),
+    /// Nowhere: the log functions are left out, for sandboxed plugins that may not write
+    /// files, so they can't write to or close the session log either.
+    Nowhere,
+}
+
+/// Adds `OpenLog`, `WriteLog`, `CloseLog` and `IsLogOpen`, unless `files` is `Nowhere`.
+pub fn register_functions(lua: &Lua, log: SessionLog, files: LogFiles) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    if matches!(files, LogFiles::Nowhere) {
+        return Ok(());
+    }
+
+    let open_log = log.clone();
+    globals.set(
+        "OpenLog",
+        lua.create_function(move |_, (name, append): (Option<String>, Option<bool>)| {
+            if open_log.is_open() {
+                return Ok(E_LOG_FILE_ALREADY_OPEN);
+            }
+            let path = match (name.filter(|name| !name.is_empty()), &files) {
+                (None, _) => None,
+                (Some(name), LogFiles::DataFolder(data_dir)) => {
+                    Some(native::data_path(data_dir, &name)?)
+                }
+                (Some(name), _) => Some(PathBuf::from(name)),
+            };
+            Ok(match open_log.open(path, append.unwrap_or(true)) {
+                Ok(()) => E_OK,
+                Err(_) => E_COULD_NOT_OPEN_FILE,
+            })
+        })?,
+    )?;
+
+    let write_log = log.clone();
+    globals.set(
+        "WriteLog",
+        lua.create_function(move |_, message: String| {
+            let mut state = write_log.state.lock().unwrap();
+            if state.file.is_none() {
+                return Ok(E_LOG_FILE_NOT_OPEN);
+            }
+            let spans = [(message.clone(), ansi_color::default_foreground())];
+            let line = LogLine {
+                text: &message,
+                raw: &message,
+                spans: &spans,
+            };
+            // Write straight to the open file: a script's log line shouldn't start rotation.
+            let opened_by_script = std::mem::replace(&mut state.opened_by_script, true);
+            let result = state.write(&line);
+            state.opened_by_script = opened_by_script;
+            Ok(match result {
+                Ok(()) => E_OK,
+                Err(_) => E_LOG_FILE_BAD_WRITE,
+            })
+        })?,
+    )?;
+
+    let close_log = log.clone();
+    globals.set(
+        "CloseLog",
+        lua.create_function(move |_, ()| {
+            if !close_log.is_open() {
+                return Ok(E_LOG_FILE_NOT_OPEN);
+            }
+            close_log.close();
+            Ok(E_OK)
+        })?,
+    )?;
+
+    globals.set(
+        "IsLogOpen",
+        lua.create_function(move |_, ()| Ok(log.is_open()))?,
+    )?;
+
+    Ok(())
+}
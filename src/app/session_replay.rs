@@ -0,0 +1,390 @@
+use crate::app::telnet::TelnetClient;
+use egui::{Button, Color32, Slider, Window};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const HEADER: &[u8] = b"MUDFORGE-RECORDING 1\n";
+const RECORDING_FILE: &str = "session.rec";
+
+/// Writes the bytes read from the server, before telnet parsing, each chunk preceded by
+/// `<milliseconds since start> <length>\n`.
+pub struct Recorder {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let mut file = BufWriter::new(
+            File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?,
+        );
+        file.write_all(HEADER)
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        Ok(Self {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, data: &[u8]) -> io::Result<()> {
+        let millis = self.started.elapsed().as_millis();
+        writeln!(self.file, "{} {}", millis, data.len())?;
+        self.file.write_all(data)?;
+        self.file.write_all(b"\n")?;
+        self.file.flush()
+    }
+}
+
+struct Chunk {
+    at: Duration,
+    data: Vec<u8>,
+}
+
+/// A loaded recording and how far into it the replay has got.
+pub struct Replay {
+    chunks: Vec<Chunk>,
+    next: usize,
+    offset: usize, // Bytes of the next chunk already delivered by stepping
+    clock: Duration,
+    last_tick: Option<Instant>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let mut rest = bytes
+            .strip_prefix(HEADER)
+            .ok_or_else(|| format!("{} is not a MudForge recording", path))?;
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or("Truncated recording")?;
+            let header = String::from_utf8_lossy(&rest[..end]);
+            let (millis, len): (u64, usize) = header
+                .split_once(' ')
+                .and_then(|(millis, len)| Some((millis.parse().ok()?, len.parse().ok()?)))
+                .ok_or_else(|| format!("Bad chunk header: {}", header))?;
+            let data = rest
+                .get(end + 1..end + 1 + len)
+                .ok_or("Truncated recording")?;
+            chunks.push(Chunk {
+                at: Duration::from_millis(millis),
+                data: data.to_vec(),
+            });
+            rest = rest.get(end + 2 + len..).unwrap_or_default();
+        }
+        Ok(Self {
+            chunks,
+            next: 0,
+            offset: 0,
+            clock: Duration::ZERO,
+            last_tick: None,
+        })
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.chunks.len()
+    }
+
+    /// Advances the replay clock by the time since the last call, times `speed`, and returns
+    /// the bytes that came in over that time.
+    fn due(&mut self, speed: f32) -> Vec<u8> {
+        let now = Instant::now();
+        if let Some(last_tick) = self.last_tick {
+            self.clock += now.duration_since(last_tick).mul_f32(speed);
+        }
+        self.last_tick = Some(now);
+
+        let mut data = Vec::new();
+        while let Some(chunk) = self.chunks.get(self.next) {
+            if chunk.at > self.clock {
+                break;
+            }
+            data.extend_from_slice(&chunk.data[self.offset..]);
+            self.next += 1;
+            self.offset = 0;
+        }
+        data
+    }
+
+    /// Returns the bytes up to and including the next newline.
+    fn next_line(&mut self) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(chunk) = self.chunks.get(self.next) {
+            self.clock = chunk.at;
+            let rest = &chunk.data[self.offset..];
+            if let Some(end) = rest.iter().position(|&b| b == b'\n') {
+                data.extend_from_slice(&rest[..=end]);
+                self.offset += end + 1;
+                if self.offset == chunk.data.len() {
+                    self.next += 1;
+                    self.offset = 0;
+                }
+                break;
+            }
+            data.extend_from_slice(rest);
+            self.next += 1;
+            self.offset = 0;
+        }
+        data
+    }
+
+    /// Stops the clock, so time spent paused or stepping isn't caught up on afterwards.
+    fn pause(&mut self) {
+        self.last_tick = None;
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum ReplayMode {
+    RealTime,
+    Accelerated,
+    Step,
+}
+
+/// The window for recording the live byte stream and replaying recordings offline. A replay
+/// feeds the same parser, output and triggers as a live connection, so it only runs while
+/// disconnected: the parser's negotiation replies would otherwise reach the server.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct SessionReplay {
+    #[serde(skip)]
+    pub open: bool,
+    path: String,
+    mode: ReplayMode,
+    speed: f32, // Multiplier for accelerated replay
+    #[serde(skip)]
+    replay: Option<Replay>,
+    #[serde(skip)]
+    paused: bool,
+    #[serde(skip)]
+    status: Option<Result<String, String>>,
+}
+
+impl Default for SessionReplay {
+    fn default() -> Self {
+        Self {
+            open: false,
+            path: RECORDING_FILE.to_owned(),
+            mode: ReplayMode::RealTime,
+            speed: 10.0,
+            replay: None,
+            paused: false,
+            status: None,
+        }
+    }
+}
+
+impl SessionReplay {
+    /// Feeds the replay's due bytes to the client. Call once a frame. Connecting stops it.
+    pub fn poll(&mut self, telnet_client: &Arc<Mutex<TelnetClient>>) {
+        let Some(replay) = &mut self.replay else {
+            return;
+        };
+        if telnet_client.lock().unwrap().is_connected() {
+            self.replay = None;
+            self.status = Some(Err("Replay stopped: connected to a world".to_owned()));
+            return;
+        }
+        if self.paused || self.mode == ReplayMode::Step {
+            replay.pause();
+            return;
+        }
+        let speed = match self.mode {
+            ReplayMode::Accelerated => self.speed,
+            _ => 1.0,
+        };
+        let data = replay.due(speed);
+        if !data.is_empty() {
+            telnet_client.lock().unwrap().receive(&data);
+        }
+        if replay.finished() {
+            self.replay = None;
+            self.status = Some(Ok("Replay finished".to_owned()));
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, telnet_client: &Arc<Mutex<TelnetClient>>) {
+        let mut open = self.open;
+        Window::new("Record and replay")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.path);
+                });
+
+                let (recording, connected) = {
+                    let telnet_client = telnet_client.lock().unwrap();
+                    (telnet_client.is_recording(), telnet_client.is_connected())
+                };
+                ui.horizontal(|ui| {
+                    if recording {
+                        ui.colored_label(Color32::RED, "● Recording");
+                        if ui.button("Stop recording").clicked() {
+                            telnet_client.lock().unwrap().stop_recording();
+                            self.status = Some(Ok(format!("Recorded to {}", self.path)));
+                        }
+                    } else if ui.button("Record").clicked() {
+                        self.status = Some(
+                            telnet_client
+                                .lock()
+                                .unwrap()
+                                .start_recording(&self.path)
+                                .map(|()| format!("Recording to {}", self.path)),
+                        );
+                    }
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.mode, ReplayMode::RealTime, "Real speed");
+                    ui.radio_value(&mut self.mode, ReplayMode::Accelerated, "Accelerated");
+                    ui.radio_value(&mut self.mode, ReplayMode::Step, "Line by line");
+                });
+                if self.mode == ReplayMode::Accelerated {
+                    ui.add(Slider::new(&mut self.speed, 1.0..=100.0).suffix("×"));
+                }
+                ui.horizontal(|ui| match &mut self.replay {
+                    None => {
+                        let replay = ui
+                            .add_enabled(!connected, Button::new("Replay"))
+                            .on_disabled_hover_text("Replays run only while disconnected.");
+                        if replay.clicked() {
+                            match Replay::load(&self.path) {
+                                Ok(replay) => {
+                                    self.replay = Some(replay);
+                                    self.paused = false;
+                                    self.status = None;
+                                }
+                                Err(e) => self.status = Some(Err(e)),
+                            }
+                        }
+                    }
+                    Some(replay) => {
+                        if self.mode == ReplayMode::Step {
+                            if ui
+                                .add_enabled(!connected, Button::new("Next line"))
+                                .clicked()
+                            {
+                                let data = replay.next_line();
+                                telnet_client.lock().unwrap().receive(&data);
+                            }
+                        } else if ui
+                            .button(if self.paused { "Resume" } else { "Pause" })
+                            .clicked()
+                        {
+                            self.paused = !self.paused;
+                        }
+                        ui.label(format!(
+                            "{}s, chunk {} of {}",
+                            replay.clock.as_secs(),
+                            replay.next,
+                            replay.chunks.len()
+                        ));
+                        if ui.button("Stop").clicked() || replay.finished() {
+                            self.replay = None;
+                        }
+                    }
+                });
+
+                match &self.status {
+                    Some(Ok(message)) => {
+                        ui.label(message.as_str());
+                    }
+                    Some(Err(error)) => {
+                        ui.colored_label(Color32::RED, error.as_str());
+                    }
+                    None => {}
+                }
+            });
+        self.open = open;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `bytes` to a file of its own in the temp folder and loads it.
+    fn load(name: &str, bytes: &[u8]) -> Result<Replay, String> {
+        let path = std::env::temp_dir().join(format!(
+            "mudforge-replay-{}-{}.rec",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, bytes).unwrap();
+        let replay = Replay::load(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+        replay
+    }
+
+    #[test]
+    fn loads_chunks_with_their_times() {
+        let replay = load(
+            "chunks",
+            b"MUDFORGE-RECORDING 1\n0 6\nhello\n\n250 3\na\nb\n",
+        )
+        .unwrap();
+        assert_eq!(replay.chunks.len(), 2);
+        assert_eq!(replay.chunks[0].at, Duration::ZERO);
+        assert_eq!(replay.chunks[0].data, b"hello\n");
+        assert_eq!(replay.chunks[1].at, Duration::from_millis(250));
+        assert_eq!(replay.chunks[1].data, b"a\nb");
+        assert!(!replay.finished());
+    }
+
+    #[test]
+    fn loads_an_empty_recording() {
+        assert!(load("empty", HEADER).unwrap().finished());
+    }
+
+    #[test]
+    fn round_trips_through_the_recorder() {
+        let path = std::env::temp_dir().join(format!(
+            "mudforge-replay-{}-recorder.rec",
+            std::process::id()
+        ));
+        let path = path.to_string_lossy();
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record(b"\xff\xfb\x01login: ").unwrap();
+        recorder.record(b"\n").unwrap();
+        drop(recorder);
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&*path).unwrap();
+        assert_eq!(replay.chunks.len(), 2);
+        assert_eq!(replay.chunks[0].data, b"\xff\xfb\x01login: ");
+        assert_eq!(replay.chunks[1].data, b"\n");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(load("header", b"not a recording\n").is_err());
+        assert!(load("bad_chunk", b"MUDFORGE-RECORDING 1\nsoon 5\nhello\n").is_err());
+        assert!(load("no_length", b"MUDFORGE-RECORDING 1\n12\nhello\n").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_recordings() {
+        assert!(load("short", b"MUDFORGE-RECORDING 1\n0 10\nhello").is_err());
+        assert!(load("no_newline", b"MUDFORGE-RECORDING 1\n0 5").is_err());
+    }
+
+    #[test]
+    fn steps_line_by_line_across_chunks() {
+        let mut replay = load(
+            "lines",
+            b"MUDFORGE-RECORDING 1\n0 6\none\ntw\n10 5\no\nthr\n",
+        )
+        .unwrap();
+        assert_eq!(replay.next_line(), b"one\n");
+        assert_eq!(replay.next_line(), b"two\n");
+        assert_eq!(replay.next_line(), b"thr");
+        assert!(replay.finished());
+    }
+}
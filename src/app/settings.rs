@@ -0,0 +1,344 @@
+use crate::app::ansi_color::Palette;
+use crate::app::styles;
+use egui::{Color32, FontFamily, FontId, TextStyle, Visuals};
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Everything in the Settings window. Saved with the app state and exportable as TOML.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub style: StyleSettings,
+    pub appearance: AppearanceSettings,
+    pub fonts: FontSettings,
+    pub colors: ColorSettings,
+    pub input: InputSettings,
+    pub logging: LoggingSettings,
+    pub network: NetworkSettings,
+    pub mapper: MapperSettings,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct StyleSettings {
+    pub window_fill: Color32,
+    pub panel_fill: Color32,
+}
+
+impl Default for StyleSettings {
+    fn default() -> Self {
+        let visuals = styles::default_style().visuals;
+        Self {
+            window_fill: visuals.window_fill,
+            panel_fill: visuals.panel_fill,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct AppearanceSettings {
+    pub primary_color: Color32, // Selections and highlighted widgets
+    pub split_scrollback: bool, // Scrolling back splits the output, keeping live text below
+    pub split_ratio: f32,       // Share of the output height given to the history pane
+}
+
+impl Default for AppearanceSettings {
+    fn default() -> Self {
+        Self {
+            primary_color: styles::default_style().visuals.selection.bg_fill,
+            split_scrollback: true,
+            split_ratio: 0.7,
+        }
+    }
+}
+
+/// Font files are paths to TTF/OTF files; an empty path means the built-in ReFixedys Mono.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct FontSettings {
+    pub output_font: String,
+    pub input_font: String,
+    pub ui_font: String,
+    pub fallback_fonts: Vec<String>,
+    pub system_cjk_font: bool, // Use the platform's usual CJK font when it is installed
+    pub detect_fallback_fonts: bool, // Also use any known CJK and emoji fonts installed
+    pub body_size: f32,
+    pub monospace_size: f32,
+    pub output_size: f32,
+    pub world_output_sizes: BTreeMap<String, f32>, // Output sizes zoomed per world
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        Self {
+            output_font: String::new(),
+            input_font: String::new(),
+            ui_font: String::new(),
+            fallback_fonts: Vec::new(),
+            system_cjk_font: true,
+            detect_fallback_fonts: false,
+            body_size: 12.5,
+            monospace_size: 12.0,
+            output_size: 12.5,
+            world_output_sizes: BTreeMap::new(),
+        }
+    }
+}
+
+impl FontSettings {
+    pub const MIN_SIZE: f32 = 6.0;
+    pub const MAX_SIZE: f32 = 48.0;
+
+    /// Whether `other` loads the same font files, so the fonts need not be rebuilt.
+    pub fn same_files(&self, other: &FontSettings) -> bool {
+        self.output_font == other.output_font
+            && self.input_font == other.input_font
+            && self.ui_font == other.ui_font
+            && self.fallback_fonts == other.fallback_fonts
+            && self.system_cjk_font == other.system_cjk_font
+            && self.detect_fallback_fonts == other.detect_fallback_fonts
+    }
+
+    pub fn output_size(&self, world: &str) -> f32 {
+        self.world_output_sizes
+            .get(world)
+            .copied()
+            .unwrap_or(self.output_size)
+    }
+
+    pub fn set_output_size(&mut self, world: &str, size: f32) {
+        let size = size.clamp(Self::MIN_SIZE, Self::MAX_SIZE);
+        self.world_output_sizes.insert(world.to_string(), size);
+    }
+
+    pub fn output_font_id(&self, world: &str) -> FontId {
+        FontId::new(
+            self.output_size(world),
+            FontFamily::Name(styles::OUTPUT_FONT.into()),
+        )
+    }
+
+    pub fn input_font_id(&self) -> FontId {
+        FontId::new(self.body_size, FontFamily::Name(styles::INPUT_FONT.into()))
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ColorSettings {
+    pub text: Color32,
+    pub text_box: Color32,
+    pub hyperlink: Color32,
+    pub palette: Palette,
+}
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        let visuals = styles::default_style().visuals;
+        Self {
+            text: visuals.override_text_color.unwrap_or(Color32::WHITE),
+            text_box: visuals.extreme_bg_color,
+            hyperlink: visuals.hyperlink_color,
+            palette: Palette::default(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct InputSettings {
+    pub echo_commands: bool, // Show sent commands in the output
+    pub echo_color: Color32,
+    pub echo_style: EchoStyle,
+    pub keep_command: bool, // Leave the last command in the input line, selected
+    pub history_size: usize, // Commands remembered per world
+    pub history_dedupe: bool, // Keep only the latest copy of a repeated command
+    pub completion_lines: usize, // Output lines searched for Tab completion words
+    pub completion_words: String, // Extra Tab completion words, separated by whitespace
+    pub command_separator: String, // Splits one line into several commands
+    pub repeat_prefix: String, // `#5 kill rat` sends the command five times
+    pub speedwalk_prefix: String, // `.3n2e` sends n, n, n, e, e
+    pub multiline: bool,    // Multi-line input, Shift+Enter for a new line
+    pub send_lines_separately: bool, // Multi-line input is queued, else sent at once
+    pub line_delay_ms: u64, // Pause between those lines
+}
+
+/// How sent commands echoed into the output are set apart from the server's text.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct EchoStyle {
+    pub italics: bool,
+    pub underline: bool,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            echo_commands: true,
+            echo_color: Color32::KHAKI,
+            echo_style: EchoStyle::default(),
+            keep_command: false,
+            history_size: 1000,
+            history_dedupe: true,
+            completion_lines: 500,
+            completion_words: String::new(),
+            command_separator: ";".to_owned(),
+            repeat_prefix: "#".to_owned(),
+            speedwalk_prefix: ".".to_owned(),
+            multiline: false,
+            send_lines_separately: true,
+            line_delay_ms: 0,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Ansi,
+    Html,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum LogRotation {
+    Never,
+    Daily,
+    Size,
+}
+
+/// The file name may use `{world}`, `{date}` and `{character}`; the extension follows the format.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct LoggingSettings {
+    pub enabled: bool,
+    pub directory: String,
+    pub file_name: String,
+    pub format: LogFormat,
+    pub timestamps: bool,
+    pub log_input: bool, // Also log the commands we send
+    pub rotation: LogRotation,
+    pub max_size_kb: u64, // Start a new file past this size, for size rotation
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "logs".to_owned(),
+            file_name: "{world}-{date}".to_owned(),
+            format: LogFormat::Text,
+            timestamps: false,
+            log_input: false,
+            rotation: LogRotation::Daily,
+            max_size_kb: 1024,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub connect_timeout_secs: u64,
+    pub connect_on_startup: bool, // Reconnect to the last world when MudForge starts
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            connect_on_startup: false,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct MapperSettings {
+    pub enabled: bool,
+    pub regex_fallback: bool, // Recognise rooms from the text when the MUD sends no GMCP
+    pub room_name_pattern: String, // The last matching line before the exits is the room name
+    pub exits_pattern: String, // The first group holds the exits, like `north, east and up`
+    pub blocked_pattern: String, // A walk step that failed, to find another way
+}
+
+impl Default for MapperSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            regex_fallback: false,
+            room_name_pattern: r"^([A-Z][^.!?:]{2,60})$".to_owned(),
+            exits_pattern: r"^\[?\s*(?:Obvious )?[Ee]xits?:\s*(.*?)\.?\s*\]?$".to_owned(),
+            blocked_pattern: r"(?i)^(?:you can'?t go that way|alas, you cannot go that way|the .+ is (?:closed|locked))"
+                .to_owned(),
+        }
+    }
+}
+
+impl Settings {
+    /// Sets the egui style from the default MudForge style with these settings on top. Under
+    /// the light theme picked from the menu, only colours changed from their defaults apply.
+    pub fn apply(&self, ctx: &egui::Context) {
+        let dark_mode = ctx.style().visuals.dark_mode;
+        let mut style = styles::default_style();
+        if !dark_mode {
+            style.visuals = Visuals::light();
+        }
+        let visuals = &mut style.visuals;
+        let set = |target: &mut Color32, value: Color32, default: Color32| {
+            if dark_mode || value != default {
+                *target = value;
+            }
+        };
+        let (style_defaults, colors) = (StyleSettings::default(), ColorSettings::default());
+        set(
+            &mut visuals.window_fill,
+            self.style.window_fill,
+            style_defaults.window_fill,
+        );
+        set(
+            &mut visuals.panel_fill,
+            self.style.panel_fill,
+            style_defaults.panel_fill,
+        );
+        set(
+            &mut visuals.selection.bg_fill,
+            self.appearance.primary_color,
+            AppearanceSettings::default().primary_color,
+        );
+        set(
+            &mut visuals.extreme_bg_color,
+            self.colors.text_box,
+            colors.text_box,
+        );
+        set(
+            &mut visuals.hyperlink_color,
+            self.colors.hyperlink,
+            colors.hyperlink,
+        );
+        // The theme's own text colour is kept unless the user picked another.
+        if self.colors.text != colors.text {
+            visuals.override_text_color = Some(self.colors.text);
+        }
+
+        for (text_style, font_id) in style.text_styles.iter_mut() {
+            match text_style {
+                TextStyle::Body | TextStyle::Button => font_id.size = self.fonts.body_size,
+                TextStyle::Monospace => font_id.size = self.fonts.monospace_size,
+                _ => {}
+            }
+        }
+        ctx.set_style(style);
+    }
+
+    pub fn export(&self, path: &str) -> Result<(), String> {
+        let text = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path, e))
+    }
+
+    pub fn import(path: &str) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid settings file: {}", e))
+    }
+}
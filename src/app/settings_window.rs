@@ -1,36 +1,106 @@
-use egui::{Color32, Ui, Visuals, Window};
+use crate::app::ansi_color::{self, Palette, PalettePreset};
+use crate::app::settings::{
+    AppearanceSettings, ColorSettings, FontSettings, InputSettings, LogFormat, LogRotation,
+    LoggingSettings, MapperSettings, NetworkSettings, Settings, StyleSettings,
+};
+use crate::app::styles;
+use egui::{Color32, ComboBox, DragValue, Grid, Slider, Ui, Window};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-#[derive(PartialEq)]
+const SETTINGS_FILE: &str = "mudforge_settings.toml";
+const PALETTE_FILE: &str = "mudforge_palette.toml";
+
+const BASE_COLOR_NAMES: [&str; 8] = [
+    "Black", "Red", "Green", "Yellow", "Blue", "Magenta", "Cyan", "White",
+];
+
+#[derive(PartialEq, Default)]
 pub enum SettingsCategory {
+    #[default]
     Style,
     Appearance,
+    Fonts,
+    Colors,
+    Input,
+    Logging,
+    Network,
+    Mapper,
 }
 
-impl Default for SettingsCategory {
-    fn default() -> Self {
-        SettingsCategory::Style
-    }
-}
-
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct SettingsWindow {
+    pub settings: Settings,
+    #[serde(skip)]
     pub selected_category: SettingsCategory,
-    pub style_settings: StyleSettings,
-    pub appearance_settings: AppearanceSettings,
+    #[serde(skip)]
     pub open: bool,
+    file_path: String,
+    #[serde(skip)]
+    status: Option<Result<String, String>>,
+    #[serde(skip)]
+    applied: Option<Settings>,
+    #[serde(skip)]
+    installed_fonts: Option<Vec<PathBuf>>,
+    #[serde(skip)]
+    new_fallback_font: String,
+    palette_path: String,
+    #[serde(skip)]
+    palette_status: Option<Result<String, String>>,
 }
 
 impl Default for SettingsWindow {
     fn default() -> Self {
         Self {
+            settings: Settings::default(),
             selected_category: SettingsCategory::default(),
-            style_settings: StyleSettings::default(),
-            appearance_settings: AppearanceSettings::default(),
             open: false,
+            file_path: SETTINGS_FILE.to_owned(),
+            status: None,
+            applied: None,
+            installed_fonts: None,
+            new_fallback_font: String::new(),
+            palette_path: PALETTE_FILE.to_owned(),
+            palette_status: None,
         }
     }
 }
 
+/// Settings that were just applied.
+pub struct Applied {
+    /// How to recolor received text, when the ANSI palette changed.
+    pub recolor: Option<HashMap<Color32, Color32>>,
+}
+
 impl SettingsWindow {
+    /// Applies the settings to `ctx` if they changed since they were last applied.
+    pub fn apply(&mut self, ctx: &egui::Context) -> Option<Applied> {
+        if self.applied.as_ref() == Some(&self.settings) {
+            return None;
+        }
+        let fonts_changed = self
+            .applied
+            .as_ref()
+            .is_none_or(|applied| !applied.fonts.same_files(&self.settings.fonts));
+        if fonts_changed {
+            ctx.set_fonts(styles::custom_font(&self.settings.fonts));
+        }
+        let palette = &self.settings.colors.palette;
+        let mut recolor = None;
+        if self
+            .applied
+            .as_ref()
+            .is_none_or(|applied| applied.colors.palette != *palette)
+        {
+            let old = ansi_color::set_palette(palette.clone());
+            recolor = Some(old.recolor_map(palette));
+        }
+        self.settings.apply(ctx);
+        self.applied = Some(self.settings.clone());
+        Some(Applied { recolor })
+    }
+
     pub fn show(&mut self, ctx: &egui::Context) {
         Window::new("Settings")
             .open(&mut self.open)
@@ -38,57 +108,99 @@ impl SettingsWindow {
                 ui.horizontal(|ui| {
                     // Side panel equivalent for selecting categories
                     ui.vertical(|ui| {
-                        ui.selectable_value(
-                            &mut self.selected_category,
-                            SettingsCategory::Style,
-                            "Style",
-                        );
-                        ui.selectable_value(
-                            &mut self.selected_category,
-                            SettingsCategory::Appearance,
-                            "Appearance",
-                        );
+                        for (category, name) in [
+                            (SettingsCategory::Style, "Style"),
+                            (SettingsCategory::Appearance, "Appearance"),
+                            (SettingsCategory::Fonts, "Fonts"),
+                            (SettingsCategory::Colors, "Colors"),
+                            (SettingsCategory::Input, "Input"),
+                            (SettingsCategory::Logging, "Logging"),
+                            (SettingsCategory::Network, "Network"),
+                            (SettingsCategory::Mapper, "Mapper"),
+                        ] {
+                            ui.selectable_value(&mut self.selected_category, category, name);
+                        }
                     });
 
                     ui.separator();
 
                     // Display settings for the selected category
+                    let settings = &mut self.settings;
                     match self.selected_category {
-                        SettingsCategory::Style => self.style_settings.ui(ui),
-                        SettingsCategory::Appearance => self.appearance_settings.ui(ui),
-                        // Handle UI for other categories...
+                        SettingsCategory::Style => settings.style.ui(ui),
+                        SettingsCategory::Appearance => settings.appearance.ui(ui),
+                        SettingsCategory::Fonts => {
+                            let installed = self
+                                .installed_fonts
+                                .get_or_insert_with(styles::installed_fonts);
+                            settings
+                                .fonts
+                                .ui(ui, installed, &mut self.new_fallback_font)
+                        }
+                        SettingsCategory::Colors => {
+                            settings
+                                .colors
+                                .ui(ui, &mut self.palette_path, &mut self.palette_status)
+                        }
+                        SettingsCategory::Input => settings.input.ui(ui),
+                        SettingsCategory::Logging => settings.logging.ui(ui),
+                        SettingsCategory::Network => settings.network.ui(ui),
+                        SettingsCategory::Mapper => settings.mapper.ui(ui),
                     }
                 });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.file_path);
+                    if ui.button("Export").clicked() {
+                        self.status = Some(
+                            self.settings
+                                .export(&self.file_path)
+                                .map(|()| format!("Exported to {}", self.file_path)),
+                        );
+                    }
+                    if ui.button("Import").clicked() {
+                        self.status = Some(Settings::import(&self.file_path).map(|settings| {
+                            self.settings = settings;
+                            format!("Imported {}", self.file_path)
+                        }));
+                    }
+                    if ui.button("Restore defaults").clicked() {
+                        self.settings = Settings::default();
+                        self.status = None;
+                    }
+                });
+                match &self.status {
+                    Some(Ok(message)) => {
+                        ui.label(message);
+                    }
+                    Some(Err(error)) => {
+                        ui.colored_label(Color32::RED, error);
+                    }
+                    None => {}
+                }
             });
     }
 }
 
-#[derive(Default)]
-pub struct StyleSettings {
-    pub visuals: Visuals, // We are directly using egui's Visuals struct here
-}
-
 impl StyleSettings {
     pub fn ui(&mut self, ui: &mut Ui) {
         ui.group(|ui| {
             ui.heading("Visuals");
             ui.add_space(10.0);
-            let visuals = &mut self.visuals;
             ui.horizontal(|ui| {
-                ui.color_edit_button_srgba(&mut visuals.window_fill);
+                ui.color_edit_button_srgba(&mut self.window_fill);
                 ui.label("Window background");
             });
-            // Add other visuals settings here...
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgba(&mut self.panel_fill);
+                ui.label("Panel background");
+            });
         });
     }
 }
 
-#[derive(Default)]
-pub struct AppearanceSettings {
-    pub primary_color: Color32,
-    // Add other appearance-related settings fields here...
-}
-
 impl AppearanceSettings {
     pub fn ui(&mut self, ui: &mut Ui) {
         ui.group(|ui| {
@@ -98,9 +210,357 @@ impl AppearanceSettings {
                 ui.color_edit_button_srgba(&mut self.primary_color);
                 ui.label("Primary color");
             });
-            // Add other appearance settings here...
+            ui.checkbox(
+                &mut self.split_scrollback,
+                "Keep live output visible below the scrollback",
+            );
+            ui.add_enabled(
+                self.split_scrollback,
+                Slider::new(&mut self.split_ratio, 0.2..=0.9).text("Scrollback pane height"),
+            );
+        });
+    }
+}
+
+impl FontSettings {
+    pub fn ui(&mut self, ui: &mut Ui, installed: &[PathBuf], new_fallback: &mut String) {
+        ui.group(|ui| {
+            ui.heading("Fonts");
+            ui.add_space(10.0);
+            Grid::new("font_files").num_columns(2).show(ui, |ui| {
+                font_picker(ui, "Output", &mut self.output_font, installed);
+                font_picker(ui, "Input", &mut self.input_font, installed);
+                font_picker(ui, "Interface", &mut self.ui_font, installed);
+            });
+            ui.add_space(10.0);
+            ui.add(Slider::new(&mut self.output_size, 8.0..=32.0).text("Output size"));
+            ui.add(Slider::new(&mut self.body_size, 8.0..=32.0).text("Text size"));
+            ui.add(Slider::new(&mut self.monospace_size, 8.0..=32.0).text("Monospace size"));
+            if !self.world_output_sizes.is_empty() && ui.button("Forget zoom per world").clicked() {
+                self.world_output_sizes.clear();
+            }
+            ui.label("Ctrl+scroll or Ctrl +/- over the output zooms it for the current world.");
+
+            ui.add_space(10.0);
+            ui.strong("Fallback fonts");
+            ui.checkbox(&mut self.system_cjk_font, "Use the system CJK font")
+                .on_hover_text("Emoji are covered by the built-in fonts.");
+            ui.checkbox(
+                &mut self.detect_fallback_fonts,
+                "Use installed CJK and emoji fonts",
+            )
+            .on_hover_text("Scans the system font folders; large fonts slow down startup.");
+            let mut removed = None;
+            for (index, path) in self.fallback_fonts.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("✖").clicked() {
+                        removed = Some(index);
+                    }
+                    ui.label(path);
+                });
+            }
+            if let Some(index) = removed {
+                self.fallback_fonts.remove(index);
+            }
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(new_fallback);
+                if ui.button("Add").clicked() && !new_fallback.is_empty() {
+                    self.fallback_fonts.push(std::mem::take(new_fallback));
+                }
+            });
         });
     }
 }
 
-// Add other settings structs and their impls here...
+/// A combo box of the installed fonts with a path field for any other file.
+fn font_picker(ui: &mut Ui, label: &str, path: &mut String, installed: &[PathBuf]) {
+    ui.label(label);
+    ui.horizontal(|ui| {
+        let selected = if path.is_empty() {
+            "Built-in".to_owned()
+        } else {
+            Path::new(path.as_str())
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone())
+        };
+        ComboBox::from_id_source(("font_picker", label))
+            .selected_text(selected)
+            .width(200.0)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(path, String::new(), "Built-in");
+                for font in installed {
+                    let name = font.file_name().unwrap_or_default().to_string_lossy();
+                    ui.selectable_value(path, font.to_string_lossy().into_owned(), name)
+                        .on_hover_text(font.to_string_lossy());
+                }
+            });
+        ui.text_edit_singleline(path);
+    });
+    ui.end_row();
+}
+
+impl ColorSettings {
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        palette_path: &mut String,
+        status: &mut Option<Result<String, String>>,
+    ) {
+        ui.group(|ui| {
+            ui.heading("Colors");
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgba(&mut self.text);
+                ui.label("Text");
+            });
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgba(&mut self.text_box);
+                ui.label("Text box background");
+            });
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgba(&mut self.hyperlink);
+                ui.label("Links");
+            });
+        });
+
+        ui.group(|ui| {
+            ui.heading("ANSI palette");
+            ui.add_space(10.0);
+            let palette = &mut self.palette;
+            ui.horizontal(|ui| {
+                ComboBox::from_id_source("palette_preset")
+                    .selected_text("Load preset")
+                    .show_ui(ui, |ui| {
+                        for preset in PalettePreset::ALL {
+                            if ui.button(preset.name()).clicked() {
+                                *palette = preset.palette();
+                            }
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgba(&mut palette.foreground);
+                ui.label("Default foreground");
+                ui.color_edit_button_srgba(&mut palette.background);
+                ui.label("Default background");
+            });
+            Grid::new("palette_base").num_columns(3).show(ui, |ui| {
+                ui.label("");
+                ui.label("Normal");
+                ui.label("Bright");
+                ui.end_row();
+                for (index, name) in BASE_COLOR_NAMES.iter().enumerate() {
+                    ui.label(*name);
+                    ui.color_edit_button_srgba(&mut palette.base[index]);
+                    ui.color_edit_button_srgba(&mut palette.base[index + 8]);
+                    ui.end_row();
+                }
+            });
+            ui.checkbox(&mut palette.remap_cube, "Remap the 256-color cube");
+            if palette.remap_cube {
+                Grid::new("palette_cube")
+                    .spacing([2.0, 2.0])
+                    .show(ui, |ui| {
+                        for (index, colour) in palette.cube.iter_mut().enumerate().skip(16) {
+                            ui.color_edit_button_srgba(colour)
+                                .on_hover_text(format!("38;5;{}", index));
+                            if (index - 16) % 18 == 17 {
+                                ui.end_row();
+                            }
+                        }
+                    });
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(palette_path);
+                if ui.button("Export").clicked() {
+                    *status = Some(
+                        palette
+                            .export(palette_path)
+                            .map(|()| format!("Exported to {}", palette_path)),
+                    );
+                }
+                if ui.button("Import").clicked() {
+                    *status = Some(Palette::import(palette_path).map(|imported| {
+                        *palette = imported;
+                        format!("Imported {}", palette_path)
+                    }));
+                }
+            });
+            match status {
+                Some(Ok(message)) => {
+                    ui.label(message.as_str());
+                }
+                Some(Err(error)) => {
+                    ui.colored_label(Color32::RED, error.as_str());
+                }
+                None => {}
+            }
+        });
+    }
+}
+
+impl InputSettings {
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.heading("Input");
+            ui.add_space(10.0);
+            ui.checkbox(&mut self.echo_commands, "Echo sent commands in the output");
+            ui.add_enabled_ui(self.echo_commands, |ui| {
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgba(&mut self.echo_color);
+                    ui.label("Echo color");
+                    ui.checkbox(&mut self.echo_style.italics, "Italics");
+                    ui.checkbox(&mut self.echo_style.underline, "Underline");
+                });
+            });
+            ui.checkbox(
+                &mut self.keep_command,
+                "Keep the last command in the input line, selected",
+            );
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.history_size).range(10..=100_000));
+                ui.label("Commands kept in history, per world");
+            });
+            ui.checkbox(
+                &mut self.history_dedupe,
+                "Keep only the latest copy of repeated commands",
+            );
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.completion_lines).range(0..=10_000));
+                ui.label("Output lines searched for Tab completion");
+            });
+            ui.label("Extra Tab completion words:");
+            ui.add(
+                egui::TextEdit::multiline(&mut self.completion_words)
+                    .hint_text("One or more per line")
+                    .desired_rows(3),
+            );
+            ui.add_space(10.0);
+            ui.label("Special characters (leave empty to turn off):");
+            Grid::new("command_syntax").num_columns(2).show(ui, |ui| {
+                for (value, label) in [
+                    (&mut self.command_separator, "Command separator"),
+                    (&mut self.repeat_prefix, "Repeat prefix"),
+                    (&mut self.speedwalk_prefix, "Speedwalk prefix"),
+                ] {
+                    ui.add(egui::TextEdit::singleline(value).desired_width(40.0));
+                    ui.label(label);
+                    ui.end_row();
+                }
+            });
+            ui.add_space(10.0);
+            ui.checkbox(
+                &mut self.multiline,
+                "Multi-line input (Shift+Enter for a new line)",
+            );
+            ui.checkbox(
+                &mut self.send_lines_separately,
+                "Queue multi-line input, pausing between lines",
+            );
+            ui.add_enabled_ui(self.send_lines_separately, |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut self.line_delay_ms).range(0..=10_000));
+                    ui.label("Delay between lines (ms)");
+                });
+            });
+        });
+    }
+}
+
+impl LoggingSettings {
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.heading("Logging");
+            ui.add_space(10.0);
+            ui.checkbox(&mut self.enabled, "Log received lines to a file per world");
+            ui.horizontal(|ui| {
+                ui.label("Directory:");
+                ui.text_edit_singleline(&mut self.directory);
+            });
+            ui.horizontal(|ui| {
+                ui.label("File name:");
+                ui.text_edit_singleline(&mut self.file_name)
+                    .on_hover_text("{world}, {date} and {character} are filled in");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Format:");
+                ui.radio_value(&mut self.format, LogFormat::Text, "Plain text");
+                ui.radio_value(&mut self.format, LogFormat::Ansi, "ANSI");
+                ui.radio_value(&mut self.format, LogFormat::Html, "HTML");
+            });
+            ui.checkbox(&mut self.timestamps, "Start each line with the time");
+            ui.checkbox(&mut self.log_input, "Log our own commands");
+            ui.horizontal(|ui| {
+                ui.label("New file:");
+                ui.radio_value(&mut self.rotation, LogRotation::Never, "Never");
+                ui.radio_value(&mut self.rotation, LogRotation::Daily, "Every day");
+                ui.radio_value(&mut self.rotation, LogRotation::Size, "Past a size");
+            });
+            if self.rotation == LogRotation::Size {
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut self.max_size_kb).range(16..=1_048_576));
+                    ui.label("Maximum size (KB)");
+                });
+            }
+        });
+    }
+}
+
+impl NetworkSettings {
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.heading("Network");
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.connect_timeout_secs).range(1..=120));
+                ui.label("Connect timeout (seconds)");
+            });
+            ui.checkbox(
+                &mut self.connect_on_startup,
+                "Connect to the last world on startup",
+            );
+        });
+    }
+}
+
+impl MapperSettings {
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.heading("Mapper");
+            ui.add_space(10.0);
+            ui.checkbox(&mut self.enabled, "Map rooms from GMCP Room.Info");
+            ui.checkbox(
+                &mut self.regex_fallback,
+                "Recognise rooms from the text when there is no GMCP",
+            );
+            ui.add_enabled_ui(self.regex_fallback, |ui| {
+                Grid::new("mapper_patterns").num_columns(2).show(ui, |ui| {
+                    ui.label("Room name");
+                    ui.text_edit_singleline(&mut self.room_name_pattern);
+                    ui.end_row();
+                    ui.label("Exits");
+                    ui.text_edit_singleline(&mut self.exits_pattern);
+                    ui.end_row();
+                });
+            });
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                ui.label("Walk blocked");
+                ui.text_edit_singleline(&mut self.blocked_pattern)
+                    .on_hover_text("Lines that mean a walk step failed, so another way is tried");
+            });
+            for pattern in [
+                &self.room_name_pattern,
+                &self.exits_pattern,
+                &self.blocked_pattern,
+            ] {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    ui.colored_label(Color32::RED, e.to_string());
+                }
+            }
+        });
+    }
+}
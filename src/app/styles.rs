@@ -1,4 +1,8 @@
+use crate::app::settings::FontSettings;
 use egui::{Color32, FontData, FontDefinitions, FontFamily, Stroke, Style};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 pub fn default_style() -> Style {
     let mut style = Style::default();
@@ -21,20 +25,180 @@ pub fn default_style() -> Style {
     style
 }
 
-pub fn custom_font() -> FontDefinitions {
+pub const OUTPUT_FONT: &str = "output";
+pub const INPUT_FONT: &str = "input";
+const BUILT_IN_FONT: &str = "ReFixedys Mono";
+
+/// File names of fonts worth loading as fallbacks for CJK and emoji text.
+const FALLBACK_FONT_NAMES: &[&str] = &[
+    "notosanscjk",
+    "droidsansfallback",
+    "wqy-microhei",
+    "wqy-zenhei",
+    "msyh.",
+    "msgothic.",
+    "malgun.",
+    "pingfang",
+    "hiragino sans gb",
+    "applesdgothicneo",
+    "seguiemj.",
+    "symbola",
+];
+
+/// Where each platform usually installs a CJK font, tried in order. Only the first one found is
+/// loaded, so this is cheap enough to be on by default.
+const SYSTEM_CJK_FONTS: &[&str] = &[
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/droid/DroidSansFallbackFull.ttf",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/wenquanyi/wqy-microhei/wqy-microhei.ttc",
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/Hiragino Sans GB.ttc",
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "C:\\Windows\\Fonts\\msgothic.ttc",
+    "C:\\Windows\\Fonts\\malgun.ttf",
+];
+
+/// Builds the font families: `output` and `input` for the game panes, proportional for the rest
+/// of the UI. Each uses its chosen file, or ReFixedys Mono, followed by the fallback fonts and
+/// egui's defaults, which include emoji.
+pub fn custom_font(settings: &FontSettings) -> FontDefinitions {
     let font_re_fixedys_mono = include_bytes!("../data/refixedsys-mono.otf").to_vec();
     let mut font = FontDefinitions::default();
     font.font_data.insert(
-        "ReFixedys Mono".to_string(),
+        BUILT_IN_FONT.to_string(),
         FontData::from_owned(font_re_fixedys_mono),
     );
+
+    let mut load = |path: &str| -> Option<String> {
+        if path.is_empty() {
+            return None;
+        }
+        if !font.font_data.contains_key(path) {
+            match fs::read(path) {
+                Ok(bytes) => {
+                    font.font_data
+                        .insert(path.to_string(), FontData::from_owned(bytes));
+                }
+                Err(e) => {
+                    eprintln!("Failed to load font {}: {}", path, e);
+                    return None;
+                }
+            }
+        }
+        Some(path.to_string())
+    };
+
+    let output = load(&settings.output_font);
+    let input = load(&settings.input_font);
+    let ui = load(&settings.ui_font);
+    let mut fallbacks: Vec<String> = settings
+        .fallback_fonts
+        .iter()
+        .filter_map(|path| load(path))
+        .collect();
+    if settings.system_cjk_font {
+        let found = SYSTEM_CJK_FONTS
+            .iter()
+            .find(|path| Path::new(path).is_file());
+        if let Some(name) = found.and_then(|path| load(path)) {
+            fallbacks.push(name);
+        }
+    }
+    if settings.detect_fallback_fonts {
+        for path in detect_fallback_fonts() {
+            if let Some(name) = load(&path.to_string_lossy()) {
+                if !fallbacks.contains(&name) {
+                    fallbacks.push(name);
+                }
+            }
+        }
+    }
+
+    let monospace = font.families[&FontFamily::Monospace].clone();
+    let family = |first: Option<String>, defaults: &[String]| -> Vec<String> {
+        let mut family = vec![first.unwrap_or_else(|| BUILT_IN_FONT.to_string())];
+        family.extend(fallbacks.iter().cloned());
+        family.extend(defaults.iter().cloned());
+        family
+    };
+    let proportional = family(ui, &font.families[&FontFamily::Proportional]);
+    font.families.insert(
+        FontFamily::Name(OUTPUT_FONT.into()),
+        family(output, &monospace),
+    );
+    font.families.insert(
+        FontFamily::Name(INPUT_FONT.into()),
+        family(input, &monospace),
+    );
     font.families
-        .get_mut(&FontFamily::Monospace)
-        .unwrap()
-        .insert(0, "ReFixedys Mono".to_string());
-    font.families
-        .get_mut(&FontFamily::Proportional)
-        .unwrap()
-        .insert(0, "ReFixedys Mono".to_string());
+        .insert(FontFamily::Monospace, family(None, &monospace));
+    font.families.insert(FontFamily::Proportional, proportional);
     font
 }
+
+/// Every `.ttf`, `.otf` and `.ttc` file in the usual system and user font folders.
+pub fn installed_fonts() -> Vec<PathBuf> {
+    let mut folders = vec![
+        PathBuf::from("fonts"),
+        PathBuf::from("/usr/share/fonts"),
+        PathBuf::from("/usr/local/share/fonts"),
+        PathBuf::from("/Library/Fonts"),
+        PathBuf::from("/System/Library/Fonts"),
+    ];
+    if let Ok(home) = env::var("HOME") {
+        folders.push(Path::new(&home).join(".fonts"));
+        folders.push(Path::new(&home).join(".local/share/fonts"));
+        folders.push(Path::new(&home).join("Library/Fonts"));
+    }
+    if let Ok(windows) = env::var("WINDIR") {
+        folders.push(Path::new(&windows).join("Fonts"));
+    }
+
+    let mut fonts = Vec::new();
+    for folder in folders {
+        collect_fonts(&folder, 0, &mut fonts);
+    }
+    fonts.sort();
+    fonts.dedup();
+    fonts
+}
+
+fn collect_fonts(folder: &Path, depth: usize, fonts: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return;
+    };
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if path.is_dir() {
+            if depth < 4 {
+                collect_fonts(&path, depth + 1, fonts);
+            }
+        } else if matches!(
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext.to_ascii_lowercase())
+                .as_deref(),
+            Some("ttf" | "otf" | "ttc")
+        ) {
+            fonts.push(path);
+        }
+    }
+}
+
+/// The first installed font matching each of the known CJK and emoji font names.
+fn detect_fallback_fonts() -> Vec<PathBuf> {
+    let installed = installed_fonts();
+    FALLBACK_FONT_NAMES
+        .iter()
+        .filter_map(|name| {
+            installed.iter().find(|path| {
+                path.file_name()
+                    .map(|file| file.to_string_lossy().to_lowercase().starts_with(name))
+                    .unwrap_or(false)
+            })
+        })
+        .cloned()
+        .collect()
+}
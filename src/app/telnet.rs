@@ -1,43 +1,110 @@
-use crate::app::ansi_color::COLOR_MAP;
-use egui::{Color32, ScrollArea};
+use crate::app::ansi_color::{self, palette_color};
+use crate::app::search::ScrollbackSearch;
+use crate::app::selection::{LineLayout, OutputSelection};
+use crate::app::session_replay::Recorder;
+use crate::app::settings::EchoStyle;
+use egui::scroll_area::ScrollAreaOutput;
+use egui::{Color32, FontId, ScrollArea, Sense};
+use lazy_static::lazy_static;
 use libmudtelnet::events::TelnetEvents;
+use libmudtelnet::telnet::{op_command, op_option};
 use libmudtelnet::Parser;
-use std::collections::VecDeque;
+use regex::Regex;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::time::Duration;
+
+const LIVE_PANE_LINES: usize = 200; // Lines of text kept in the live pane of the split view
+
+lazy_static! {
+    static ref URL_PATTERN: Regex = Regex::new(r#"\b(?:https?://|www\.)[^\s<>"'`]+"#).unwrap();
+}
+
 pub struct TelnetClient {
     stream: Option<TcpStream>,
-    pub connection_open: bool,
     pub received_data: Vec<Vec<(String, Color32)>>,
+    pub chat_data: Vec<Vec<(String, Color32)>>, // Lines captured for the chat pane
     parser: Parser,
     incomplete_sequence: Vec<u8>, // Buffer for incomplete ANSI sequences
+    partial_utf8: Vec<u8>,        // A multi-byte character split across reads
     write_queue: VecDeque<Vec<u8>>, // Queue for outgoing data
+    partial_line: Vec<(String, Color32)>, // Text received since the last newline
+    partial_raw: String,          // The same text with its ANSI codes
+    raw_lines: VecDeque<String>,  // Completed lines with their ANSI codes
+    completed_lines: Vec<ReceivedLine>, // Lines waiting for triggers and the log
+    recorder: Option<Recorder>,   // Records received bytes for replay
+    lines_received: usize,
+    pub links: HashMap<usize, Vec<Link>>, // Hyperlinks and URLs by output line
+    input_lines: HashMap<usize, EchoStyle>, // Output lines that are echoed commands
+    notes: HashSet<usize>, // Output lines the client added in fixed colors, kept by recolor
+    server_echo: bool,     // The server said WILL ECHO, so typed text is hidden (passwords)
+    gmcp_messages: Vec<(String, String)>, // GMCP package names and JSON data not yet handled
+}
+
+/// Text spans with their colors.
+pub type StyledLine = Vec<(String, Color32)>;
+
+/// A link target and the bytes of a line's plain text it covers.
+pub type Link = (Range<usize>, String);
+
+/// A complete line received from the server.
+pub struct ReceivedLine {
+    pub text: String,                  // Plain text, for triggers
+    pub raw: String,                   // As received, ANSI codes included
+    pub spans: Vec<(String, Color32)>, // As shown on screen
 }
 
 impl TelnetClient {
     pub fn new() -> Self {
         Self {
             stream: None,
-            connection_open: false,
             received_data: Vec::new(),
-            parser: Parser::new(),
+            chat_data: Vec::new(),
+            parser: new_parser(),
             incomplete_sequence: Vec::new(),
+            partial_utf8: Vec::new(),
             write_queue: VecDeque::new(),
+            partial_line: Vec::new(),
+            partial_raw: String::new(),
+            raw_lines: VecDeque::new(),
+            completed_lines: Vec::new(),
+            recorder: None,
+            lines_received: 0,
+            links: HashMap::new(),
+            input_lines: HashMap::new(),
+            notes: HashSet::new(),
+            server_echo: false,
+            gmcp_messages: Vec::new(),
         }
     }
 
     pub fn append_text(&mut self, text: &str, color: Color32) {
+        self.notes.insert(self.received_data.len());
         self.received_data.push(vec![(text.to_string(), color)]);
     }
 
+    /// Echoes a sent command into the output. It is kept apart from the server's lines, so
+    /// triggers never see it.
+    pub fn append_input(&mut self, text: &str, color: Color32, style: EchoStyle) {
+        self.input_lines.insert(self.received_data.len(), style);
+        self.append_text(text, color);
+    }
+
     pub fn append_text_with_colours(
         &mut self,
         text: &str,
         text_colour: Color32,
         _back_colour: Color32,
     ) {
-        self.received_data
-            .push(vec![(text.to_string(), text_colour)]);
+        self.append_text(text, text_colour);
+    }
+
+    /// Adds ANSI-coloured text to the chat pane.
+    pub fn append_chat(&mut self, text: &str) {
+        self.chat_data
+            .extend(parse_ansi_codes(format!("{}\n", text).into_bytes()));
     }
 
     pub fn _append_ansi_text(&mut self, text: &str) {
@@ -45,7 +112,12 @@ impl TelnetClient {
         self.received_data.extend(parsed_segments);
     }
 
-    pub fn connect(&mut self, ip_address: &str, port: &str) -> Result<(), String> {
+    pub fn connect(
+        &mut self,
+        ip_address: &str,
+        port: &str,
+        timeout: Duration,
+    ) -> Result<(), String> {
         let addr = format!("{}:{}", ip_address, port);
         let socket_addr = addr
             .to_socket_addrs()
@@ -53,13 +125,19 @@ impl TelnetClient {
             .next()
             .ok_or("Invalid address")?;
 
-        let stream =
-            TcpStream::connect(socket_addr).map_err(|e| format!("Connection failed: {}", e))?;
+        let stream = TcpStream::connect_timeout(&socket_addr, timeout)
+            .map_err(|e| format!("Connection failed: {}", e))?;
         stream
             .set_nonblocking(true)
             .map_err(|e| format!("Failed to set non-blocking mode: {}", e))?;
+        // Start from a fresh parser and queue, so nothing left from a replay or an earlier
+        // connection reaches the new server.
         self.stream = Some(stream);
-        self.connection_open = true;
+        self.server_echo = false;
+        self.parser = new_parser();
+        self.incomplete_sequence.clear();
+        self.partial_utf8.clear();
+        self.write_queue.clear();
         Ok(())
     }
 
@@ -68,17 +146,13 @@ impl TelnetClient {
             let mut buffer = [0; 8192];
             match stream.read(&mut buffer) {
                 Ok(size) if size > 0 => {
-                    let mut data = self.incomplete_sequence.clone();
-                    data.extend_from_slice(&buffer[..size]);
-
-                    let events = self.parser.receive(&data);
-                    let parsed_text = self.handle_telnet_events(events);
-
-                    self.received_data.extend(parsed_text.clone());
-
-                    self.incomplete_sequence = data;
-
-                    Some(parsed_text.into_iter().flatten().collect())
+                    if let Some(recorder) = &mut self.recorder {
+                        if let Err(e) = recorder.record(&buffer[..size]) {
+                            eprintln!("Failed to record session: {}", e);
+                            self.recorder = None;
+                        }
+                    }
+                    Some(self.receive(&buffer[..size]))
                 }
                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
                 _ => None,
@@ -88,6 +162,86 @@ impl TelnetClient {
         }
     }
 
+    /// Runs bytes from the server, or from a recording, through the telnet parser, the ANSI
+    /// parser and on to the output and the trigger lines.
+    pub fn receive(&mut self, bytes: &[u8]) -> Vec<(String, Color32)> {
+        let mut data = self.incomplete_sequence.clone();
+        data.extend_from_slice(bytes);
+
+        let events = self.parser.receive(&data);
+        let (parsed_text, links) = self.handle_telnet_events(events);
+
+        let first = self.received_data.len();
+        for (index, (line, mut line_links)) in parsed_text.iter().zip(links).enumerate() {
+            let text: String = line.iter().map(|(text, _)| text.as_str()).collect();
+            line_links.extend(find_urls(&text, &line_links));
+            if !line_links.is_empty() {
+                self.links.insert(first + index, line_links);
+            }
+        }
+        self.received_data.extend(parsed_text.clone());
+        self.collect_lines(&parsed_text);
+
+        self.incomplete_sequence.clear();
+
+        parsed_text.into_iter().flatten().collect()
+    }
+
+    pub fn start_recording(&mut self, path: &str) -> Result<(), String> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Splits received text into complete lines for triggers, plugins and the log.
+    fn collect_lines(&mut self, parsed_text: &[Vec<(String, Color32)>]) {
+        for (text, color) in parsed_text.iter().flatten() {
+            for c in text.chars() {
+                match c {
+                    '\n' => {
+                        self.lines_received += 1;
+                        let spans = std::mem::take(&mut self.partial_line);
+                        self.completed_lines.push(ReceivedLine {
+                            text: spans.iter().map(|(text, _)| text.as_str()).collect(),
+                            raw: self.raw_lines.pop_front().unwrap_or_default(),
+                            spans,
+                        });
+                    }
+                    '\r' => {}
+                    _ => match self.partial_line.last_mut() {
+                        Some((span, span_color)) if span_color == color => span.push(c),
+                        _ => self.partial_line.push((c.to_string(), *color)),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Keeps the received bytes, ANSI codes and all, split into lines for raw logging.
+    fn collect_raw_lines(&mut self, data: &[u8]) {
+        for c in String::from_utf8_lossy(data).chars() {
+            match c {
+                '\n' => self
+                    .raw_lines
+                    .push_back(std::mem::take(&mut self.partial_raw)),
+                '\r' => {}
+                _ => self.partial_raw.push(c),
+            }
+        }
+    }
+
+    /// Returns the lines completed since the last call.
+    pub fn take_completed_lines(&mut self) -> Vec<ReceivedLine> {
+        std::mem::take(&mut self.completed_lines)
+    }
+
     pub fn write(&mut self, buffer: &[u8]) -> Result<(), String> {
         self.write_queue.push_back(buffer.to_vec());
         self.flush_write_queue()
@@ -113,45 +267,169 @@ impl TelnetClient {
         self.stream.is_some()
     }
 
-    pub fn show(&mut self, ctx: &egui::Context) {
-        if self.connection_open {
-            egui::Window::new("Telnet Connection")
-                .open(&mut self.connection_open)
-                .resizable(true)
-                .show(ctx, |ui| {
-                    let scroll_area = ScrollArea::vertical()
-                        .auto_shrink([false; 2])
-                        .stick_to_bottom(true);
-
-                    scroll_area.show(ui, |ui| {
-                        for (_row_index, line) in self.received_data.iter().enumerate() {
-                            let mut job = egui::text::LayoutJob::default();
-                            for (text, color) in line {
-                                job.append(
-                                    text,
-                                    0.0,
-                                    egui::text::TextFormat {
-                                        font_id: ui.style().text_styles[&egui::TextStyle::Body]
-                                            .clone(),
-                                        color: *color,
-                                        ..Default::default()
-                                    },
-                                );
-                            }
-                            ui.add(egui::Label::new(job));
-                        }
-                    });
-                });
+    /// Whether the server has turned local echo off, as it does while a password is typed.
+    pub fn echo_off(&self) -> bool {
+        self.server_echo
+    }
+
+    /// Returns the GMCP messages received since the last call, as package name and JSON data.
+    pub fn take_gmcp(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.gmcp_messages)
+    }
+
+    /// Sends a GMCP message, if the server has agreed to GMCP. Nothing is sent while replaying
+    /// a recording offline.
+    fn send_gmcp(&mut self, package: &str, data: &str) {
+        if self.stream.is_none() {
+            return;
+        }
+        let message = format!("{} {}", package, data);
+        if let Some(TelnetEvents::DataSend(data)) =
+            self.parser.subnegotiation_text(op_option::GMCP, &message)
+        {
+            let _ = self.write(&data);
+        }
+    }
+
+    /// Changes the colors of text already received after the palette changed. Notes and
+    /// echoed commands keep their colors even where they match a palette entry.
+    pub fn recolor(&mut self, map: &HashMap<Color32, Color32>) {
+        let received = self
+            .received_data
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, _)| !self.notes.contains(index))
+            .map(|(_, line)| line);
+        for line in received.chain(self.chat_data.iter_mut()) {
+            for (_, color) in line.iter_mut() {
+                if let Some(new_color) = map.get(color) {
+                    *color = *new_color;
+                }
+            }
         }
     }
 
-    fn handle_telnet_events(&mut self, events: Vec<TelnetEvents>) -> Vec<Vec<(String, Color32)>> {
+    /// Draws the output. Once scrolled back it shows how many lines came in since, and with a
+    /// `split_ratio` the history freezes in an upper pane while live output follows below.
+    pub fn output_ui(
+        &self,
+        ui: &mut egui::Ui,
+        font_id: &FontId,
+        search: &mut ScrollbackSearch,
+        split: &mut SplitView,
+        split_ratio: Option<f32>,
+        selection: &mut OutputSelection,
+    ) {
+        selection.handle_copy(ui, &self.received_data);
+        let rect = ui.available_rect_before_wrap();
+        ui.allocate_rect(rect, Sense::hover());
+        let (history_rect, live_rect) = match split_ratio.filter(|_| split.scrolled_back) {
+            Some(ratio) => {
+                let (history_rect, live_rect) = rect.split_top_bottom_at_fraction(ratio);
+                (history_rect, Some(live_rect))
+            }
+            None => (rect, None),
+        };
+
+        // The history pane keeps one id in both layouts so its scroll position carries over.
+        let mut history_ui =
+            ui.child_ui_with_id_source(history_rect, *ui.layout(), "output_history", None);
+        let jump_to_bottom = std::mem::take(&mut split.jump_to_bottom);
+        let output = show_lines(
+            &mut history_ui,
+            &self.received_data,
+            0,
+            "output",
+            font_id,
+            LineOptions {
+                search: Some(search),
+                selection: Some(selection),
+                links: Some(&self.links),
+                inputs: Some(&self.input_lines),
+                jump_to_bottom,
+            },
+        );
+        let at_bottom =
+            output.state.offset.y + output.inner_rect.height() >= output.content_size.y - 1.0;
+        if at_bottom {
+            split.scrolled_back = false;
+            return;
+        }
+        if !split.scrolled_back {
+            split.scrolled_back = true;
+            split.lines_at_split = self.lines_received;
+        }
+
+        if let Some(live_rect) = live_rect {
+            let mut live_ui =
+                ui.child_ui_with_id_source(live_rect, *ui.layout(), "output_live", None);
+            let first = first_of_last_lines(&self.received_data, LIVE_PANE_LINES);
+            show_lines(
+                &mut live_ui,
+                &self.received_data,
+                first,
+                "output_live",
+                font_id,
+                LineOptions {
+                    inputs: Some(&self.input_lines),
+                    ..Default::default()
+                },
+            );
+            ui.painter().hline(
+                rect.x_range(),
+                live_rect.top(),
+                ui.visuals().widgets.noninteractive.bg_stroke,
+            );
+        }
+
+        let new_lines = self.lines_received - split.lines_at_split;
+        if new_lines > 0 {
+            let size = egui::vec2(140.0, 20.0);
+            let button_rect = egui::Rect::from_min_size(
+                history_rect.right_bottom() - size - egui::vec2(20.0, 6.0),
+                size,
+            );
+            let label = if new_lines == 1 {
+                "⏷ 1 new line".to_owned()
+            } else {
+                format!("⏷ {} new lines", new_lines)
+            };
+            if ui.put(button_rect, egui::Button::new(label)).clicked() {
+                split.jump_to_bottom = true;
+            }
+        }
+    }
+
+    pub fn chat_ui(&self, ui: &mut egui::Ui, font_id: &FontId) {
+        show_lines(
+            ui,
+            &self.chat_data,
+            0,
+            "chat",
+            font_id,
+            LineOptions::default(),
+        );
+    }
+
+    /// Returns the parsed text and, for each parsed line, its hyperlinks.
+    fn handle_telnet_events(
+        &mut self,
+        events: Vec<TelnetEvents>,
+    ) -> (Vec<StyledLine>, Vec<Vec<Link>>) {
         let mut parsed_data: Vec<Vec<(String, Color32)>> = Vec::new();
+        let mut links = Vec::new();
 
         for event in events {
             match event {
-                TelnetEvents::DataReceive(data) => {
-                    let parsed_text = parse_ansi_codes(data.to_vec());
+                TelnetEvents::DataReceive(received) => {
+                    let mut data = std::mem::take(&mut self.partial_utf8);
+                    data.extend_from_slice(&received);
+                    self.partial_utf8 = data.split_off(complete_utf8_len(&data));
+                    self.collect_raw_lines(&data);
+                    let (parsed_text, line_links) = parse_ansi_with_links(data);
+                    if !parsed_text.is_empty() {
+                        links.push(line_links);
+                    }
                     parsed_data.extend(parsed_text);
                 }
                 TelnetEvents::DataSend(data) => {
@@ -159,12 +437,230 @@ impl TelnetClient {
                         let _ = stream.write_all(&data);
                     }
                 }
+                TelnetEvents::Negotiation(negotiation) if negotiation.option == op_option::ECHO => {
+                    match negotiation.command {
+                        op_command::WILL => self.server_echo = true,
+                        op_command::WONT => self.server_echo = false,
+                        _ => {}
+                    }
+                }
+                TelnetEvents::Negotiation(negotiation)
+                    if negotiation.option == op_option::GMCP
+                        && negotiation.command == op_command::WILL =>
+                {
+                    // The parser only passes on subnegotiations for options enabled on our side,
+                    // and GMCP is agreed with the server's WILL alone.
+                    let mut entry = self.parser.options.get_option(op_option::GMCP);
+                    entry.local_state = true;
+                    self.parser.options.set_option(op_option::GMCP, entry);
+                    self.send_gmcp(
+                        "Core.Hello",
+                        &format!(
+                            r#"{{"client":"MudForge","version":"{}"}}"#,
+                            env!("CARGO_PKG_VERSION")
+                        ),
+                    );
+                    self.send_gmcp("Core.Supports.Set", r#"["Room 1"]"#);
+                }
+                TelnetEvents::Subnegotiation(subnegotiation)
+                    if subnegotiation.option == op_option::GMCP =>
+                {
+                    let message = String::from_utf8_lossy(&subnegotiation.buffer);
+                    let (package, data) = message.split_once(' ').unwrap_or((&message, ""));
+                    self.gmcp_messages
+                        .push((package.trim().to_string(), data.trim().to_string()));
+                }
                 _ => {}
             }
         }
 
-        parsed_data
+        (parsed_data, links)
+    }
+}
+
+/// What a pane adds on top of plain scrolling lines.
+#[derive(Default)]
+struct LineOptions<'a> {
+    search: Option<&'a mut ScrollbackSearch>,
+    selection: Option<&'a mut OutputSelection>,
+    links: Option<&'a HashMap<usize, Vec<Link>>>,
+    inputs: Option<&'a HashMap<usize, EchoStyle>>,
+    jump_to_bottom: bool,
+}
+
+/// Shows `lines` from index `first` on, scrolled to the bottom unless scrolled back.
+fn show_lines(
+    ui: &mut egui::Ui,
+    lines: &[Vec<(String, Color32)>],
+    first: usize,
+    id: &str,
+    font_id: &FontId,
+    mut options: LineOptions<'_>,
+) -> ScrollAreaOutput<()> {
+    let scroll_to = options
+        .search
+        .as_mut()
+        .and_then(|search| search.take_scroll_target());
+    // Dragging selects text rather than scrolling.
+    let scroll_area = ScrollArea::vertical()
+        .id_source(id)
+        .auto_shrink([false; 2])
+        .stick_to_bottom(true)
+        .drag_to_scroll(options.selection.is_none());
+    let selection_color = ui.visuals().selection.bg_fill;
+    let hyperlink_color = ui.visuals().hyperlink_color;
+
+    let background = ansi_color::default_background();
+    ui.painter()
+        .rect_filled(ui.max_rect(), egui::Rounding::ZERO, background);
+    scroll_area.show(ui, |ui| {
+        for (index, line) in lines.iter().enumerate().skip(first) {
+            let len = line.iter().map(|(text, _)| text.len()).sum();
+            let mut highlights = options
+                .search
+                .as_ref()
+                .map(|search| search.highlights(index))
+                .unwrap_or_default();
+            if let Some(range) = options
+                .selection
+                .as_ref()
+                .and_then(|selection| selection.highlight(index, len))
+            {
+                highlights.push((range, selection_color));
+                highlights.sort_by_key(|(range, _)| range.start);
+            }
+            let links = options
+                .links
+                .and_then(|links| links.get(&index))
+                .map_or(&[][..], Vec::as_slice);
+            let link_ranges: Vec<Range<usize>> =
+                links.iter().map(|(range, _)| range.clone()).collect();
+            let echo = options
+                .inputs
+                .and_then(|inputs| inputs.get(&index))
+                .copied()
+                .unwrap_or_default();
+            let mut job = egui::text::LayoutJob::default();
+            job.wrap.max_width = ui.available_width();
+            let mut offset = 0;
+            for (text, color) in line {
+                for (piece, piece_offset, link) in split_at_ranges(text, offset, &link_ranges) {
+                    let (color, underline) = match link {
+                        Some(_) => (hyperlink_color, true),
+                        None => (*color, echo.underline),
+                    };
+                    let format = SpanFormat {
+                        font_id,
+                        color,
+                        underline,
+                        italics: echo.italics,
+                    };
+                    append_highlighted(&mut job, piece, piece_offset, &format, &highlights);
+                }
+                offset += text.len();
+            }
+
+            let galley = ui.fonts(|fonts| fonts.layout_job(job));
+            let sense = if options.selection.is_some() {
+                Sense::click_and_drag()
+            } else {
+                Sense::hover()
+            };
+            let size = egui::vec2(ui.available_width(), galley.size().y);
+            let (rect, response) = ui.allocate_exact_size(size, sense);
+            ui.painter()
+                .galley(rect.min, galley.clone(), ansi_color::default_foreground());
+            if let Some(selection) = options.selection.as_mut() {
+                let layout = LineLayout {
+                    galley: &galley,
+                    rect,
+                    index,
+                    links,
+                };
+                selection.line_ui(ui, &response, &layout, lines);
+            }
+            if scroll_to == Some(index) {
+                response.scroll_to_me(Some(egui::Align::Center));
+            }
+            if options.jump_to_bottom && index + 1 == lines.len() {
+                response.scroll_to_me(Some(egui::Align::BOTTOM));
+            }
+        }
+    })
+}
+
+struct SpanFormat<'a> {
+    font_id: &'a FontId,
+    color: Color32,
+    underline: bool,
+    italics: bool,
+}
+
+/// Appends a span that starts `offset` bytes into its line, giving the parts inside `highlights`
+/// their background color.
+fn append_highlighted(
+    job: &mut egui::text::LayoutJob,
+    text: &str,
+    offset: usize,
+    format: &SpanFormat<'_>,
+    highlights: &[(Range<usize>, Color32)],
+) {
+    let ranges: Vec<Range<usize>> = highlights.iter().map(|(range, _)| range.clone()).collect();
+    for (piece, _, highlight) in split_at_ranges(text, offset, &ranges) {
+        let background = highlight.map_or(Color32::TRANSPARENT, |index| highlights[index].1);
+        let underline = if format.underline {
+            egui::Stroke::new(1.0, format.color)
+        } else {
+            egui::Stroke::NONE
+        };
+        job.append(
+            piece,
+            0.0,
+            egui::text::TextFormat {
+                font_id: format.font_id.clone(),
+                color: format.color,
+                background,
+                underline,
+                italics: format.italics,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// Splits `text`, which starts `offset` bytes into its line, at the edges of `ranges` (sorted
+/// byte ranges of the line). Each piece comes with its line offset and the range it lies in.
+fn split_at_ranges<'t>(
+    text: &'t str,
+    offset: usize,
+    ranges: &[Range<usize>],
+) -> Vec<(&'t str, usize, Option<usize>)> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for (index, range) in ranges.iter().enumerate() {
+        let from = range.start.saturating_sub(offset).clamp(start, text.len());
+        let to = range.end.saturating_sub(offset).min(text.len());
+        if from >= to {
+            continue;
+        }
+        if start < from {
+            pieces.push((&text[start..from], offset + start, None));
+        }
+        pieces.push((&text[from..to], offset + from, Some(index)));
+        start = to;
+    }
+    if start < text.len() || pieces.is_empty() {
+        pieces.push((&text[start..], offset + start, None));
     }
+    pieces
+}
+
+/// Where the output is scrolled: following live text, or scrolled back with a split view.
+#[derive(Default)]
+pub struct SplitView {
+    scrolled_back: bool,
+    lines_at_split: usize, // Lines received when we scrolled away from the bottom
+    jump_to_bottom: bool,
 }
 
 impl Default for TelnetClient {
@@ -177,40 +673,84 @@ enum AnsiState {
     Normal,
     Escaped,
     Parsing(Vec<u8>),
+    Osc(Vec<u8>),       // Operating system command, up to BEL or ESC \
+    OscEscape(Vec<u8>), // ESC seen inside an OSC
+}
+
+const MAX_OSC_LEN: usize = 4096;
+
+fn new_parser() -> Parser {
+    let mut parser = Parser::new();
+    parser.options.support_remote(op_option::ECHO);
+    parser.options.support(op_option::GMCP);
+    parser
 }
 
 pub fn parse_ansi_codes(buffer: Vec<u8>) -> Vec<Vec<(String, Color32)>> {
+    parse_ansi_with_links(buffer).0
+}
+
+/// Parses ANSI text like `parse_ansi_codes`, also returning the OSC 8 hyperlinks as byte ranges
+/// of the plain text.
+pub fn parse_ansi_with_links(buffer: Vec<u8>) -> (Vec<StyledLine>, Vec<Link>) {
     let mut results: Vec<Vec<(String, Color32)>> = Vec::new();
     let mut current_line: Vec<(String, Color32)> = Vec::new();
-    let mut current_text = String::new();
-    let mut current_color = Color32::WHITE;
+    let mut current_text: Vec<u8> = Vec::new(); // Decoded as UTF-8 once the span ends
+    let mut current_color = ansi_color::default_foreground();
     let mut state = AnsiState::Normal;
+    let mut links: Vec<Link> = Vec::new();
+    let mut open_link: Option<(usize, String)> = None;
+    let line_len = |line: &[(String, Color32)], text: &[u8]| {
+        line.iter().map(|(text, _)| text.len()).sum::<usize>() + String::from_utf8_lossy(text).len()
+    };
 
     for byte in buffer {
+        let mut finished_osc = None;
         match state {
             AnsiState::Normal => {
                 if byte == 0x1B {
                     state = AnsiState::Escaped;
                     if !current_text.is_empty() {
-                        current_line.push((current_text.clone(), current_color));
+                        let text = String::from_utf8_lossy(&current_text).into_owned();
+                        current_line.push((text, current_color));
                         current_text.clear();
                     }
                 } else {
-                    current_text.push(byte as char);
+                    current_text.push(byte);
                 }
             }
             AnsiState::Escaped => {
                 if byte == b'[' {
                     state = AnsiState::Parsing(Vec::new());
+                } else if byte == b']' {
+                    state = AnsiState::Osc(Vec::new());
                 } else {
                     state = AnsiState::Normal;
                 }
             }
+            AnsiState::Osc(ref mut buf) => {
+                if byte == 0x07 {
+                    finished_osc = Some(std::mem::take(buf));
+                    state = AnsiState::Normal;
+                } else if byte == 0x1B {
+                    state = AnsiState::OscEscape(std::mem::take(buf));
+                } else if buf.len() < MAX_OSC_LEN {
+                    buf.push(byte);
+                } else {
+                    state = AnsiState::Normal;
+                }
+            }
+            AnsiState::OscEscape(ref mut buf) => {
+                if byte == b'\\' {
+                    finished_osc = Some(std::mem::take(buf));
+                }
+                state = AnsiState::Normal;
+            }
             AnsiState::Parsing(ref mut buf) => {
                 if byte == b'm' {
                     let code = String::from_utf8_lossy(buf).to_string();
-                    if let Some(new_color) = COLOR_MAP.get(code.as_str()) {
-                        current_color = *new_color;
+                    if let Some(new_color) = palette_color(&code) {
+                        current_color = new_color;
                     }
                     buf.clear();
                     state = AnsiState::Normal;
@@ -221,15 +761,156 @@ pub fn parse_ansi_codes(buffer: Vec<u8>) -> Vec<Vec<(String, Color32)>> {
                 }
             }
         }
+
+        // OSC 8 ; params ; URI starts a link, and an empty URI ends it.
+        if let Some(target) = finished_osc.as_deref().and_then(osc8_target) {
+            let offset = line_len(&current_line, &current_text);
+            if let Some((start, target)) = open_link.take() {
+                if start < offset {
+                    links.push((start..offset, target));
+                }
+            }
+            if !target.is_empty() {
+                open_link = Some((offset, target));
+            }
+        }
     }
 
     if !current_text.is_empty() {
-        current_line.push((current_text, current_color));
+        let text = String::from_utf8_lossy(&current_text).into_owned();
+        current_line.push((text, current_color));
+    }
+
+    if let Some((start, target)) = open_link {
+        let offset = line_len(&current_line, &[]);
+        if start < offset {
+            links.push((start..offset, target));
+        }
     }
 
     if !current_line.is_empty() {
         results.push(current_line);
     }
 
-    results
+    (results, links)
+}
+
+/// The first entry to show so that about the last `count` lines of text are shown. Entries are
+/// received chunks holding any number of lines, so whole entries are taken from the end while
+/// they fit, and always at least the last one.
+fn first_of_last_lines(entries: &[StyledLine], count: usize) -> usize {
+    let mut lines = 0;
+    for (index, entry) in entries.iter().enumerate().rev() {
+        lines += entry
+            .iter()
+            .map(|(text, _)| text.matches('\n').count())
+            .sum::<usize>();
+        if lines > count && index + 1 < entries.len() {
+            return index + 1;
+        }
+    }
+    0
+}
+
+/// How much of `data` is whole UTF-8 characters: everything but a multi-byte character cut off
+/// at the end, which waits for the rest of its bytes.
+fn complete_utf8_len(data: &[u8]) -> usize {
+    for back in 1..=data.len().min(3) {
+        let byte = data[data.len() - back];
+        if byte & 0xC0 != 0x80 {
+            let needed = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if needed > back {
+                data.len() - back
+            } else {
+                data.len()
+            };
+        }
+    }
+    data.len()
+}
+
+/// The URI of an OSC 8 hyperlink command, empty when it closes a link.
+fn osc8_target(osc: &[u8]) -> Option<String> {
+    let rest = osc.strip_prefix(b"8;")?;
+    let uri_start = rest.iter().position(|&b| b == b';')? + 1;
+    Some(String::from_utf8_lossy(&rest[uri_start..]).to_string())
+}
+
+/// Finds `http`, `https` and `www.` URLs in plain text, skipping any already covered by `links`.
+fn find_urls(text: &str, links: &[Link]) -> Vec<Link> {
+    URL_PATTERN
+        .find_iter(text)
+        .filter_map(|found| {
+            let url = found
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '}', '\'']);
+            let range = found.start()..found.start() + url.len();
+            let covered = links
+                .iter()
+                .any(|(link, _)| link.start < range.end && range.start < link.end);
+            let target = if url.starts_with("www.") {
+                format!("http://{}", url)
+            } else {
+                url.to_string()
+            };
+            (!covered).then_some((range, target))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[StyledLine]) -> String {
+        lines
+            .iter()
+            .flatten()
+            .map(|(text, _)| text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn text_is_decoded_as_utf8_around_colour_codes() {
+        let (lines, _) = parse_ansi_with_links("\x1b[1;31m你好\x1b[0m 😀 café".as_bytes().to_vec());
+        assert_eq!(text(&lines), "你好 😀 café");
+        assert_eq!(lines[0][0].0, "你好");
+    }
+
+    #[test]
+    fn characters_split_across_reads_are_joined() {
+        let bytes = "a你😀".as_bytes();
+        for split in 1..bytes.len() {
+            let mut client = TelnetClient::new();
+            client.receive(&bytes[..split]);
+            client.receive(&bytes[split..]);
+            assert_eq!(text(&client.received_data), "a你😀", "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn live_pane_takes_whole_entries_up_to_the_line_count() {
+        let entries: Vec<StyledLine> = ["a\nb\n", "c\n", "d", "e\nf\n"]
+            .iter()
+            .map(|text| vec![(text.to_string(), Color32::WHITE)])
+            .collect();
+        assert_eq!(first_of_last_lines(&entries, 10), 0);
+        assert_eq!(first_of_last_lines(&entries, 3), 1);
+        assert_eq!(first_of_last_lines(&entries, 2), 2);
+        assert_eq!(first_of_last_lines(&entries, 1), 3); // The last entry even if it's longer
+    }
+
+    #[test]
+    fn complete_utf8_len_holds_back_only_a_cut_off_character() {
+        assert_eq!(complete_utf8_len(b"abc"), 3);
+        assert_eq!(complete_utf8_len("é".as_bytes()), 2);
+        assert_eq!(complete_utf8_len(&"😀".as_bytes()[..3]), 0);
+        assert_eq!(complete_utf8_len(&"a你".as_bytes()[..2]), 1);
+        assert_eq!(complete_utf8_len(&[b'a', 0x80]), 2); // A stray continuation byte
+    }
 }